@@ -0,0 +1,72 @@
+// padded vs packed elimination slots: every thread keeps exchanging values through its own
+// slot, the same way push and pop meet in TreiberStack's elimination array. threads never
+// touch each other's slots, so any slowdown of packed slots is false sharing.
+// run with `cargo bench --bench elimination_slots`
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// same as TreiberStack's elimination array
+const SLOTS: usize = 8;
+const THREADS: usize = 8;
+const PER_THREAD_OPS: usize = 1_000_000;
+const ROUNDS: usize = 10;
+
+const EMPTY: usize = 0;
+
+#[repr(align(64))]
+struct Padded(AtomicUsize);
+
+fn run_round(slots: &[&AtomicUsize; SLOTS]) -> Duration {
+    let barrier = std::sync::Barrier::new(THREADS + 1);
+    let barrier_ref = &barrier;
+    std::thread::scope(|s| {
+        for t in 0..THREADS {
+            let slot = slots[t % SLOTS];
+            s.spawn(move || {
+                barrier_ref.wait();
+                for i in 1..=PER_THREAD_OPS {
+                    // place an offer, then take it back, as an unmatched pusher does
+                    _ = slot.compare_exchange(EMPTY, i, Ordering::AcqRel, Ordering::Relaxed);
+                    _ = slot.compare_exchange(i, EMPTY, Ordering::AcqRel, Ordering::Relaxed);
+                }
+                barrier_ref.wait();
+            });
+        }
+        barrier_ref.wait();
+        let start = Instant::now();
+        barrier_ref.wait();
+        start.elapsed()
+    })
+}
+
+fn report(name: &str, mut timings: Vec<Duration>) {
+    timings.sort();
+    let total_ops = (THREADS * PER_THREAD_OPS * 2) as f64;
+    let median = timings[ROUNDS / 2];
+    println!(
+        "{}: {} threads x {} exchanges, median {:?} ({:.1} ns/op), best {:?}",
+        name,
+        THREADS,
+        PER_THREAD_OPS,
+        median,
+        median.as_nanos() as f64 / total_ops,
+        timings[0],
+    );
+}
+
+fn main() {
+    let packed = [const { AtomicUsize::new(EMPTY) }; SLOTS];
+    let packed_refs = std::array::from_fn(|i| &packed[i]);
+    let packed_timings = (0..ROUNDS)
+        .map(|_| run_round(&packed_refs))
+        .collect::<Vec<_>>();
+    report("packed", packed_timings);
+
+    let padded = [const { Padded(AtomicUsize::new(EMPTY)) }; SLOTS];
+    let padded_refs = std::array::from_fn(|i| &padded[i].0);
+    let padded_timings = (0..ROUNDS)
+        .map(|_| run_round(&padded_refs))
+        .collect::<Vec<_>>();
+    report("padded", padded_timings);
+}
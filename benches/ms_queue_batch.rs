@@ -0,0 +1,72 @@
+// MSQueue::enqueue_batch() against as many single enqueues: every thread enqueues BATCH items,
+// either one by one or in one batch, so the batch pays one next CAS and one tail CAS for all of
+// them, and doesn't race other enqueuers on every item. queue is drained between rounds.
+// run with `cargo bench --bench ms_queue_batch`
+
+use lock_freedom::collections::ms_queue::MSQueue;
+use lock_freedom::mechanisms::hp::HazardPointerArray;
+use std::time::{Duration, Instant};
+
+// bounded by hazard pointer array's MAX_THREADS
+const THREAD_COUNT: usize = 4;
+const BATCH: usize = 1000;
+const PER_THREAD_BATCHES: usize = 100;
+const ROUNDS: usize = 10;
+
+static HP_ARRAY: HazardPointerArray = HazardPointerArray::new();
+
+fn run_round(thread_count: usize, batched: bool) -> Duration {
+    let q = MSQueue::new();
+    let q_ref = &q;
+    let barrier = std::sync::Barrier::new(thread_count + 1);
+    let barrier_ref = &barrier;
+
+    std::thread::scope(|s| {
+        for _ in 0..thread_count {
+            s.spawn(move || {
+                let guard = HP_ARRAY.register_thread().ok().unwrap();
+                barrier_ref.wait();
+                for b in 0..PER_THREAD_BATCHES {
+                    let items = b * BATCH..(b + 1) * BATCH;
+                    if batched {
+                        q_ref.enqueue_batch(items, &guard);
+                    } else {
+                        for i in items {
+                            q_ref.enqueue(i, &guard);
+                        }
+                    }
+                }
+                barrier_ref.wait();
+                // drain, so that nodes are retired by the queue owners
+                while q_ref.dequeue(&guard).is_some() {}
+            });
+        }
+        barrier_ref.wait();
+        let start = Instant::now();
+        barrier_ref.wait();
+        start.elapsed()
+    })
+}
+
+fn main() {
+    for thread_count in [1, THREAD_COUNT] {
+        for batched in [false, true] {
+            let mut timings = (0..ROUNDS)
+                .map(|_| run_round(thread_count, batched))
+                .collect::<Vec<_>>();
+            timings.sort();
+            let total_ops = (thread_count * PER_THREAD_BATCHES * BATCH) as f64;
+            let median = timings[ROUNDS / 2];
+            println!(
+                "ms_queue {}: {} threads x {} x {} items, median {:?} ({:.1} ns/item), best {:?}",
+                if batched { "enqueue_batch" } else { "enqueue" },
+                thread_count,
+                PER_THREAD_BATCHES,
+                BATCH,
+                median,
+                median.as_nanos() as f64 / total_ops,
+                timings[0],
+            );
+        }
+    }
+}
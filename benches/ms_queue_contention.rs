@@ -0,0 +1,71 @@
+// enqueue-heavy contention benchmark for MSQueue: all threads hammer the tail at once,
+// so most time is spent on retrying tail's next CAS and helping lagging tail.
+// run with `cargo bench --bench ms_queue_contention`, add `--features scan-stats` to also count
+// lost CAS races per op: backoff tuning may trade one for the other, which wall time alone hides
+
+use lock_freedom::collections::ms_queue::MSQueue;
+use lock_freedom::mechanisms::hp::HazardPointerArray;
+use std::time::{Duration, Instant};
+
+// well under hazard pointer array's MAX_THREADS (64 by default), enough to contend the tail
+const THREAD_COUNT: usize = 4;
+const PER_THREAD_OPS: usize = 100_000;
+const ROUNDS: usize = 10;
+
+static HP_ARRAY: HazardPointerArray = HazardPointerArray::new();
+
+// wall time of the enqueue phase, and tail's next CAS retries during it (0 without scan-stats)
+fn run_round() -> (Duration, usize) {
+    let q = MSQueue::new();
+    let q_ref = &q;
+    let barrier = std::sync::Barrier::new(THREAD_COUNT + 1);
+    let barrier_ref = &barrier;
+
+    std::thread::scope(|s| {
+        for _ in 0..THREAD_COUNT {
+            s.spawn(move || {
+                let guard = HP_ARRAY.register_thread().ok().unwrap();
+                barrier_ref.wait();
+                for i in 0..PER_THREAD_OPS {
+                    q_ref.enqueue(i, &guard);
+                }
+                barrier_ref.wait();
+                // drain, so that nodes are retired by the queue owners
+                while q_ref.dequeue(&guard).is_some() {}
+            });
+        }
+        barrier_ref.wait();
+        let start = Instant::now();
+        barrier_ref.wait();
+        let elapsed = start.elapsed();
+        #[cfg(feature = "scan-stats")]
+        let retries = q_ref.cas_retries();
+        #[cfg(not(feature = "scan-stats"))]
+        let retries = 0;
+        (elapsed, retries)
+    })
+}
+
+fn main() {
+    let (mut timings, mut retries): (Vec<_>, Vec<_>) = (0..ROUNDS).map(|_| run_round()).unzip();
+    timings.sort();
+    retries.sort();
+    let total_ops = (THREAD_COUNT * PER_THREAD_OPS) as f64;
+    let median = timings[ROUNDS / 2];
+    println!(
+        "ms_queue enqueue contention: {} threads x {} ops, median {:?} ({:.1} ns/op), best {:?}",
+        THREAD_COUNT,
+        PER_THREAD_OPS,
+        median,
+        median.as_nanos() as f64 / total_ops,
+        timings[0],
+    );
+    if cfg!(feature = "scan-stats") {
+        println!(
+            "  CAS retries: median {} ({:.3} per op), fewest {}",
+            retries[ROUNDS / 2],
+            retries[ROUNDS / 2] as f64 / total_ops,
+            retries[0],
+        );
+    }
+}
@@ -0,0 +1,84 @@
+// SegQueue vs MSQueue on small Copy values: producers and consumers run concurrently,
+// so the difference mostly comes from per-node allocation and reclamation in MSQueue.
+// run with `cargo bench --bench seg_queue_vs_ms_queue`
+
+use lock_freedom::collections::ms_queue::MSQueue;
+use lock_freedom::collections::seg_queue::SegQueue;
+use lock_freedom::mechanisms::hp::HazardPointerArray;
+use std::time::{Duration, Instant};
+
+// bounded by hazard pointer array's MAX_THREADS
+const PRODUCERS: usize = 2;
+const CONSUMERS: usize = 2;
+const PER_PRODUCER_OPS: usize = 100_000;
+const ROUNDS: usize = 10;
+
+static HP_ARRAY: HazardPointerArray = HazardPointerArray::new();
+
+// both queues share the same shape of api, but not a common trait
+macro_rules! run_round {
+    ($queue:expr) => {{
+        let q = $queue;
+        let q_ref = &q;
+        let barrier = std::sync::Barrier::new(PRODUCERS + CONSUMERS + 1);
+        let barrier_ref = &barrier;
+
+        std::thread::scope(|s| {
+            for _ in 0..PRODUCERS {
+                s.spawn(move || {
+                    let guard = HP_ARRAY.register_thread().ok().unwrap();
+                    barrier_ref.wait();
+                    for i in 0..PER_PRODUCER_OPS {
+                        q_ref.enqueue(i as u64, &guard);
+                    }
+                    barrier_ref.wait();
+                });
+            }
+            for _ in 0..CONSUMERS {
+                s.spawn(move || {
+                    let guard = HP_ARRAY.register_thread().ok().unwrap();
+                    barrier_ref.wait();
+                    let mut dequeued = 0;
+                    while dequeued < PER_PRODUCER_OPS {
+                        if q_ref.dequeue(&guard).is_some() {
+                            dequeued += 1;
+                        }
+                    }
+                    barrier_ref.wait();
+                });
+            }
+            barrier_ref.wait();
+            let start = Instant::now();
+            barrier_ref.wait();
+            start.elapsed()
+        })
+    }};
+}
+
+fn report(name: &str, mut timings: Vec<Duration>) {
+    timings.sort();
+    let total_ops = (PRODUCERS * PER_PRODUCER_OPS * 2) as f64;
+    let median = timings[ROUNDS / 2];
+    println!(
+        "{}: {} producers, {} consumers x {} ops, median {:?} ({:.1} ns/op), best {:?}",
+        name,
+        PRODUCERS,
+        CONSUMERS,
+        PER_PRODUCER_OPS,
+        median,
+        median.as_nanos() as f64 / total_ops,
+        timings[0],
+    );
+}
+
+fn main() {
+    let ms_timings = (0..ROUNDS)
+        .map(|_| run_round!(MSQueue::<u64>::new()))
+        .collect::<Vec<_>>();
+    report("ms_queue", ms_timings);
+
+    let seg_timings = (0..ROUNDS)
+        .map(|_| run_round!(SegQueue::<u64>::new()))
+        .collect::<Vec<_>>();
+    report("seg_queue", seg_timings);
+}
@@ -0,0 +1,60 @@
+// push/pop throughput of TreiberStack under contention, in the same batches as its
+// test_concurrent: every thread pushes a batch, then pops as many, so pushes and pops keep
+// meeting on head, and failed CASes send them to the elimination array.
+// run with `cargo bench --bench treiber_stack_push_pop`
+
+use lock_freedom::collections::treiber_stack::TreiberStack;
+use lock_freedom::mechanisms::hp::HazardPointerArray;
+use std::time::{Duration, Instant};
+
+// bounded by hazard pointer array's MAX_THREADS
+const THREAD_COUNT: usize = 4;
+const BATCH: usize = 8;
+const PER_THREAD_OPS: usize = 200_000;
+const ROUNDS: usize = 10;
+
+static HP_ARRAY: HazardPointerArray = HazardPointerArray::new();
+
+fn run_round() -> Duration {
+    let stack = TreiberStack::new();
+    let stack_ref = &stack;
+    let barrier = std::sync::Barrier::new(THREAD_COUNT + 1);
+    let barrier_ref = &barrier;
+
+    std::thread::scope(|s| {
+        for _ in 0..THREAD_COUNT {
+            s.spawn(move || {
+                let guard = HP_ARRAY.register_thread().ok().unwrap();
+                barrier_ref.wait();
+                for i in 0..PER_THREAD_OPS / BATCH {
+                    for j in 0..BATCH {
+                        stack_ref.push(i * BATCH + j);
+                    }
+                    for _ in 0..BATCH {
+                        assert!(stack_ref.pop(&guard).is_some());
+                    }
+                }
+                barrier_ref.wait();
+            });
+        }
+        barrier_ref.wait();
+        let start = Instant::now();
+        barrier_ref.wait();
+        start.elapsed()
+    })
+}
+
+fn main() {
+    let mut timings = (0..ROUNDS).map(|_| run_round()).collect::<Vec<_>>();
+    timings.sort();
+    let total_ops = (THREAD_COUNT * PER_THREAD_OPS * 2) as f64;
+    let median = timings[ROUNDS / 2];
+    println!(
+        "treiber_stack push/pop: {} threads x {} ops, median {:?} ({:.1} ns/op), best {:?}",
+        THREAD_COUNT,
+        PER_THREAD_OPS,
+        median,
+        median.as_nanos() as f64 / total_ops,
+        timings[0],
+    );
+}
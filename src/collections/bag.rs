@@ -0,0 +1,106 @@
+use crate::collections::treiber_stack::TreiberStack;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+    unordered collection for add-and-drain workloads (e.g. collecting results of parallel tasks).
+    single Treiber stack head becomes a contention point when many threads push at once, so here
+    we spread pushes across several independent stacks (shards). every thread sticks to its own
+    shard, assigned round-robin on first add. adds don't dereference shared pointers, hence need no
+    guard; drain requires exclusive access and takes everything at once
+*/
+
+const BAG_SHARDS: usize = 8;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD_INDEX: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % BAG_SHARDS;
+}
+
+// keep shards' heads on separate cache lines
+#[repr(align(128))]
+struct Shard<T>(TreiberStack<T>);
+
+pub struct Bag<T> {
+    shards: [Shard<T>; BAG_SHARDS],
+}
+
+impl<T: Send> Default for Bag<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> Bag<T> {
+    pub fn new() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Shard(TreiberStack::new())),
+        }
+    }
+
+    pub fn add(&self, value: T) {
+        let index = SHARD_INDEX.with(|index| *index);
+        self.shards[index].0.push(value);
+    }
+}
+
+impl<T> Bag<T> {
+    // order of items is unspecified
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut values = Vec::new();
+        for shard in self.shards.iter_mut() {
+            values.append(&mut shard.0.take_all());
+        }
+        values
+    }
+}
+
+impl<T> Drop for Bag<T> {
+    fn drop(&mut self) {
+        self.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bag;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_basic_operations() {
+        let mut bag = Bag::new();
+        bag.add(1);
+        bag.add(2);
+        bag.add(3);
+        let mut values = bag.drain();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 3]);
+        assert!(bag.drain().is_empty());
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let thread_count = 8;
+        let per_thread_ops = 1000;
+        let expected_values: HashSet<usize> = (0..thread_count * per_thread_ops).collect();
+
+        for _ in 0..100 {
+            let mut bag = Bag::new();
+            let bag_ref = &bag;
+            std::thread::scope(|s| {
+                for t in 0..thread_count {
+                    s.spawn(move || {
+                        for i in 0..per_thread_ops {
+                            bag_ref.add(t * per_thread_ops + i);
+                        }
+                    });
+                }
+            });
+
+            let values = bag.drain();
+            assert_eq!(values.len(), thread_count * per_thread_ops);
+            let actual_values: HashSet<usize> = values.into_iter().collect();
+            assert_eq!(actual_values, expected_values);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/*
+    strength of CASes, that are retried in a loop anyway (or are just hints, like proceeding a
+    lagging tail), so that strong CAS is always a valid substitute for a weak one. on x86 both
+    compile to the same instruction; on LL/SC platforms (e.g. arm) weak CAS is cheaper, but may
+    fail spuriously, and every spurious failure costs a retry (with backoff, re-protection).
+    which one wins depends on the target and contention, hence it's a per-structure choice.
+    CASes, whose failure is reported to the caller, are always strong, whatever is chosen here:
+    single-attempt pops (PopPolicy::NonBlocking, pop_if_top_is()) would report a spurious
+    failure as a lost race. OMSQueue's tail CAS isn't configurable either: weak one would be
+    correct there, but its every failure costs a tail re-protection and a new next store
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CasStrength {
+    #[default]
+    Weak,
+    Strong,
+}
+
+impl CasStrength {
+    pub(crate) fn compare_exchange<T>(
+        self,
+        atomic: &AtomicPtr<T>,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        match self {
+            CasStrength::Weak => atomic.compare_exchange_weak(current, new, success, failure),
+            CasStrength::Strong => atomic.compare_exchange(current, new, success, failure),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::mechanisms::reclaim::{Reclaimer, ReclaimerGuard};
+use std::marker::PhantomData;
+
+/*
+    consistent snapshot of a collection, e.g. from TreiberStack::freeze() or MSQueue::freeze().
+    either pinned: every node of the snapshot stays protected for as long as the view lives, and
+    items point right into the nodes, or cloned: values were copied out, when guard didn't have
+    enough free hazard slots to pin them all (see freeze_or_clone()).
+    pinning takes one slot per element (plus the ones collection needs for validation), so with
+    hazard pointers it's only viable for small collections: at most HP_PER_THREAD minus whatever
+    guard already protects. with EBR, there's no such limit, but the whole view keeps the thread
+    pinned, holding back reclamation for everyone.
+    note, that protection only keeps nodes from being freed. collections move values out of the
+    nodes they unlink, so a pinned item (or one being cloned) may be popped (dequeued) while the
+    view is alive, and whatever it owns freed by the popper. hence collections only freeze Copy
+    values: moving those out is just another read, so the view keeps seeing them intact
+*/
+pub struct FrozenView<'g, T, P> {
+    // never read, just keeps nodes protected
+    _pinned: Vec<P>,
+    items: Vec<*const T>,
+    cloned: Vec<T>,
+    _guard: PhantomData<&'g T>,
+}
+
+// view pinning nodes N of a collection with reclaimer R, borrowing guard for 'g
+pub type PinnedView<'g, 'a, T, N, R> =
+    FrozenView<'g, T, <<R as Reclaimer>::Guard<'a, N> as ReclaimerGuard<N>>::Protected<'g>>;
+
+// guard's slots were exhausted before the whole snapshot got pinned
+pub struct InsufficientSlots;
+
+impl<'g, T, P> FrozenView<'g, T, P> {
+    // items point into the nodes protected by pinned
+    pub(crate) fn pinned(pinned: Vec<P>, items: Vec<*const T>) -> Self {
+        FrozenView {
+            _pinned: pinned,
+            items,
+            cloned: Vec::new(),
+            _guard: PhantomData,
+        }
+    }
+
+    pub(crate) fn cloned(cloned: Vec<T>) -> Self {
+        FrozenView {
+            _pinned: Vec::new(),
+            items: Vec::new(),
+            cloned,
+            _guard: PhantomData,
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.cloned.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len() + self.cloned.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // in collection's order: top to bottom for stack, front to back for queue
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            items: self.items.iter(),
+            cloned: self.cloned.iter(),
+        }
+    }
+}
+
+impl<'v, T, P> IntoIterator for &'v FrozenView<'_, T, P> {
+    type Item = &'v T;
+    type IntoIter = Iter<'v, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'v, T> {
+    items: std::slice::Iter<'v, *const T>,
+    cloned: std::slice::Iter<'v, T>,
+}
+
+impl<'v, T> Iterator for Iter<'v, T> {
+    type Item = &'v T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.items.next() {
+            // node is protected by the view, that outlives 'v
+            Some(&item) => Some(unsafe { &*item }),
+            None => self.cloned.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.items.len() + self.cloned.len();
+        (len, Some(len))
+    }
+}
@@ -0,0 +1,548 @@
+use crate::mechanisms::hp::ProtectionError;
+use crate::mechanisms::reclaim::{HazardBudget, HpReclaimer, Reclaimer, ReclaimerGuard};
+use crate::utils::backoff::Backoff;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+
+/*
+    lock-free hash set as a split-ordered list (O.Shalev, N.Shavit, "Split-Ordered Lists:
+    Lock-Free Extensible Hash Tables", 2006). all elements live in a single sorted lock-free list
+    (Harris-Michael: logical deletion by marking node's next pointer, then physical unlinking),
+    and buckets are just shortcuts into it: every bucket points to its own sentinel node.
+    list is sorted by bit-reversed hash ('split order'), so that elements of bucket b are exactly
+    those between b's sentinel and the next one, and doubling bucket count splits every bucket
+    in two without moving anything: new bucket b + size gets its sentinel inserted in the middle
+    of bucket b's run. so resizing is just bumping bucket count, and new buckets are initialized
+    lazily on the first access, starting from their parent bucket.
+    bucket array is a directory of lazily allocated segments, so it grows without copying.
+    hash collisions (equal split order keys) are resolved by comparing values along the run
+*/
+
+const SEGMENT_SIZE: usize = 64;
+const MAX_SEGMENTS: usize = 1024;
+const MAX_BUCKETS: usize = SEGMENT_SIZE * MAX_SEGMENTS;
+// average number of elements per bucket, that triggers doubling of bucket count
+const LOAD_FACTOR: usize = 2;
+const INITIAL_BUCKETS: usize = 2;
+
+// logical deletion mark, stored in LSB of node's next pointer
+const MARK: usize = 1;
+
+pub struct LockFreeHashSet<T, R: Reclaimer = HpReclaimer> {
+    // bucket index -> sentinel node, in segments of SEGMENT_SIZE
+    segments: Box<[AtomicPtr<BucketSegment<T>>]>,
+    bucket_count: AtomicUsize,
+    len: AtomicUsize,
+    hasher: RandomState,
+    _reclaimer: PhantomData<R>,
+}
+
+struct BucketSegment<T> {
+    buckets: [AtomicPtr<SetNode<T>>; SEGMENT_SIZE],
+}
+
+struct Node<T> {
+    // split order key: bit-reversed hash with MSB set for elements, bit-reversed bucket index
+    // for sentinels, so that sentinel goes before all elements of its bucket
+    key: usize,
+    // None for sentinels
+    value: Option<T>,
+    next: AtomicPtr<SetNode<T>>,
+}
+
+#[repr(transparent)]
+pub struct SetNode<T>(Node<T>);
+
+fn is_marked<T>(ptr: *mut SetNode<T>) -> bool {
+    ptr as usize & MARK != 0
+}
+
+fn with_mark<T>(ptr: *mut SetNode<T>) -> *mut SetNode<T> {
+    (ptr as usize | MARK) as *mut SetNode<T>
+}
+
+fn without_mark<T>(ptr: *mut SetNode<T>) -> *mut SetNode<T> {
+    (ptr as usize & !MARK) as *mut SetNode<T>
+}
+
+fn element_key(hash: usize) -> usize {
+    (hash | 1 << (usize::BITS - 1)).reverse_bits()
+}
+
+fn sentinel_key(bucket: usize) -> usize {
+    bucket.reverse_bits()
+}
+
+// bucket, that is split into this one on doubling: the same index without its highest bit
+fn parent_bucket(bucket: usize) -> usize {
+    bucket & !(1 << (usize::BITS - 1 - bucket.leading_zeros()))
+}
+
+type ProtectedNode<'g, 'a, T, R> =
+    <<R as Reclaimer>::Guard<'a, SetNode<T>> as ReclaimerGuard<SetNode<T>>>::Protected<'g>;
+// (prev, curr) protections, prev is None for sentinel link
+type ProtectedPair<'g, 'a, T, R> = (
+    Option<ProtectedNode<'g, 'a, T, R>>,
+    ProtectedNode<'g, 'a, T, R>,
+);
+
+// outcome of find(): position in list, where key (and value) is, or should be inserted
+struct Position<P> {
+    // link, that points to curr: next of either a sentinel (never freed while set is alive)
+    // or of prev_protected node
+    prev: *const AtomicPtr<SetNode<P>>,
+    curr: *mut SetNode<P>,
+    found: bool,
+}
+
+impl<T: Hash + Eq + Send> Default for LockFreeHashSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Send> LockFreeHashSet<T> {
+    pub fn new() -> Self {
+        Self::with_reclaimer()
+    }
+}
+
+impl<T: Hash + Eq + Send, R: Reclaimer> LockFreeHashSet<T, R> {
+    // max number of pointers a single operation protects at once (list traversal: prev and
+    // curr). hazard pointer guards used with this set need at least that many free slots
+    pub const HAZARDS_PER_OP: usize = 2;
+
+    // e.g. LockFreeHashSet::<T, EbrReclaimer>::with_reclaimer()
+    pub fn with_reclaimer() -> Self {
+        let set = LockFreeHashSet {
+            segments: (0..MAX_SEGMENTS)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            bucket_count: AtomicUsize::new(INITIAL_BUCKETS),
+            len: AtomicUsize::new(0),
+            hasher: RandomState::new(),
+            _reclaimer: PhantomData,
+        };
+        // bucket 0 sentinel is the head of the whole list
+        let head = Box::into_raw(Box::new(Node::<T> {
+            key: sentinel_key(0),
+            value: None,
+            next: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut SetNode<T>;
+        set.bucket_slot(0).store(head, Ordering::Relaxed);
+        set
+    }
+
+    // approximate under concurrent modification
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.bucket_count.load(Ordering::Relaxed)
+    }
+
+    // user should register thread to obtain guard
+    pub fn insert(&self, value: T, guard: &R::Guard<'_, SetNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "LockFreeHashSet::insert()");
+        let hash = self.hasher.hash_one(&value) as usize;
+        let sentinel = self.bucket_sentinel(hash, guard);
+        let node = Box::into_raw(Box::new(Node::<T> {
+            key: element_key(hash),
+            value: Some(value),
+            next: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut SetNode<T>;
+
+        let mut backoff = Backoff::new();
+        loop {
+            // keeps position valid until link CAS
+            let mut protected = None;
+            let position = self.find(
+                sentinel,
+                element_key(hash),
+                unsafe { (*node).0.value.as_ref() },
+                guard,
+                &mut protected,
+            );
+            if position.found {
+                unsafe { drop(Box::from_raw(node)) };
+                return false;
+            }
+            unsafe { (*node).0.next.store(position.curr, Ordering::Relaxed) };
+            if unsafe { &*position.prev }
+                .compare_exchange(position.curr, node, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+                let bucket_count = self.bucket_count();
+                if len > bucket_count * LOAD_FACTOR && bucket_count < MAX_BUCKETS {
+                    // whoever wins doubles it, others' CASes would double it once again
+                    _ = self.bucket_count.compare_exchange(
+                        bucket_count,
+                        bucket_count * 2,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    );
+                }
+                return true;
+            }
+            backoff.spin();
+        }
+    }
+
+    pub fn contains(&self, value: &T, guard: &R::Guard<'_, SetNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "LockFreeHashSet::contains()");
+        let hash = self.hasher.hash_one(value) as usize;
+        let sentinel = self.bucket_sentinel(hash, guard);
+        self.find(sentinel, element_key(hash), Some(value), guard, &mut None)
+            .found
+    }
+
+    pub fn remove(&self, value: &T, guard: &R::Guard<'_, SetNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "LockFreeHashSet::remove()");
+        let hash = self.hasher.hash_one(value) as usize;
+        let sentinel = self.bucket_sentinel(hash, guard);
+
+        let mut backoff = Backoff::new();
+        loop {
+            let mut protected = None;
+            let position = self.find(
+                sentinel,
+                element_key(hash),
+                Some(value),
+                guard,
+                &mut protected,
+            );
+            if !position.found {
+                return false;
+            }
+            let Some((protected_prev, protected_curr)) = protected else {
+                unreachable!("LockFreeHashSet::remove(): found node is always protected");
+            };
+            let next = protected_curr.0.next.load(Ordering::Acquire);
+            // logical deletion: whoever marks the node, removes the value
+            if is_marked(next)
+                || protected_curr
+                    .0
+                    .next
+                    .compare_exchange(next, with_mark(next), Ordering::AcqRel, Ordering::Relaxed)
+                    .is_err()
+            {
+                backoff.spin();
+                continue;
+            }
+            self.len.fetch_sub(1, Ordering::Relaxed);
+
+            // physical deletion: on failure, find() unlinks (and retires) it on its way
+            if unsafe { &*position.prev }
+                .compare_exchange(position.curr, next, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                guard.retire_node(protected_curr);
+            } else {
+                drop((protected_prev, protected_curr));
+                self.find(sentinel, element_key(hash), Some(value), guard, &mut None);
+            }
+            return true;
+        }
+    }
+
+    /*
+        Harris-Michael search (M.Michael, "High Performance Dynamic Lock-Free Hash Tables and
+        List-Based Sets", 2002), starting from a sentinel: returns the first node, that either
+        matches (same key and value) or goes after key, along with the link pointing to it.
+        marked (logically deleted) nodes met on the way are unlinked and retired. on return,
+        protected holds (prev, curr) protections, that keep position valid
+    */
+    fn find<'g, 'a>(
+        &self,
+        start: *mut SetNode<T>,
+        key: usize,
+        value: Option<&T>,
+        guard: &'g R::Guard<'a, SetNode<T>>,
+        protected: &mut Option<ProtectedPair<'g, 'a, T, R>>,
+    ) -> Position<T> {
+        let mut backoff = Backoff::new();
+        'retry: loop {
+            *protected = None;
+            let mut prev_protected = None;
+            let mut prev: *const AtomicPtr<SetNode<T>> = unsafe { &(*start).0.next };
+            let mut curr = unsafe { (*prev).load(Ordering::Acquire) };
+            loop {
+                if curr.is_null() {
+                    return Position {
+                        prev,
+                        curr,
+                        found: false,
+                    };
+                }
+                let protected_curr = match unsafe { guard.protect(curr) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
+                    Err(ProtectionError::NoAvailableIndices) => {
+                        backoff.spin();
+                        continue 'retry;
+                    }
+                    Err(ProtectionError::NullPointer) => unreachable!(),
+                };
+                // prev is either changed or marked itself: start over
+                if unsafe { (*prev).load(Ordering::Acquire) } != curr {
+                    continue 'retry;
+                }
+
+                let next = protected_curr.0.next.load(Ordering::Acquire);
+                if is_marked(next) {
+                    if unsafe { &*prev }
+                        .compare_exchange(
+                            curr,
+                            without_mark(next),
+                            Ordering::AcqRel,
+                            Ordering::Relaxed,
+                        )
+                        .is_err()
+                    {
+                        backoff.spin();
+                        continue 'retry;
+                    }
+                    guard.retire_node(protected_curr);
+                    curr = without_mark(next);
+                    continue;
+                }
+
+                let curr_key = protected_curr.0.key;
+                if curr_key > key || (curr_key == key && protected_curr.0.value.as_ref() == value) {
+                    let found = curr_key == key;
+                    *protected = Some((prev_protected, protected_curr));
+                    return Position { prev, curr, found };
+                }
+                prev = &protected_curr.0.next;
+                // unprotects the previous prev
+                prev_protected = Some(protected_curr);
+                curr = next;
+            }
+        }
+    }
+
+    // sentinel of hash's bucket under current bucket count, initializing it if needed
+    fn bucket_sentinel(&self, hash: usize, guard: &R::Guard<'_, SetNode<T>>) -> *mut SetNode<T> {
+        let bucket = hash & (self.bucket_count() - 1);
+        self.initialized_bucket(bucket, guard)
+    }
+
+    fn initialized_bucket(
+        &self,
+        bucket: usize,
+        guard: &R::Guard<'_, SetNode<T>>,
+    ) -> *mut SetNode<T> {
+        let slot = self.bucket_slot(bucket);
+        let sentinel = slot.load(Ordering::Acquire);
+        if !sentinel.is_null() {
+            return sentinel;
+        }
+
+        // bucket's elements are still in its parent's run: sentinel splits that run in two
+        let parent_sentinel = self.initialized_bucket(parent_bucket(bucket), guard);
+        let key = sentinel_key(bucket);
+        let new_sentinel = Box::into_raw(Box::new(Node::<T> {
+            key,
+            value: None,
+            next: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut SetNode<T>;
+        let sentinel = loop {
+            let mut protected = None;
+            let position = self.find(parent_sentinel, key, None, guard, &mut protected);
+            if position.found {
+                // some other thread has initialized it concurrently
+                unsafe { drop(Box::from_raw(new_sentinel)) };
+                break position.curr;
+            }
+            unsafe {
+                (*new_sentinel)
+                    .0
+                    .next
+                    .store(position.curr, Ordering::Relaxed)
+            };
+            if unsafe { &*position.prev }
+                .compare_exchange(
+                    position.curr,
+                    new_sentinel,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                break new_sentinel;
+            }
+        };
+        // everybody, who got here, has found the same sentinel in list
+        slot.store(sentinel, Ordering::Release);
+        sentinel
+    }
+
+    fn bucket_slot(&self, bucket: usize) -> &AtomicPtr<SetNode<T>> {
+        let segment_slot = &self.segments[bucket / SEGMENT_SIZE];
+        let mut segment = segment_slot.load(Ordering::Acquire);
+        if segment.is_null() {
+            let new_segment = Box::into_raw(Box::new(BucketSegment {
+                buckets: [const { AtomicPtr::new(ptr::null_mut()) }; SEGMENT_SIZE],
+            }));
+            segment = match segment_slot.compare_exchange(
+                ptr::null_mut(),
+                new_segment,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => new_segment,
+                Err(actual) => {
+                    unsafe { drop(Box::from_raw(new_segment)) };
+                    actual
+                }
+            };
+        }
+        unsafe { &(*segment).buckets[bucket % SEGMENT_SIZE] }
+    }
+
+    /*
+        structural check for tests, run in a single-threaded phase (hence &mut self): list is
+        sorted by split order key, has no marked nodes left, and every initialized bucket points
+        to its own sentinel. returns number of elements
+    */
+    #[cfg(test)]
+    pub(crate) fn check_invariants(&mut self) -> usize {
+        let mut elements = 0;
+        let mut previous_key = 0;
+        let mut current = self.bucket_slot(0).load(Ordering::Relaxed);
+        while !current.is_null() {
+            let node = unsafe { &(*current).0 };
+            assert!(
+                node.key >= previous_key,
+                "LockFreeHashSet: list isn't sorted"
+            );
+            previous_key = node.key;
+            elements += node.value.is_some() as usize;
+            let next = node.next.load(Ordering::Relaxed);
+            assert!(
+                !is_marked(next),
+                "LockFreeHashSet: marked node left in list"
+            );
+            current = next;
+        }
+        for bucket in 0..self.bucket_count() {
+            let sentinel = self.bucket_slot(bucket).load(Ordering::Relaxed);
+            if !sentinel.is_null() {
+                let sentinel = unsafe { &(*sentinel).0 };
+                assert!(sentinel.value.is_none() && sentinel.key == sentinel_key(bucket));
+            }
+        }
+        elements
+    }
+}
+
+impl<T, R: Reclaimer> Drop for LockFreeHashSet<T, R> {
+    fn drop(&mut self) {
+        // sentinels and elements are all in the list, buckets only point into it
+        let first_segment = *self.segments[0].get_mut();
+        let mut current = unsafe { *(*first_segment).buckets[0].get_mut() };
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current as *mut Node<T>) };
+            current = without_mark(node.next.load(Ordering::Relaxed));
+        }
+        for segment in self.segments.iter_mut() {
+            if !segment.get_mut().is_null() {
+                unsafe { drop(Box::from_raw(*segment.get_mut())) };
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync, R: Reclaimer> Sync for LockFreeHashSet<T, R> {}
+unsafe impl<T: Send, R: Reclaimer> Send for LockFreeHashSet<T, R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{parent_bucket, LockFreeHashSet, INITIAL_BUCKETS};
+    use crate::mechanisms::hp::HazardPointerArray;
+
+    #[test]
+    fn test_basic_operations() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut set = LockFreeHashSet::new();
+        assert!(!set.contains(&1, &guard));
+        assert!(set.insert(1, &guard));
+        assert!(!set.insert(1, &guard));
+        assert!(set.contains(&1, &guard));
+        assert!(set.remove(&1, &guard));
+        assert!(!set.remove(&1, &guard));
+        assert!(!set.contains(&1, &guard));
+        assert!(set.is_empty());
+
+        // grows past initial bucket count
+        for i in 0..1000 {
+            assert!(set.insert(i, &guard));
+        }
+        assert_eq!(set.len(), 1000);
+        assert!(set.bucket_count() >= 1000 / 2);
+        assert!((0..1000).all(|i| set.contains(&i, &guard)));
+        assert_eq!(set.check_invariants(), 1000);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    #[test]
+    fn test_parent_bucket() {
+        assert_eq!(parent_bucket(1), 0);
+        assert_eq!(parent_bucket(2), 0);
+        assert_eq!(parent_bucket(3), 1);
+        assert_eq!(parent_bucket(6), 2);
+        assert_eq!(parent_bucket(13), 5);
+    }
+
+    // mixed inserts and removes of overlapping ranges, while the set keeps resizing
+    #[test]
+    fn test_concurrent() {
+        let hp_array = HazardPointerArray::new();
+        let threads = 4;
+        let per_thread = 2000;
+        let mut set = LockFreeHashSet::new();
+        let (set_ref, hp_array_ref) = (&set, &hp_array);
+
+        std::thread::scope(|s| {
+            for t in 0..threads {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    // neighbours' ranges overlap by half, so every value is inserted twice
+                    let range = t * per_thread / 2..t * per_thread / 2 + per_thread;
+                    for i in range.clone() {
+                        set_ref.insert(i, &guard);
+                        assert!(set_ref.contains(&i, &guard));
+                    }
+                    // odd ones go away, some of them while the other thread still inserts them
+                    for i in range.filter(|i| i % 2 == 1) {
+                        set_ref.remove(&i, &guard);
+                    }
+                });
+            }
+        });
+
+        let guard = hp_array.register_thread().ok().unwrap();
+        let total = (threads + 1) * per_thread / 2;
+        assert!(set.bucket_count() > INITIAL_BUCKETS);
+        for i in 0..total {
+            // every thread removes odd values only after inserting them, so the last operation
+            // on an odd value is always a remove
+            assert_eq!(set.contains(&i, &guard), i % 2 == 0, "value {}", i);
+        }
+        drop(guard);
+        assert_eq!(set.check_invariants(), total / 2);
+        assert_eq!(set.len(), total / 2);
+    }
+}
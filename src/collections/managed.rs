@@ -0,0 +1,224 @@
+use crate::collections::ms_queue::{MSQueue, QueueNode};
+use crate::collections::treiber_stack::{StackNode, TreiberStack};
+use crate::mechanisms::hp::{HazardPointerArray, HazardPointerGuard};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+/*
+    collections, that hide hazard pointers entirely: each one owns its HazardPointerArray, and
+    every thread gets its guard registered lazily on the first operation that needs one. guards
+    live in a thread-local map keyed by collection's id (not address, which may be reused), and
+    are dropped on thread exit, giving the array slot back and leaving unreclaimed nodes as
+    orphans to other threads. thread-local entry also holds the array itself, so that array
+    outlives every guard borrowing it, even if the collection is dropped first; entries of dropped
+    collections are cleaned up next time this thread registers a guard.
+    as with explicit guards, at most MAX_THREADS threads may use one collection at a time.
+    for the explicit-guard api (and for other reclaimers), use TreiberStack and MSQueue directly
+*/
+
+static MANAGED_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static GUARDS: RefCell<HashMap<usize, LocalGuard>> = RefCell::new(HashMap::new());
+}
+
+struct LocalGuard {
+    // HazardPointerGuard<'static, N>, borrowing the array below. fields are dropped in order of
+    // declaration, so guard goes first
+    guard: Box<dyn Any>,
+    _array: Arc<HazardPointerArray>,
+    // dangling once collection is dropped
+    collection: Weak<()>,
+}
+
+struct ManagedDomain {
+    id: usize,
+    array: Arc<HazardPointerArray>,
+    alive: Arc<()>,
+}
+
+impl ManagedDomain {
+    fn new() -> Self {
+        ManagedDomain {
+            id: MANAGED_ID.fetch_add(1, Ordering::Relaxed),
+            array: Arc::new(HazardPointerArray::new()),
+            alive: Arc::new(()),
+        }
+    }
+
+    fn with_guard<N: 'static, U>(
+        &self,
+        operation: &str,
+        f: impl FnOnce(&HazardPointerGuard<'static, N>) -> U,
+    ) -> U {
+        let guard = GUARDS.with(|guards| {
+            let mut guards = guards.borrow_mut();
+            if !guards.contains_key(&self.id) {
+                guards.retain(|_, local| local.collection.strong_count() > 0);
+                // safety: thread-local entry keeps its own reference to the array, and drops
+                // the guard before it
+                let array: &'static HazardPointerArray = unsafe { &*Arc::as_ptr(&self.array) };
+                let guard = match array.register_thread::<N>() {
+                    Ok(guard) => guard,
+                    Err(_) => panic!("{}: more threads than MAX_THREADS use collection", operation),
+                };
+                guards.insert(
+                    self.id,
+                    LocalGuard {
+                        guard: Box::new(guard),
+                        _array: self.array.clone(),
+                        collection: Arc::downgrade(&self.alive),
+                    },
+                );
+            }
+            let guard = guards[&self.id].guard.downcast_ref::<HazardPointerGuard<'static, N>>();
+            guard.unwrap() as *const HazardPointerGuard<'static, N>
+        });
+        /*
+            map isn't borrowed while operation runs: it may drop values (and so run user code),
+            that uses other managed collections. entry is boxed and stays in the map for as long
+            as collection is alive, so the pointer remains valid
+        */
+        f(unsafe { &*guard })
+    }
+}
+
+pub struct ManagedStack<T: 'static> {
+    // dropped before the domain
+    stack: TreiberStack<T>,
+    domain: ManagedDomain,
+}
+
+impl<T: Send + 'static> Default for ManagedStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + 'static> ManagedStack<T> {
+    pub fn new() -> Self {
+        ManagedStack {
+            stack: TreiberStack::new(),
+            domain: ManagedDomain::new(),
+        }
+    }
+
+    pub fn push(&self, data: T) {
+        self.stack.push(data);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.domain
+            .with_guard::<StackNode<T>, _>("ManagedStack::pop()", |guard| self.stack.pop(guard))
+    }
+}
+
+pub struct ManagedQueue<T: 'static> {
+    // dropped before the domain
+    queue: MSQueue<T>,
+    domain: ManagedDomain,
+}
+
+impl<T: Send + 'static> Default for ManagedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + 'static> ManagedQueue<T> {
+    pub fn new() -> Self {
+        ManagedQueue {
+            queue: MSQueue::new(),
+            domain: ManagedDomain::new(),
+        }
+    }
+
+    pub fn enqueue(&self, value: T) -> bool {
+        self.domain.with_guard::<QueueNode<T>, _>("ManagedQueue::enqueue()", |guard| {
+            self.queue.enqueue(value, guard)
+        })
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        self.domain.with_guard::<QueueNode<T>, _>("ManagedQueue::dequeue()", |guard| {
+            self.queue.dequeue(guard)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManagedQueue, ManagedStack, GUARDS};
+    use std::collections::HashSet;
+
+    // guards are registered on demand, and given back on thread exit: many more threads than
+    // MAX_THREADS use the same collections over time
+    #[test]
+    fn test_managed_across_threads() {
+        let stack = ManagedStack::new();
+        let queue = ManagedQueue::new();
+        let threads = 3;
+        let per_thread = 1000;
+        let (stack_ref, queue_ref) = (&stack, &queue);
+
+        for _ in 0..4 {
+            // joined explicitly: unlike scope's implicit join, it waits for thread-local
+            // destructors, so previous threads' guards are given back before the next round
+            std::thread::scope(|s| {
+                let handles = (0..threads)
+                    .map(|t| {
+                        s.spawn(move || {
+                            for i in 0..per_thread {
+                                stack_ref.push(t * per_thread + i);
+                                queue_ref.enqueue(t * per_thread + i);
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            });
+
+            let popped = std::thread::scope(|s| {
+                let handles = (0..threads)
+                    .map(|_| {
+                        s.spawn(move || {
+                            let mut values = Vec::new();
+                            while let Some(value) = stack_ref.pop() {
+                                values.push(value);
+                            }
+                            while let Some(value) = queue_ref.dequeue() {
+                                values.push(value);
+                            }
+                            values
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            assert_eq!(popped.len(), 2 * threads * per_thread);
+            assert_eq!(
+                popped.into_iter().collect::<HashSet<_>>(),
+                (0..threads * per_thread).collect::<HashSet<_>>()
+            );
+        }
+    }
+
+    // guards of dropped collections don't pile up on a long-lived thread
+    #[test]
+    fn test_dropped_collections_are_cleaned_up() {
+        for i in 0..100 {
+            let queue = ManagedQueue::new();
+            queue.enqueue(i);
+            assert_eq!(queue.dequeue(), Some(i));
+        }
+        assert!(GUARDS.with(|guards| guards.borrow().len()) <= 1);
+    }
+}
@@ -1,3 +1,16 @@
 pub mod treiber_stack;
 pub mod ms_queue;
-pub mod optimistic_ms_queue;
\ No newline at end of file
+pub mod optimistic_ms_queue;
+pub mod bag;
+pub mod seg_queue;
+pub mod managed;
+pub mod cas;
+pub mod frozen;
+pub mod hash_set;
+pub mod mpsc_queue;
+pub mod spsc_ring;
+pub mod queue_group;
+
+pub use ms_queue::MSQueue;
+pub use optimistic_ms_queue::OMSQueue;
+pub use treiber_stack::TreiberStack;
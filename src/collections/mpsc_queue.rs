@@ -0,0 +1,212 @@
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/*
+    bounded multi-producer single-consumer queue, e.g. for actor's mailbox (D.Vyukov's
+    intrusive MPSC node-based queue). producers link their nodes at the tail, consumer takes
+    them from the head. no hazard pointers (and no guards) at all:
+    - producer claims the tail with a single swap (CAS that can't fail), and only then writes
+      into the previous tail's next. the previous tail can't be freed meanwhile: consumer frees
+      a node only once it has moved past it, which takes that very next to be set;
+    - there's exactly one consumer, so nobody but it ever reads the head, and it frees nodes
+      right away.
+    the price is a short window between producer's swap and its next store: consumer sees the
+    queue as empty (or ending) there, even though later producers' nodes may already be linked
+    after it. those become visible once the stalled producer completes its store.
+    capacity bounds the number of values in the queue, send() gives the value back when full
+*/
+
+struct Node<T> {
+    // None for dummy
+    value: Option<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+struct MpscQueue<T> {
+    // consumer-owned dummy node
+    head: UnsafeCell<*mut Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    len: AtomicUsize,
+    capacity: usize,
+}
+
+pub struct Sender<T> {
+    queue: Arc<MpscQueue<T>>,
+}
+
+// the only one, so it isn't Clone, and it isn't Sync: head is never read by two threads
+pub struct Receiver<T> {
+    queue: Arc<MpscQueue<T>>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+pub fn mpsc_queue<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let dummy = Box::into_raw(Box::new(Node {
+        value: None,
+        next: AtomicPtr::new(ptr::null_mut()),
+    }));
+    let queue = Arc::new(MpscQueue {
+        head: UnsafeCell::new(dummy),
+        tail: AtomicPtr::new(dummy),
+        len: AtomicUsize::new(0),
+        capacity,
+    });
+    (
+        Sender {
+            queue: queue.clone(),
+        },
+        Receiver {
+            queue,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Sender<T> {
+    // gives value back if queue is full
+    pub fn send(&self, value: T) -> Result<(), T> {
+        let queue = &*self.queue;
+        // reserve a place first, so that the queue never goes over capacity
+        if queue
+            .len
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |len| {
+                (len < queue.capacity).then_some(len + 1)
+            })
+            .is_err()
+        {
+            return Err(value);
+        }
+        let node = Box::into_raw(Box::new(Node {
+            value: Some(value),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }));
+        let prev = queue.tail.swap(node, Ordering::AcqRel);
+        // consumer doesn't go past prev (let alone free it) until this store
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // None if queue is empty, or if the next value's producer hasn't linked its node yet
+    pub fn try_recv(&mut self) -> Option<T> {
+        let queue = &*self.queue;
+        let head = unsafe { *queue.head.get() };
+        let next = unsafe { (*head).next.load(Ordering::Acquire) };
+        if next.is_null() {
+            return None;
+        }
+        // next becomes the new dummy, the old one is ours to free: producers are done with it
+        let value = unsafe { (*next).value.take() };
+        unsafe {
+            *queue.head.get() = next;
+            drop(Box::from_raw(head));
+        }
+        queue.len.fetch_sub(1, Ordering::Relaxed);
+        value
+    }
+
+    // approximate under concurrent sends
+    pub fn len(&self) -> usize {
+        self.queue.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity
+    }
+}
+
+// both ends are gone by now
+impl<T> Drop for MpscQueue<T> {
+    fn drop(&mut self) {
+        let mut current = *self.head.get_mut();
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current) };
+            current = node.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for MpscQueue<T> {}
+unsafe impl<T: Send> Sync for MpscQueue<T> {}
+// may be moved to another thread, but not shared
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::mpsc_queue;
+
+    #[test]
+    fn test_basic_operations() {
+        let (sender, mut receiver) = mpsc_queue(2);
+        assert!(receiver.try_recv().is_none());
+        assert!(sender.send(1).is_ok());
+        assert!(sender.clone().send(2).is_ok());
+        assert_eq!(sender.send(3), Err(3));
+        assert_eq!(receiver.len(), 2);
+        assert_eq!(receiver.try_recv(), Some(1));
+        assert!(sender.send(3).is_ok());
+        assert_eq!(receiver.try_recv(), Some(2));
+        assert_eq!(receiver.try_recv(), Some(3));
+        assert_eq!(receiver.try_recv(), None);
+        assert!(receiver.is_empty());
+
+        // leftovers are freed with the queue
+        sender.send(4).unwrap();
+        drop((sender, receiver));
+    }
+
+    // more producers than hazard pointer arrays would allow: no guards involved
+    #[test]
+    fn test_many_senders() {
+        let (sender, mut receiver) = mpsc_queue(64);
+        let senders = 8;
+        let per_sender = 5000;
+        std::thread::scope(|s| {
+            for p in 0..senders {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..per_sender {
+                        let mut value = (p, i);
+                        while let Err(rejected) = sender.send(value) {
+                            value = rejected;
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            // every sender's values arrive exactly once, and in order
+            let mut next_expected = vec![0; senders];
+            let mut received = 0;
+            while received < senders * per_sender {
+                match receiver.try_recv() {
+                    Some((p, i)) => {
+                        assert_eq!(i, next_expected[p]);
+                        next_expected[p] += 1;
+                        received += 1;
+                        assert!(receiver.len() <= receiver.capacity());
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+            assert!(next_expected.iter().all(|&count| count == per_sender));
+        });
+        assert_eq!(receiver.try_recv(), None);
+    }
+}
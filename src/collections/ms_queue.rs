@@ -1,109 +1,1046 @@
-use crate::mechanisms::hp::{HazardPointerGuard, ProtectionError};
-use std::default::Default;
+use crate::collections::cas::CasStrength;
+use crate::collections::frozen::{FrozenView, InsufficientSlots, PinnedView};
+use crate::mechanisms::hp::ProtectionError;
+use crate::mechanisms::reclaim::{HazardBudget, HpReclaimer, Protected, Reclaimer, ReclaimerGuard};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::ptr;
-use std::sync::atomic::{fence, AtomicPtr, Ordering};
+use std::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use crate::utils::backoff::Backoff;
+use crate::utils::interleave::yield_point;
 
-pub struct MSQueue<T> {
+// dequeue_timeout() stages: failed attempts with spinning, then with yielding, before parking
+const SPIN_ROUNDS: usize = 8;
+const YIELD_ROUNDS: usize = 8;
+
+pub struct MSQueue<T, R: Reclaimer = HpReclaimer> {
     head: AtomicPtr<QueueNode<T>>,
     tail: AtomicPtr<QueueNode<T>>,
+    // threads parked in dequeue_timeout(), so that enqueue touches the mutex only if there are any
+    waiters: AtomicUsize,
+    parking: Mutex<()>,
+    not_empty: Condvar,
+    // for retried CASes only, see CasStrength
+    cas_strength: CasStrength,
+    tail_help: TailHelpPolicy,
+    // see bounded(). None for unbounded queue
+    capacity: Option<usize>,
+    // number of elements, including enqueues in flight, see approx_len()
+    len: AtomicUsize,
+    // see cas_retries()
+    #[cfg(feature = "scan-stats")]
+    cas_retries: AtomicUsize,
+    _reclaimer: PhantomData<R>,
 }
 
+/*
+    data is initialized in the nodes, that hold elements, and uninitialized in the dummy: initial
+    dummy is created without a value, and a node becomes the dummy at the very head CAS, that
+    dequeues its value, so the dequeuer moves the value out right after that (see dequeue_with()).
+    MaybeUninit never drops its content, so neither a freed dummy, nor a retired one (old dummies
+    are the only nodes that get retired) drops anything: values are dropped only by whoever has
+    moved them out, and by Drop for the ones still in the queue. that's what lets T be anything,
+    with no Default to make up a placeholder from
+*/
 struct Node<T> {
-    data: T,
+    data: MaybeUninit<T>,
     next: AtomicPtr<QueueNode<T>>,
 }
 
 #[repr(transparent)]
 pub struct QueueNode<T>(Node<T>);
 
-impl<T> MSQueue<T>
+impl<T: Send> Default for MSQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> MSQueue<T> {
+    pub fn new() -> MSQueue<T> {
+        Self::with_reclaimer()
+    }
+
+    /*
+        queue of at most capacity elements, for producer/consumer pipelines with backpressure:
+        enqueue() returns false (dropping the value), and try_enqueue() gives the value back,
+        once the queue is full. same soft bound as OMSQueue::bounded(): enqueue reserves a place
+        in len (fetch_add, rolled back with fetch_sub, if it went over capacity) before it links
+        its node, and gives it back, if it doesn't link it after all. dequeue gives its place back
+        only after its head CAS. so under races, len includes reservations in flight, and enqueue
+        may be refused with fewer than capacity elements actually in the queue, but the queue
+        never holds more than capacity.
+        e.g. MSQueue::<T, EbrReclaimer>::with_reclaimer().with_capacity(cap)
+    */
+    pub fn bounded(capacity: usize) -> MSQueue<T> {
+        Self::new().with_capacity(capacity)
+    }
+}
+
+impl<T, R> MSQueue<T, R>
 where
-    T: Default,
+    T: Send,
+    R: Reclaimer,
 {
-    pub fn new() -> MSQueue<T> {
+    // max number of pointers a single operation protects at once (dequeue: head and its next,
+    // front_and_len: head, current node and its next). hazard pointer guards used with this queue
+    // need at least that many free slots
+    pub const HAZARDS_PER_OP: usize = 3;
+
+    // e.g. MSQueue::<T, EbrReclaimer>::with_reclaimer()
+    pub fn with_reclaimer() -> MSQueue<T, R> {
         //head should point to a dummy node
         let dummy_node = Box::into_raw(Box::new(Node {
-            data: T::default(),
+            data: MaybeUninit::<T>::uninit(),
             next: AtomicPtr::new(ptr::null_mut()),
         })) as * mut QueueNode<T>;
         MSQueue {
             head: AtomicPtr::new(dummy_node),
             tail: AtomicPtr::new(dummy_node),
+            waiters: AtomicUsize::new(0),
+            parking: Mutex::new(()),
+            not_empty: Condvar::new(),
+            cas_strength: CasStrength::Weak,
+            tail_help: TailHelpPolicy::Always,
+            capacity: None,
+            len: AtomicUsize::new(0),
+            #[cfg(feature = "scan-stats")]
+            cas_retries: AtomicUsize::new(0),
+            _reclaimer: PhantomData,
         }
     }
 
-    // user should register thread to obtain guard
-    pub fn enqueue(&self, value: T, guard: &HazardPointerGuard<QueueNode<T>>) -> bool {
-        let mut backoff = Backoff::new();
+    // e.g. MSQueue::new().with_cas_strength(CasStrength::Strong)
+    pub fn with_cas_strength(mut self, cas_strength: CasStrength) -> Self {
+        self.cas_strength = cas_strength;
+        self
+    }
 
-        let new_node = Box::into_raw(Box::new(Node {
-            data: value,
-            next: AtomicPtr::new(ptr::null_mut()),
-        })) as * mut QueueNode<T>;
+    // e.g. MSQueue::new().with_tail_help(TailHelpPolicy::OnlyWhenBlocked)
+    pub fn with_tail_help(mut self, tail_help: TailHelpPolicy) -> Self {
+        self.tail_help = tail_help;
+        self
+    }
+
+    // see bounded()
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    // number of lost races for tail's next so far, i.e. link CASes enqueue() and enqueue_batch()
+    // had to retry, e.g. for benchmarks: wall time alone doesn't tell contention from the rest
+    #[cfg(feature = "scan-stats")]
+    pub fn cas_retries(&self) -> usize {
+        self.cas_retries.load(Ordering::Relaxed)
+    }
+
+    /*
+        number of elements, kept in a counter rather than counted by a walk, e.g. for monitoring.
+        exact once the queue is quiescent. under races it's a moment's view of the counter, that
+        enqueue bumps before it links its node, and dequeue lowers only after its head CAS: so it
+        may include elements about to be enqueued (or refused by bounded queue, see bounded()),
+        and ones already dequeued, but it never misses an element, that is in the queue.
+        hence not a len(): it may well be non-zero, while is_empty() (which reads head's next
+        itself) reports an empty queue, e.g. right after an enqueue has reserved its place,
+        but before it has linked its node.
+        note the cost: every enqueue and dequeue does one more RMW on the counter, on top of its
+        CAS, and it's a single cache line shared by all producers and consumers, so it contends
+        just like head and tail do. bounded queue needs it anyway, unbounded one pays for this
+        method alone
+    */
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    // reserves a place for one more element, see bounded()
+    fn reserve(&self) -> bool {
+        self.reserve_many(1)
+    }
+
+    // reserves places for count more elements at once, or for none of them. unbounded queue
+    // never refuses, but counts them all the same, see approx_len()
+    fn reserve_many(&self, count: usize) -> bool {
+        let len = self.len.fetch_add(count, Ordering::Relaxed);
+        match self.capacity {
+            Some(capacity) if len + count > capacity => {
+                self.len.fetch_sub(count, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    // gives back the place of a dequeued element, or of an enqueue, that has given up
+    fn release(&self) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // user should register thread to obtain guard. false if bounded queue is full, value is
+    // dropped then (see try_enqueue())
+    pub fn enqueue(&self, value: T, guard: &R::Guard<'_, QueueNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::enqueue()");
+        self.enqueue_with(value, guard, false).is_ok()
+    }
+
+    /*
+        enqueue, that gives value back instead of waiting for a free hazard slot. guard's slots
+        are only ever taken by its own thread, so if they are all held (by the caller, say, with
+        a few long-lived protections), none of them is freed while enqueue waits: enqueue() would
+        spin forever. so there's no point in retrying, and it fails on the first exhausted attempt,
+        same as PopPolicy::NonBlocking does. gives value back if bounded queue is full as well
+    */
+    pub fn try_enqueue(&self, value: T, guard: &R::Guard<'_, QueueNode<T>>) -> Result<(), T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::try_enqueue()");
+        self.enqueue_with(value, guard, true)
+    }
+
+    /*
+        new node is allocated only once tail is protected for the first time: after that, slot
+        is released and taken again on every retry, so protection can't run out anymore. until
+        then, value stays with us, and giving up (on hazard slots exhaustion) hands it back
+    */
+    fn enqueue_with(
+        &self,
+        value: T,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        give_up_on_exhaustion: bool,
+    ) -> Result<(), T> {
+        if !self.reserve() {
+            return Err(value);
+        }
+        let mut hp_backoff = Backoff::new();
+        let mut cas_backoff = Backoff::new();
+
+        let mut value = Some(value);
+        let mut new_node: *mut QueueNode<T> = ptr::null_mut();
 
         let mut tail_ptr = std::mem::MaybeUninit::<*mut QueueNode<T>>::uninit();
+        // consecutive attempts, that have found tail lagging, see TailHelpPolicy
+        let mut lagging_attempts = 0;
 
         loop {
+            yield_point();
             tail_ptr.write(self.tail.load(Ordering::Relaxed));
-            let mut protected_tail = match unsafe { guard.protect(tail_ptr.assume_init()) } {
+            let protected_tail = match unsafe { guard.protect(tail_ptr.assume_init()) } {
                 Ok(ptr) => {
                     fence(Ordering::Acquire);
                     ptr
                 }
-                Err(_) => {
-                    backoff.spin();
+                Err(ProtectionError::NoAvailableIndices) if give_up_on_exhaustion => {
+                    self.release();
+                    return Err(match value {
+                        Some(value) => value,
+                        // never published, nobody else has seen it
+                        None => unsafe { Box::from_raw(new_node as *mut Node<T>).data.assume_init() },
+                    });
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    hp_backoff.spin();
                     continue; // no hazard pointer slots available, retry
                 }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue::enqueue(): found null pointer while protecting tail");
+                }
             };
+            hp_backoff.reset();
+            // tail may have been dequeued and freed before protection was published. then its
+            // memory may even be handed out to our own node below, so recheck before using it
+            if self.tail.load(Ordering::Acquire) != unsafe { tail_ptr.assume_init() } {
+                continue;
+            }
+            if let Some(value) = value.take() {
+                new_node = Box::into_raw(Box::new(Node {
+                    data: MaybeUninit::new(value),
+                    next: AtomicPtr::new(ptr::null_mut()),
+                })) as *mut QueueNode<T>;
+            }
 
             // first, check if tail is located correctly
-            let tail_next = (*protected_tail).0.next.load(Ordering::Acquire);
-            if tail_next != ptr::null_mut() {
-                _ = self.tail.compare_exchange_weak(
+            let tail_next = protected_tail.0.next.load(Ordering::Acquire);
+            if !tail_next.is_null() {
+                lagging_attempts += 1;
+                self.on_lagging_tail(
                     protected_tail.as_mut_ptr(),
                     tail_next,
-                    Ordering::Release,
-                    Ordering::Relaxed,
+                    lagging_attempts,
+                    &mut cas_backoff,
                 );
                 // regardless succeed we or not need to protect new tail node pointer
                 continue;
             }
+            lagging_attempts = 0;
 
-            if (*protected_tail)
-                .0
-                .next
-                .compare_exchange_weak(
+            yield_point();
+            if self
+                .cas_strength
+                .compare_exchange(
+                    &protected_tail.0.next,
                     ptr::null_mut(),
                     new_node,
-                    Ordering::Release,
+                    // acquire: see wake_waiters()
+                    Ordering::AcqRel,
                     Ordering::Relaxed,
                 )
                 .is_ok()
             {
                 break;
             }
+            // lost the race for tail's next, back off before retrying
+            #[cfg(feature = "scan-stats")]
+            self.cas_retries.fetch_add(1, Ordering::Relaxed);
+            cas_backoff.spin();
         }
         // attempt to proceed tail; on fail, tail will be proceeded by others
-        _ = self.tail.compare_exchange_weak(
-            unsafe { tail_ptr.assume_init_read() },
-            new_node,
+        yield_point();
+        self.proceed_tail(unsafe { tail_ptr.assume_init_read() }, &[new_node]);
+        self.wake_waiters(1);
+        Ok(())
+    }
+
+    /*
+        enqueues all items in order, as one contiguous run, e.g. for bulk loads: nodes are linked
+        into a chain locally first, then the whole chain is spliced onto the tail with a single
+        next CAS, and tail is proceeded straight to its last node. so the batch costs one link CAS
+        and one tail CAS, instead of one of each per item, and no other enqueue gets in between
+        its items. on a lost race only the splice is retried, the chain stays as it is. until tail
+        is proceeded, it lags behind by up to the whole batch, and other threads treat it as any
+        lagging tail (see TailHelpPolicy), one node at a time.
+        bounded queue takes the whole batch or nothing: false, and items are dropped, if it
+        doesn't fit (see bounded()). empty batch is a no-op
+    */
+    pub fn enqueue_batch(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        guard: &R::Guard<'_, QueueNode<T>>,
+    ) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::enqueue_batch()");
+        let chain: Vec<*mut QueueNode<T>> = items
+            .into_iter()
+            .map(|value| {
+                Box::into_raw(Box::new(Node {
+                    data: MaybeUninit::new(value),
+                    next: AtomicPtr::new(ptr::null_mut()),
+                })) as *mut QueueNode<T>
+            })
+            .collect();
+        let (Some(&first), Some(&last)) = (chain.first(), chain.last()) else {
+            return true;
+        };
+        if !self.reserve_many(chain.len()) {
+            // never published, nobody else has seen them
+            for node in chain {
+                unsafe { Box::from_raw(node as *mut Node<T>).data.assume_init_drop() };
+            }
+            return false;
+        }
+        // release CAS of the splice publishes these along with the values
+        for pair in chain.windows(2) {
+            unsafe { (*pair[0]).0.next.store(pair[1], Ordering::Relaxed) };
+        }
+
+        let mut hp_backoff = Backoff::new();
+        let mut cas_backoff = Backoff::new();
+        let mut lagging_attempts = 0;
+        let tail_ptr = loop {
+            let tail_ptr = self.tail.load(Ordering::Relaxed);
+            let protected_tail = match unsafe { guard.protect(tail_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    hp_backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue::enqueue_batch(): found null pointer while protecting tail");
+                }
+            };
+            hp_backoff.reset();
+            // see enqueue_with()
+            if self.tail.load(Ordering::Acquire) != tail_ptr {
+                continue;
+            }
+            let tail_next = protected_tail.0.next.load(Ordering::Acquire);
+            if !tail_next.is_null() {
+                lagging_attempts += 1;
+                self.on_lagging_tail(tail_ptr, tail_next, lagging_attempts, &mut cas_backoff);
+                continue;
+            }
+            lagging_attempts = 0;
+
+            if self
+                .cas_strength
+                .compare_exchange(
+                    &protected_tail.0.next,
+                    ptr::null_mut(),
+                    first,
+                    // acquire: see wake_waiters()
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                break tail_ptr;
+            }
+            #[cfg(feature = "scan-stats")]
+            self.cas_retries.fetch_add(1, Ordering::Relaxed);
+            cas_backoff.spin();
+        };
+        debug_assert!(unsafe { (*last).0.next.load(Ordering::Relaxed) }.is_null());
+        self.proceed_tail(tail_ptr, &chain);
+        self.wake_waiters(chain.len());
+        true
+    }
+
+    // enqueuer has found tail lagging for lagging_attempts in a row, see TailHelpPolicy
+    fn on_lagging_tail(
+        &self,
+        tail: *mut QueueNode<T>,
+        tail_next: *mut QueueNode<T>,
+        lagging_attempts: usize,
+        cas_backoff: &mut Backoff,
+    ) {
+        if !self.tail_help.should_help(lagging_attempts) {
+            // leave it to its enqueuer, and see again
+            cas_backoff.spin();
+            return;
+        }
+        // helping to proceed tail is a progress, while failing it means someone else
+        // has already done it and we're racing with other enqueuers
+        match self.cas_strength.compare_exchange(
+            &self.tail,
+            tail,
+            tail_next,
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => cas_backoff.reset(),
+            Err(_) => cas_backoff.spin(),
+        }
+    }
+
+    /*
+        moves tail from tail_ptr, that chain (the nodes just linked, in order) has been spliced
+        onto, to the chain's last node. with others helping, tail may be already proceeded.
+        without helping, nobody but us moves it from our old tail, but dequeue, that proceeds it
+        one node at a time along our chain, if it catches up with the tail (see TailHelpPolicy).
+        so then failure is either spurious, or leaves tail at one of our nodes: either way it's
+        ours to retry, or enqueuers would wait for the tail forever
+    */
+    fn proceed_tail(&self, tail_ptr: *mut QueueNode<T>, chain: &[*mut QueueNode<T>]) {
+        let last = chain[chain.len() - 1];
+        let mut expected = tail_ptr;
+        // position in chain, that dequeue has proceeded tail to so far
+        let mut reached = 0;
+        while let Err(actual) = self.cas_strength.compare_exchange(
+            &self.tail,
+            expected,
+            last,
             Ordering::Release,
             Ordering::Relaxed,
+        ) {
+            if actual == last || self.tail_help != TailHelpPolicy::Never {
+                break;
+            }
+            if actual != expected {
+                match chain[reached..chain.len() - 1].iter().position(|&node| node == actual) {
+                    Some(offset) => reached += offset,
+                    None => break,
+                }
+                expected = actual;
+            }
+        }
+    }
+
+    /*
+        threads park only after they've found the queue empty, so waking one up on enqueue is
+        effectively an empty -> non-empty transition signal. enqueue pays no more than a relaxed
+        load here, it's the waiter, that does the handshake: having registered, it confirms the
+        queue is empty with an RMW on the last node's next (see confirm_empty()), the very word
+        our linking CAS writes. both are RMWs on the same word, so one of them comes first:
+        - waiter's one: our CAS reads what it has written (acquire on release), so we see
+          the waiter registered;
+        - ours: waiter's RMW fails on our node, and it dequeues instead of parking.
+        taking the mutex guarantees the waiter is either not yet past its confirmation or already
+        parked, so the notification isn't lost.
+        count is the number of enqueued elements: a batch has enough for every waiter
+    */
+    fn wake_waiters(&self, count: usize) {
+        if self.waiters.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let _parking = self.parking.lock().unwrap_or_else(PoisonError::into_inner);
+        if count == 1 {
+            self.not_empty.notify_one();
+        } else {
+            self.not_empty.notify_all();
+        }
+    }
+
+    /*
+        single-threaded fast path, e.g. for bulk initialization before the queue is shared, or
+        for teardown. &mut self guarantees nobody else is accessing the queue, so we skip CAS
+        loops and reclamation entirely and work with plain loads and stores via get_mut().
+        bounded queue counts the element, but doesn't refuse it: it's up to the caller
+    */
+    pub fn enqueue_mut(&mut self, value: T) {
+        *self.len.get_mut() += 1;
+        let new_node = Box::into_raw(Box::new(Node {
+            data: MaybeUninit::new(value),
+            next: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut QueueNode<T>;
+        let tail = self.tail.get_mut();
+        // tail could be left lagging by some concurrent enqueue, whose final CAS failed
+        loop {
+            let tail_next = unsafe { *(**tail).0.next.get_mut() };
+            if tail_next.is_null() {
+                break;
+            }
+            *tail = tail_next;
+        }
+        unsafe { *(**tail).0.next.get_mut() = new_node };
+        *tail = new_node;
+    }
+
+    /*
+        transition protocol between exclusive use (_mut fast paths, plain loads and stores) and
+        shared use (concurrent operations):
+        - exclusive -> shared: call sync_fence() after the last _mut call and before publishing
+          the queue. it's a release fence, so that publishing store, even a relaxed one (e.g. of
+          a pointer to the queue into some AtomicPtr), carries all the fast path writes along,
+          as long as other threads pick the queue up with acquire load or acquire fence;
+        - shared -> exclusive: once all other threads are done with the queue, and before
+          the first _mut call, call quiesce(). it's an acquire fence, pairing with whatever
+          release operation other threads have signalled their completion with (e.g. a relaxed
+          counter decrement preceded by a release fence), and a check that no operation is
+          left in flight.
+        sharing via thread spawn/join, Mutex, channels or Arc synchronizes on its own, and then
+        both calls are merely documenting the transition. &mut self makes sure there's nobody
+        to race with at the moment of the call
+    */
+    pub fn sync_fence(&mut self) {
+        fence(Ordering::Release);
+    }
+
+    // see sync_fence()
+    pub fn quiesce(&mut self) {
+        fence(Ordering::Acquire);
+        debug_assert_eq!(
+            *self.waiters.get_mut(),
+            0,
+            "MSQueue::quiesce(): some thread is still parked in dequeue_timeout()"
         );
-        true
+    }
+
+    // see enqueue_mut()
+    pub fn dequeue_mut(&mut self) -> Option<T> {
+        let head = *self.head.get_mut();
+        let head_next = unsafe { *(*head).0.next.get_mut() };
+        if head_next.is_null() {
+            return None;
+        }
+        // head_next is the dummy from now on
+        let data = unsafe { (*head_next).0.data.assume_init_read() };
+        *self.head.get_mut() = head_next;
+        self.release();
+        let tail = self.tail.get_mut();
+        if *tail == head {
+            *tail = head_next;
+        }
+        // nobody can hold the old dummy: freeing it right away
+        unsafe { drop(Box::from_raw(head)) };
+        Some(data)
+    }
+
+    /*
+        structural check for tests, run in a single-threaded phase (hence &mut self): head is
+        a (non-null) dummy, and tail is reachable from it, i.e. tail never falls behind head.
+        nodes are remembered along the walk, so a cycle is reported instead of looping forever.
+        returns number of elements (nodes after the dummy)
+    */
+    #[cfg(test)]
+    pub(crate) fn check_invariants(&mut self) -> usize {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        assert!(!head.is_null(), "MSQueue: head is null, dummy node is missing");
+        let mut visited = std::collections::HashSet::new();
+        let mut tail_found = false;
+        let mut current = head;
+        while !current.is_null() {
+            assert!(visited.insert(current), "MSQueue: cycle in next chain at {:p}", current);
+            tail_found |= current == tail;
+            current = unsafe { (*current).0.next.load(Ordering::Relaxed) };
+        }
+        assert!(tail_found, "MSQueue: tail {:p} isn't reachable from head {:p}", tail, head);
+        visited.len() - 1
+    }
+
+    // exact at the moment of head's next load: queue is empty iff dummy head has no successor.
+    // note, that comparing head with tail isn't enough: tail may lag behind, when enqueuer has
+    // linked its node but hasn't yet proceeded tail, so head == tail in a non-empty queue
+    pub fn is_empty(&self, guard: &R::Guard<'_, QueueNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::is_empty()");
+        self.check_head_next(guard, |next| next.load(Ordering::Acquire).is_null())
+    }
+
+    /*
+        waiter's side of the handshake with wake_waiters(): is_empty(), confirmed with an RMW
+        rather than a load. head's next, that is still null, is the last node's next, so it's
+        exactly the word the next enqueue links its node at. SeqCst, to be on the safe side:
+        it's done right before parking only
+    */
+    fn confirm_empty(&self, guard: &R::Guard<'_, QueueNode<T>>) -> bool {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::confirm_empty()");
+        self.check_head_next(guard, |next| {
+            next.compare_exchange(
+                ptr::null_mut(),
+                ptr::null_mut(),
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        })
+    }
+
+    // check of head's next, with head protected and validated
+    fn check_head_next(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        check: impl Fn(&AtomicPtr<QueueNode<T>>) -> bool,
+    ) -> bool {
+        let mut backoff = Backoff::new();
+        loop {
+            let head_ptr = self.head.load(Ordering::Relaxed);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue: found null pointer while protecting head");
+                }
+            };
+            if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
+                continue;
+            }
+            return check(&protected_head.0.next);
+        }
+    }
+
+    /*
+        calls f on the front value without dequeuing it, e.g. for a scheduler, that decides on
+        front's priority whether to dequeue it, and returns what f returns, or None on empty
+        queue. front is protected and validated the same way dequeue does it: head is rechecked
+        after its next is protected, so that front is still in the queue, and isn't retired, at
+        that point. it may be dequeued right after though, and then moved out while f is reading
+        it (see dequeue_if() for a check and dequeue, that are one step). hence T: Copy: moving
+        a Copy value out is just another read, while a dequeuer of, say, a String could free its
+        buffer right under f
+    */
+    pub fn peek_front<U>(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        f: impl FnOnce(&T) -> U,
+    ) -> Option<U>
+    where
+        T: Copy,
+    {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::peek_front()");
+        let mut backoff = Backoff::new();
+        loop {
+            let head_ptr = self.head.load(Ordering::Relaxed);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue::peek_front(): found null pointer while protecting head");
+                }
+            };
+            // head's next must not be read until head is known to be still there
+            if self.head.load(Ordering::Acquire) != protected_head.as_mut_ptr() {
+                continue;
+            }
+            let front = match unsafe { guard.protect(protected_head.0.next.load(Ordering::Acquire)) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => return None,
+            };
+            if self.head.load(Ordering::Acquire) != protected_head.as_mut_ptr() {
+                continue;
+            }
+            return Some(f(unsafe { front.0.data.assume_init_ref() }));
+        }
+    }
+
+    /*
+        best-effort consistent snapshot for monitoring: front value (passed to f) along with the
+        number of elements, both observed while head stayed the same. any dequeue during the
+        traversal restarts it, so f is called exactly once, right after the last successful check.
+        enqueues aren't blocked though: elements enqueued while we're counting may or may not be
+        included. head is kept protected all the way, so that it can't be freed and reused for
+        another node, which would fool the 'head is the same' check.
+        traversal is O(n) and may be restarted over and over under constant dequeues.
+        returns None on empty queue. T: Copy, same as for peek_front(): the front may be dequeued
+        right after the last check, and moved out while f is reading it
+    */
+    pub fn front_and_len<U>(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        f: impl FnOnce(&T) -> U,
+    ) -> Option<(U, usize)>
+    where
+        T: Copy,
+    {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::front_and_len()");
+        let mut backoff = Backoff::new();
+        'snapshot: loop {
+            let head_ptr = self.head.load(Ordering::Relaxed);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue::front_and_len(): found null pointer while protecting head");
+                }
+            };
+            if self.head.load(Ordering::Acquire) != head_ptr {
+                continue;
+            }
+
+            // hand-over-hand: next node is protected before the current one is released.
+            // while head stays the same, nodes after it are neither dequeued nor retired
+            let mut len = 0;
+            let mut current = None;
+            let mut current_next = protected_head.0.next.load(Ordering::Acquire);
+            while !current_next.is_null() {
+                let protected_next = match unsafe { guard.protect(current_next) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
+                    Err(_) => {
+                        backoff.spin();
+                        continue 'snapshot;
+                    }
+                };
+                if self.head.load(Ordering::Acquire) != head_ptr {
+                    continue 'snapshot;
+                }
+                len += 1;
+                current_next = protected_next.0.next.load(Ordering::Acquire);
+                current = Some(protected_next);
+            }
+            drop(current);
+            if len == 0 {
+                return None;
+            }
+
+            let front_ptr = protected_head.0.next.load(Ordering::Acquire);
+            let front = match unsafe { guard.protect(front_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(_) => {
+                    backoff.spin();
+                    continue;
+                }
+            };
+            if self.head.load(Ordering::Acquire) != head_ptr {
+                continue;
+            }
+            return Some((f(unsafe { front.0.data.assume_init_ref() }), len));
+        }
+    }
+
+    /*
+        consistent snapshot of the whole queue, front to back, with every node pinned for as long
+        as the view lives (see FrozenView). same idea as in front_and_len(): while head stays the
+        same, nodes after it are neither dequeued nor retired. walk ends at null next, which is
+        checked against the same head once again, so the snapshot is exactly the queue at that
+        moment. dummy head is pinned as well, so its address can't be reused by an enqueue.
+        needs a free hazard slot per element plus one for head, otherwise gives up with
+        InsufficientSlots. T: Copy, since pinned values may still be dequeued, see FrozenView
+    */
+    pub fn freeze<'g, 'a>(
+        &self,
+        guard: &'g R::Guard<'a, QueueNode<T>>,
+    ) -> Result<PinnedView<'g, 'a, T, QueueNode<T>, R>, InsufficientSlots>
+    where
+        T: Copy,
+    {
+        let mut backoff = Backoff::new();
+        'snapshot: loop {
+            let head_ptr = self.head.load(Ordering::Acquire);
+            let mut pinned = Vec::new();
+            let mut current = head_ptr;
+            while !current.is_null() {
+                let protected = match unsafe { guard.protect(current) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
+                    Err(ProtectionError::NoAvailableIndices) => return Err(InsufficientSlots),
+                    Err(ProtectionError::NullPointer) => {
+                        panic!("MSQueue::freeze(): found null pointer while protecting head");
+                    }
+                };
+                if self.head.load(Ordering::Acquire) != head_ptr {
+                    backoff.spin();
+                    continue 'snapshot;
+                }
+                current = protected.0.next.load(Ordering::Acquire);
+                pinned.push(protected);
+            }
+            if self.head.load(Ordering::Acquire) != head_ptr {
+                backoff.spin();
+                continue;
+            }
+            let items = pinned[1..].iter().map(|node| node.0.data.as_ptr()).collect();
+            return Ok(FrozenView::pinned(pinned, items));
+        }
+    }
+
+    // freeze(), that falls back to cloning values out when there are not enough slots to pin
+    // them all. cloned snapshot is just as consistent, but takes HAZARDS_PER_OP slots at most
+    pub fn freeze_or_clone<'g, 'a>(
+        &self,
+        guard: &'g R::Guard<'a, QueueNode<T>>,
+    ) -> PinnedView<'g, 'a, T, QueueNode<T>, R>
+    where
+        T: Copy,
+    {
+        if let Ok(view) = self.freeze(guard) {
+            return view;
+        }
+        let mut backoff = Backoff::new();
+        'snapshot: loop {
+            let head_ptr = self.head.load(Ordering::Acquire);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("MSQueue::freeze_or_clone(): found null pointer while protecting head");
+                }
+            };
+            if self.head.load(Ordering::Acquire) != head_ptr {
+                continue;
+            }
+            let mut values = Vec::new();
+            let mut current = None;
+            let mut next = protected_head.0.next.load(Ordering::Acquire);
+            while !next.is_null() {
+                let protected_next = match unsafe { guard.protect(next) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
+                    Err(_) => {
+                        backoff.spin();
+                        continue 'snapshot;
+                    }
+                };
+                if self.head.load(Ordering::Acquire) != head_ptr {
+                    continue 'snapshot;
+                }
+                values.push(unsafe { protected_next.0.data.assume_init_read() });
+                next = protected_next.0.next.load(Ordering::Acquire);
+                current = Some(protected_next);
+            }
+            drop(current);
+            if self.head.load(Ordering::Acquire) != head_ptr {
+                continue;
+            }
+            return FrozenView::cloned(values);
+        }
+    }
+
+    /*
+        blocking dequeue with a deadline: a few attempts with spinning, then a few with yielding,
+        and then parking on condvar until enqueue signals or the deadline passes. returns None if
+        the queue stayed empty till the deadline. item, that is already there, is taken with
+        a regular lock-free dequeue, no mutex involved
+    */
+    pub fn dequeue_timeout(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        timeout: Duration,
+    ) -> Option<T> {
+        if let Some(value) = self.dequeue(guard) {
+            return Some(value);
+        }
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Backoff::new();
+        for round in 0..SPIN_ROUNDS + YIELD_ROUNDS {
+            if round < SPIN_ROUNDS {
+                backoff.spin();
+            } else {
+                std::thread::yield_now();
+            }
+            if let Some(value) = self.dequeue(guard) {
+                return Some(value);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+        }
+
+        let mut parking = self.parking.lock().unwrap_or_else(PoisonError::into_inner);
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+        let result = loop {
+            if let Some(value) = self.dequeue(guard) {
+                break Some(value);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break None;
+            }
+            // see wake_waiters(). element may have arrived since dequeue, then take it instead
+            if !self.confirm_empty(guard) {
+                continue;
+            }
+            parking = self
+                .not_empty
+                .wait_timeout(parking, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        };
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        result
     }
 
     // user should register thread to obtain guard
-    pub fn dequeue(&self, guard: &HazardPointerGuard<QueueNode<T>>) -> Option<T> {
+    pub fn dequeue(&self, guard: &R::Guard<'_, QueueNode<T>>) -> Option<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::dequeue()");
+        self.dequeue_with(guard, |_| true, |data| unsafe { data.assume_init_read() })
+            .map(|(value, _)| value)
+    }
+
+    /*
+        conditional dequeue, e.g. for deadline or priority filtering at the front: front element
+        is dequeued only if pred accepts it, otherwise the queue is left untouched. None stands
+        for both empty queue and rejected front. pred is evaluated on the front, protected and
+        validated against head, right before the head CAS, and dequeue linearizes at that CAS
+        (rejection, at the head check before pred): head stayed the same in between, so pred has
+        seen the very element, that gets dequeued. if CAS fails, the new front is protected and
+        evaluated again, so pred may be called several times (hence FnMut).
+        same as with front_and_len(), pred reads the value right in its node, before this thread
+        owns it, so a dequeuer, that wins the race for the same front, may be moving it out at
+        the same moment. hence T: Copy, for which that's just another read
+    */
+    pub fn dequeue_if(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        pred: impl FnMut(&T) -> bool,
+    ) -> Option<T>
+    where
+        T: Copy,
+    {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::dequeue_if()");
+        self.dequeue_with(guard, pred, |data| unsafe { data.assume_init_read() })
+            .map(|(value, _)| value)
+    }
+
+    /*
+        same as dequeue(), but also tells whether this dequeue has drained the queue: true if the
+        new head (i.e. the node we've just dequeued, now a dummy) had no successor right after
+        the successful head CAS. with no concurrent enqueues, exactly one dequeue of the last
+        element reports true. enqueue, that links its node between our CAS and the check, makes
+        us report false, but never the other way round: true always means the queue was empty
+        at a moment right after this dequeue
+    */
+    pub fn dequeue_detect_empty(&self, guard: &R::Guard<'_, QueueNode<T>>) -> Option<(T, bool)> {
+        let _budget =
+            HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::dequeue_detect_empty()");
+        self.dequeue_with(guard, |_| true, |data| unsafe { data.assume_init_read() })
+    }
+
+    /*
+        zero-copy dequeue for read-only consumption of large values: element is dequeued as
+        usual, but instead of being moved out, it's passed by reference to f right in its node,
+        which is still protected at that point. value is dropped in place right after f returns
+        (or while f unwinds), since the node is a dummy now, and dummy's data is never dropped.
+        reference can't escape f, only f's result is returned
+    */
+    pub fn dequeue_ref<U>(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        f: impl FnOnce(&T) -> U,
+    ) -> Option<U> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "MSQueue::dequeue_ref()");
+        // dequeuer owns the value from the head CAS on, see Node
+        struct DropInPlace<'d, T>(&'d mut MaybeUninit<T>);
+
+        impl<T> Drop for DropInPlace<'_, T> {
+            fn drop(&mut self) {
+                unsafe { self.0.assume_init_drop() };
+            }
+        }
+
+        self.dequeue_with(guard, |_| true, |data| {
+            let data = DropInPlace(data);
+            f(unsafe { data.0.assume_init_ref() })
+        })
+        .map(|(result, _)| result)
+    }
+
+    /*
+        common part of dequeues: on success, f gets the element in its node (the new dummy) and
+        exclusive access to it: no other dequeuer touches data of a node, that has become dummy
+        by someone else's CAS. f must move the value out or drop it in place, and leave data
+        uninitialized, as dummy's data is (see Node). front, that pred rejects, is left in place
+        (see dequeue_if())
+    */
+    fn dequeue_with<U>(
+        &self,
+        guard: &R::Guard<'_, QueueNode<T>>,
+        mut pred: impl FnMut(&T) -> bool,
+        f: impl FnOnce(&mut MaybeUninit<T>) -> U,
+    ) -> Option<(U, bool)> {
         let mut backoff = Backoff::new();
 
         let mut head_ptr = std::mem::MaybeUninit::<*mut QueueNode<T>>::uninit();
         let mut head_next = std::mem::MaybeUninit::<*mut QueueNode<T>>::uninit();
 
         loop {
+            yield_point();
             head_ptr.write(self.head.load(Ordering::Relaxed));
-            let mut protected_head = match unsafe { guard.protect(head_ptr.assume_init_read()) } {
+            let protected_head = match unsafe { guard.protect(head_ptr.assume_init_read()) } {
                 Ok(ptr) => {
                     fence(Ordering::Acquire);
                     ptr
@@ -115,8 +1052,14 @@ where
                 // should rather return ! aka never type
                 Err(ProtectionError::NullPointer) => return None,
             };
+            // head may have been dequeued and freed before protection was published, and its
+            // memory reused: its next must not be read until head is known to be still there
+            if self.head.load(Ordering::Acquire) != unsafe { head_ptr.assume_init_read() } {
+                continue;
+            }
 
-            head_next.write((*protected_head).0.next.load(Ordering::Relaxed));
+            yield_point();
+            head_next.write(protected_head.0.next.load(Ordering::Relaxed));
             let mut protected_head_next =
                 match unsafe { guard.protect(head_next.assume_init_read()) } {
                     Ok(ptr) => {
@@ -133,10 +1076,15 @@ where
             if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
                 continue;
             }
+            if !pred(unsafe { protected_head_next.0.data.assume_init_ref() }) {
+                return None;
+            }
 
+            yield_point();
             if self
-                .head
-                .compare_exchange_weak(
+                .cas_strength
+                .compare_exchange(
+                    &self.head,
                     protected_head.as_mut_ptr(),
                     protected_head_next.as_mut_ptr(),
                     Ordering::Release,
@@ -144,16 +1092,20 @@ where
                 )
                 .is_ok()
             {
+                self.release();
+                let drained = protected_head_next.0.next.load(Ordering::Acquire).is_null();
                 // here, we proceed tail only on successful cas
                 // (stolen from "Formal Verification of a Practical Lock-Free Queue Algorithm" by
                 // S.Doherty et al., 2004)
                 loop {
+                    yield_point();
                     let tail = self.tail.load(Ordering::Relaxed);
                     if tail == protected_head.as_mut_ptr() {
                         fence(Ordering::Acquire);
                         if self
-                            .tail
-                            .compare_exchange_weak(
+                            .cas_strength
+                            .compare_exchange(
+                                &self.tail,
                                 protected_head.as_mut_ptr(),
                                 protected_head_next.as_mut_ptr(),
                                 Ordering::Release,
@@ -166,34 +1118,106 @@ where
                     }
                     break;
                 }
+                /*
+                    value is accessed strictly before retire_node(), and while the new head is
+                    still protected by us. the new head isn't retired by anyone until some later
+                    dequeue moves head past it, and only that dequeuer's guard may free it, after
+                    our protection is gone. retire_node() may scan, so moving these two lines
+                    around, or unprotecting the new head early, would let a dequeuer, that races
+                    ahead of us, free the node under our feet
+                */
+                debug_assert!(guard.is_protected(protected_head_next.as_ptr()));
+                let result = f(&mut protected_head_next.0.data);
                 guard.retire_node(protected_head);
-                return Some(std::mem::take(&mut (*protected_head_next).0.data));
+                return Some((result, drained));
+            }
+        }
+    }
+}
+
+/*
+    &mut self: nothing is protected, and no enqueue or dequeue is in flight, so every node still
+    linked is owned by the queue alone: dummy head, and then one node per element. a lagging tail
+    doesn't matter, since the chain of 'next's from head reaches every linked node anyway. nodes
+    behind head were retired by their dequeuers, and are freed by reclamation, not here. dummy's
+    data is uninitialized (see Node), so only the values nobody has dequeued are dropped
+*/
+impl<T, R: Reclaimer> Drop for MSQueue<T, R> {
+    fn drop(&mut self) {
+        let dummy = *self.head.get_mut();
+        let mut current = dummy;
+        while !current.is_null() {
+            let mut node = unsafe { Box::from_raw(current as *mut Node<T>) };
+            if current != dummy {
+                unsafe { node.data.assume_init_drop() };
             }
+            current = *node.next.get_mut();
         }
     }
 }
 
-unsafe impl<T: Default> Sync for MSQueue<T> {}
+// values go in on one thread and come out on another
+unsafe impl<T: Send, R: Reclaimer> Sync for MSQueue<T, R> {}
+unsafe impl<T: Send, R: Reclaimer> Send for MSQueue<T, R> {}
+
+/*
+    whether enqueue proceeds a lagging tail itself (tail is lagging when its next is already set:
+    some enqueuer has linked its node, but hasn't swung the tail yet). helping keeps enqueue
+    lock-free, but under heavy enqueue contention helpers mostly race the owner and each other
+    for the same CAS. whatever the policy, tail always advances eventually: owner swings it
+    right after its successful next CAS, retrying on spurious failures when nobody else may help.
+    dequeue still proceeds tail before moving head past it, since head must never overtake tail.
+    without helping enqueue isn't lock-free anymore: enqueuers wait for a preempted owner
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TailHelpPolicy {
+    // proceed lagging tail at once. classic MS-queue behavior
+    #[default]
+    Always,
+    // give owner SPIN_ROUNDS attempts to proceed its tail first, then help
+    OnlyWhenBlocked,
+    // wait for owner to proceed its tail
+    Never,
+}
+
+impl TailHelpPolicy {
+    fn should_help(self, lagging_attempts: usize) -> bool {
+        match self {
+            TailHelpPolicy::Always => true,
+            TailHelpPolicy::OnlyWhenBlocked => lagging_attempts > SPIN_ROUNDS,
+            TailHelpPolicy::Never => false,
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::MSQueue;
+    use super::{MSQueue, TailHelpPolicy};
+    use crate::collections::cas::CasStrength;
+    use crate::mechanisms::ebr::EbrDomain;
     use crate::mechanisms::hp::HazardPointerArray;
+    use crate::mechanisms::reclaim::{EbrReclaimer, HpReclaimer, Reclaimer};
+    use crate::utils::interleave::replay;
     use std::collections::HashSet;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::LazyLock;
+    use std::ptr;
+    use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, AtomicUsize, Ordering};
+    use std::sync::{Arc, LazyLock};
+    use std::time::{Duration, Instant};
 
-    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(|| HazardPointerArray::new());
+    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(HazardPointerArray::new);
+    static EBR_DOMAIN: EbrDomain = EbrDomain::new();
 
     #[test]
     fn test_basic_operations() {
-        let q = MSQueue::new();
+        let mut q = MSQueue::new();
         let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(q.check_invariants(), 0);
 
         q.enqueue(1, &guard);
         q.enqueue(2, &guard);
         q.enqueue(3, &guard);
         q.enqueue(4, &guard);
+        assert_eq!(q.check_invariants(), 4);
 
         let results = vec![
             q.dequeue(&guard).unwrap(),
@@ -203,6 +1227,148 @@ mod tests {
         ];
 
         assert_eq!(results, vec![1, 2, 3, 4]);
+        assert_eq!(q.check_invariants(), 0);
+    }
+
+    #[test]
+    fn test_is_empty_with_lagging_tail() {
+        let q = MSQueue::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert!(q.is_empty(&guard));
+
+        q.enqueue(1, &guard);
+        assert!(!q.is_empty(&guard));
+        // pretend enqueuer got preempted right after linking its node, but before proceeding tail
+        q.tail.store(q.head.load(Ordering::Relaxed), Ordering::Relaxed);
+        assert_eq!(q.head.load(Ordering::Relaxed), q.tail.load(Ordering::Relaxed));
+        assert!(!q.is_empty(&guard));
+
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert!(q.is_empty(&guard));
+    }
+
+    // unbounded queue counts its elements too, whichever way they come in and out
+    #[test]
+    fn test_approx_len() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = MSQueue::new();
+        assert_eq!(q.approx_len(), 0);
+        q.enqueue(1, &guard);
+        assert!(q.enqueue_batch(2..=4, &guard));
+        q.enqueue_mut(5);
+        assert_eq!(q.approx_len(), 5);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(q.dequeue_if(&guard, |&value| value == 0), None);
+        assert_eq!(q.dequeue_if(&guard, |&value| value == 2), Some(2));
+        assert_eq!(q.dequeue_ref(&guard, |&value| value), Some(3));
+        assert_eq!(q.dequeue_mut(), Some(4));
+        assert_eq!(q.approx_len(), 1);
+        assert!(!q.is_empty(&guard));
+        assert_eq!(q.dequeue_timeout(&guard, Duration::ZERO), Some(5));
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(q.approx_len(), 0);
+        assert!(q.is_empty(&guard));
+        drop(guard);
+
+        // back to exact once the threads are done
+        let q = MSQueue::new();
+        let (q_ref, hp_array_ref) = (&q, &hp_array);
+        let per_thread = 5000;
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    let mut held = 0;
+                    for i in 0..per_thread {
+                        q_ref.enqueue(i, &guard);
+                        held += 1;
+                        if i % 3 != 0 && q_ref.dequeue(&guard).is_some() {
+                            held -= 1;
+                        }
+                        // dequeue lowers the counter only after enqueue has raised it, so it
+                        // never wraps around
+                        assert!(q_ref.approx_len() <= 2 * per_thread);
+                    }
+                    while held > 0 {
+                        if q_ref.dequeue(&guard).is_some() {
+                            held -= 1;
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(q.approx_len(), 0);
+    }
+
+    // setup via fast path, then concurrent use, then teardown via fast path again, with
+    // completion signalled by a relaxed counter only
+    #[test]
+    fn test_sync_fence_and_quiesce() {
+        let hp_array = HazardPointerArray::new();
+        let mut q = MSQueue::new();
+        for i in 0..1000 {
+            q.enqueue_mut(i);
+        }
+        q.sync_fence();
+
+        let shared = AtomicPtr::<MSQueue<usize>>::new(ptr::null_mut());
+        let done = AtomicUsize::new(0);
+        let threads = 2;
+        let (shared_ref, done_ref, hp_array_ref) = (&shared, &done, &hp_array);
+        let dequeued = std::thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|t| {
+                    s.spawn(move || {
+                        let q = loop {
+                            let q = shared_ref.load(Ordering::Acquire);
+                            if !q.is_null() {
+                                break unsafe { &*q };
+                            }
+                            std::hint::spin_loop();
+                        };
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let values = (0..250).filter_map(|_| q.dequeue(&guard)).collect::<Vec<_>>();
+                        for i in 0..100 {
+                            q.enqueue(1000 + t * 100 + i, &guard);
+                        }
+                        fence(Ordering::Release);
+                        done_ref.fetch_add(1, Ordering::Relaxed);
+                        values
+                    })
+                })
+                .collect::<Vec<_>>();
+            shared.store(&mut q, Ordering::Relaxed);
+            while done.load(Ordering::Relaxed) < threads {
+                std::hint::spin_loop();
+            }
+            q.quiesce();
+            let mut values = std::iter::from_fn(|| q.dequeue_mut()).collect::<Vec<_>>();
+            values.extend(handles.into_iter().flat_map(|handle| handle.join().unwrap()));
+            values
+        });
+        assert_eq!(dequeued.len(), 1000 + threads * 100);
+        assert_eq!(
+            dequeued.into_iter().collect::<HashSet<_>>(),
+            (0..1000 + threads * 100).collect()
+        );
+    }
+
+    #[test]
+    fn test_mut_fast_path() {
+        let mut q = MSQueue::new();
+        for i in 0..100 {
+            q.enqueue_mut(i);
+        }
+        assert_eq!(q.dequeue_mut(), Some(0));
+
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        q.enqueue(100, &guard);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        drop(guard);
+
+        let values = std::iter::from_fn(|| q.dequeue_mut()).collect::<Vec<_>>();
+        assert_eq!(values, (2..=100).collect::<Vec<_>>());
     }
 
     #[derive(Default)]
@@ -211,18 +1377,28 @@ mod tests {
     }
 
     static NEXT_VALUE: AtomicUsize = AtomicUsize::new(0);
+    static NEXT_EBR_VALUE: AtomicUsize = AtomicUsize::new(0);
 
     impl TrackableValue {
-        fn new() -> Self {
+        fn new(next_value: &AtomicUsize) -> Self {
             TrackableValue {
-                value: NEXT_VALUE.fetch_add(1, Ordering::Relaxed),
+                value: next_value.fetch_add(1, Ordering::Relaxed),
             }
         }
     }
 
     #[test]
     fn test_concurrent() {
-        let q = MSQueue::new();
+        run_concurrent::<HpReclaimer>(&HP_ARRAY, &NEXT_VALUE);
+    }
+
+    #[test]
+    fn test_concurrent_ebr() {
+        run_concurrent::<EbrReclaimer>(&EBR_DOMAIN, &NEXT_EBR_VALUE);
+    }
+
+    fn run_concurrent<R: Reclaimer>(domain: &R::Domain, next_value: &AtomicUsize) {
+        let q = MSQueue::<_, R>::with_reclaimer();
         let q_ref = &q;
 
         let thread_count = 8;
@@ -236,11 +1412,11 @@ mod tests {
             std::thread::scope(|scope| {
                 for _ in 0..thread_count {
                     scope.spawn(|| {
-                        let guard = HP_ARRAY.register_thread().ok().unwrap();
+                        let guard = R::register_thread(domain).ok().unwrap();
 
                         // first batch: enqueue half and eventual dequeue half
                         for _ in 0..per_thread_ops / 2 {
-                            q_ref.enqueue(TrackableValue::new(), &guard);
+                            q_ref.enqueue(TrackableValue::new(next_value), &guard);
                         }
 
                         for _ in 0..per_thread_ops / 2 {
@@ -256,7 +1432,7 @@ mod tests {
 
                         // second batch
                         for _ in 0..per_thread_ops / 2 {
-                            q_ref.enqueue(TrackableValue::new(), &guard);
+                            q_ref.enqueue(TrackableValue::new(next_value), &guard);
                         }
 
                         for _ in 0..per_thread_ops / 2 {
@@ -275,7 +1451,707 @@ mod tests {
                 collected_values.into_inner().unwrap().into_iter().collect();
             assert_eq!(actual_values, expected_values);
 
-            NEXT_VALUE.store(0, Ordering::Relaxed);
+            next_value.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // declared HAZARDS_PER_OP is exactly the peak of the most demanding operation
+    #[test]
+    fn test_hazards_per_op() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        let hazards = MSQueue::<usize>::HAZARDS_PER_OP;
+
+        guard.reset_peak_protected();
+        q.enqueue(1, &guard);
+        assert!(guard.peak_protected() <= hazards);
+
+        guard.reset_peak_protected();
+        assert!(!q.is_empty(&guard));
+        assert!(guard.peak_protected() <= hazards);
+
+        q.enqueue(2, &guard);
+        guard.reset_peak_protected();
+        assert_eq!(q.front_and_len(&guard, |front| *front), Some((1, 2)));
+        assert_eq!(guard.peak_protected(), hazards);
+
+        guard.reset_peak_protected();
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert!(guard.peak_protected() <= hazards);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    #[test]
+    fn test_front_and_len() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        assert_eq!(q.front_and_len(&guard, |_: &(usize, char)| unreachable!()), None::<((), _)>);
+
+        for i in 0..5 {
+            q.enqueue((i, 'a'), &guard);
+        }
+        // closure sees the front element, while the length covers the whole queue
+        for expected_len in (1..=5).rev() {
+            let front = (5 - expected_len, 'a');
+            assert_eq!(q.front_and_len(&guard, |&front| front), Some((front, expected_len)));
+            assert_eq!(q.dequeue(&guard), Some(front));
+        }
+        assert_eq!(q.front_and_len(&guard, |&(front, _)| front), None);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    #[test]
+    fn test_dequeue_ref() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::<Vec<u64>>::new();
+        assert_eq!(q.dequeue_ref(&guard, |value| value.len()), None);
+
+        for i in 0..10u64 {
+            q.enqueue((0..4096).map(|j| i * j).collect(), &guard);
+        }
+        for i in 0..10u64 {
+            // value right in the front node
+            let front_addr = unsafe {
+                let front = (*q.head.load(Ordering::Relaxed)).0.next.load(Ordering::Relaxed);
+                (*front).0.data.as_ptr() as usize
+            };
+            let (sum, addr) = q
+                .dequeue_ref(&guard, |value| {
+                    (value.iter().sum::<u64>(), value as *const Vec<u64> as usize)
+                })
+                .unwrap();
+            assert_eq!(sum, i * (0..4096).sum::<u64>());
+            // value wasn't moved or copied out of its node
+            assert_eq!(addr, front_addr);
+        }
+        assert_eq!(q.dequeue_ref(&guard, |value| value.len()), None);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    #[test]
+    fn test_peek_front() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        assert_eq!(q.peek_front(&guard, |_: &(u8, &str)| unreachable!()), None::<()>);
+
+        q.enqueue((2, "low"), &guard);
+        q.enqueue((9, "high"), &guard);
+        // scheduler looks at the front, and dequeues it only if it's urgent enough
+        for _ in 0..2 {
+            assert_eq!(q.peek_front(&guard, |&(priority, _)| priority > 5), Some(false));
+        }
+        assert_eq!(q.dequeue(&guard), Some((2, "low")));
+        assert_eq!(q.peek_front(&guard, |&(priority, _)| priority > 5), Some(true));
+        assert_eq!(q.peek_front(&guard, |&(_, name)| name), Some("high"));
+        assert_eq!(guard.protected_count(), 0);
+
+        // lagging tail doesn't hide the front
+        assert_eq!(q.dequeue(&guard), Some((9, "high")));
+        q.enqueue((1, "lagging"), &guard);
+        q.tail.store(q.head.load(Ordering::Relaxed), Ordering::Relaxed);
+        assert_eq!(q.peek_front(&guard, |&(_, name)| name), Some("lagging"));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = MSQueue::bounded(2);
+        assert_eq!(q.capacity(), Some(2));
+        assert!(q.try_enqueue(1, &guard).is_ok());
+        assert!(q.enqueue(2, &guard));
+        assert_eq!(q.try_enqueue(3, &guard), Err(3));
+        assert!(!q.enqueue(3, &guard));
+        // failed reservations are rolled back
+        assert_eq!(q.approx_len(), 2);
+        assert_eq!(q.check_invariants(), 2);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(q.try_enqueue(3, &guard), Ok(()));
+        assert_eq!(q.dequeue_mut(), Some(2));
+        assert_eq!(q.dequeue(&guard), Some(3));
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(q.approx_len(), 0);
+        drop(guard);
+
+        // producers are refused once the queue is full. live count (enqueued minus dequeued, as
+        // seen by the threads) never exceeds capacity by more than the number of threads
+        let capacity = 8;
+        let (producers, per_producer) = (2, 3000);
+        let q = MSQueue::bounded(capacity);
+        let live = AtomicIsize::new(0);
+        let (q_ref, hp_array_ref, live_ref) = (&q, &hp_array, &live);
+        std::thread::scope(|s| {
+            for p in 0..producers {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    for i in 0..per_producer {
+                        let mut value = p * per_producer + i;
+                        while let Err(refused) = q_ref.try_enqueue(value, &guard) {
+                            value = refused;
+                            std::thread::yield_now();
+                        }
+                        let now_live = live_ref.fetch_add(1, Ordering::Relaxed) + 1;
+                        assert!(now_live <= (capacity + producers + 1) as isize, "{}", now_live);
+                        // len includes in-flight reservations, at most one per producer
+                        assert!(q_ref.approx_len() <= capacity + producers);
+                    }
+                });
+            }
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                let mut values = HashSet::new();
+                while values.len() < producers * per_producer {
+                    match q_ref.dequeue(&guard) {
+                        Some(value) => {
+                            assert!(values.insert(value));
+                            live_ref.fetch_sub(1, Ordering::Relaxed);
+                        }
+                        None => std::thread::yield_now(),
+                    }
+                }
+            });
+        });
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+        assert_eq!(q.approx_len(), 0);
+    }
+
+    // batch comes out in order and in one piece, whatever else is enqueued concurrently
+    #[test]
+    fn test_enqueue_batch() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = MSQueue::new();
+        assert!(q.enqueue_batch(std::iter::empty(), &guard));
+        assert!(q.is_empty(&guard));
+        q.enqueue(0, &guard);
+        assert!(q.enqueue_batch(1..=3, &guard));
+        assert!(q.enqueue_batch(Some(4), &guard));
+        assert_eq!(q.check_invariants(), 5);
+        // tail is proceeded over the whole batch, to its last node
+        let tail = q.tail.load(Ordering::Relaxed);
+        assert!(unsafe { (*tail).0.next.load(Ordering::Relaxed) }.is_null());
+        assert_eq!(guard.protected_count(), 0);
+        for i in 0..5 {
+            assert_eq!(q.dequeue(&guard), Some(i));
+        }
+        assert_eq!(q.dequeue(&guard), None);
+        drop(guard);
+
+        // bounded queue takes all of the batch or none of it, and drops what it refuses
+        let guard = hp_array.register_thread().ok().unwrap();
+        let counter = Arc::new(());
+        let mut q = MSQueue::bounded(3);
+        assert!(q.enqueue_batch([counter.clone(), counter.clone()], &guard));
+        assert!(!q.enqueue_batch([counter.clone(), counter.clone()], &guard));
+        assert_eq!(Arc::strong_count(&counter), 3);
+        assert_eq!(q.approx_len(), 2);
+        assert!(q.enqueue_batch(Some(counter.clone()), &guard));
+        assert_eq!(q.check_invariants(), 3);
+        drop(q);
+        assert_eq!(Arc::strong_count(&counter), 1);
+        drop(guard);
+
+        // batches of (producer, batch, index) against single enqueues of (2, i, 0). without
+        // helping, dequeuer proceeds lagging tail along the batches, and it still must end up
+        // at the last node
+        for tail_help in [TailHelpPolicy::Always, TailHelpPolicy::Never] {
+            let (batches, batch_len, singles) = (300, 7, 1000);
+            let mut q = MSQueue::new().with_tail_help(tail_help);
+            let (q_ref, hp_array_ref) = (&q, &hp_array);
+            let guard = hp_array.register_thread().ok().unwrap();
+            std::thread::scope(|s| {
+                for p in 0..2 {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for b in 0..batches {
+                            assert!(q_ref.enqueue_batch((0..batch_len).map(|i| (p, b, i)), &guard));
+                        }
+                    });
+                }
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    for i in 0..singles {
+                        q_ref.enqueue((2, i, 0), &guard);
+                    }
+                });
+                let mut next_batch = [0, 0];
+                let mut next_single = 0;
+                let mut received = 0;
+                while received < 2 * batches * batch_len + singles {
+                    let Some((p, b, i)) = q_ref.dequeue(&guard) else {
+                        std::thread::yield_now();
+                        continue;
+                    };
+                    received += 1;
+                    if p == 2 {
+                        assert_eq!(b, next_single);
+                        next_single += 1;
+                        continue;
+                    }
+                    assert_eq!((b, i), (next_batch[p], 0));
+                    next_batch[p] += 1;
+                    // rest of the batch is already there, right behind its first item
+                    for j in 1..batch_len {
+                        assert_eq!(q_ref.dequeue(&guard), Some((p, b, j)));
+                        received += 1;
+                    }
+                }
+            });
+            assert!(q.is_empty(&guard));
+            assert_eq!(q.check_invariants(), 0);
+            assert_eq!(q.tail.load(Ordering::Relaxed), q.head.load(Ordering::Relaxed));
+        }
+    }
+
+    // no Default for trait objects: dummy carries no value at all. every value is dropped exactly
+    // once, by whoever has it: the caller, dequeue_ref() right after f, or the queue on drop
+    #[test]
+    fn test_trait_objects() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let counter = Arc::new(());
+        let q: MSQueue<Box<dyn Fn() -> usize + Send>> = MSQueue::new();
+        for i in 0..4 {
+            let counter = counter.clone();
+            q.enqueue(Box::new(move || i + Arc::strong_count(&counter)), &guard);
+        }
+        assert_eq!(Arc::strong_count(&counter), 5);
+
+        assert_eq!(q.dequeue(&guard).unwrap()(), 5);
+        assert_eq!(Arc::strong_count(&counter), 4);
+        assert_eq!(q.dequeue_ref(&guard, |f| f()), Some(5));
+        assert_eq!(Arc::strong_count(&counter), 3);
+        assert_eq!(q.approx_len(), 2);
+        // retired dummies drop nothing
+        while guard.try_reclaim() > 0 {}
+        assert_eq!(Arc::strong_count(&counter), 3);
+        drop(q);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_dequeue_if() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        assert_eq!(q.dequeue_if(&guard, |_| true), None);
+        q.enqueue(1, &guard);
+        q.enqueue(2, &guard);
+        // rejected front stays, and blocks the ones behind it
+        assert_eq!(q.dequeue_if(&guard, |&value| value == 2), None);
+        assert_eq!(q.front_and_len(&guard, |&front| front), Some((1, 2)));
+        assert_eq!(q.dequeue_if(&guard, |&value| value == 1), Some(1));
+        assert_eq!(q.dequeue_if(&guard, |&value| value == 2), Some(2));
+        assert_eq!(guard.protected_count(), 0);
+        drop(guard);
+
+        // every dequeuer takes its own residue only, in order, while the producer goes on
+        let dequeuers = 3;
+        let total = 6000;
+        let q_ref = &q;
+        let hp_array_ref = &hp_array;
+        let taken = std::thread::scope(|s| {
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                for i in 0..total {
+                    q_ref.enqueue(i, &guard);
+                }
+            });
+            let handles = (0..dequeuers)
+                .map(|d| {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let mut values = Vec::new();
+                        while values.len() < total / dequeuers {
+                            match q_ref.dequeue_if(&guard, |&value| value % dequeuers == d) {
+                                Some(value) => values.push(value),
+                                None => std::thread::yield_now(),
+                            }
+                        }
+                        values
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for (d, values) in taken.into_iter().enumerate() {
+            assert_eq!(values, (d..total).step_by(dequeuers).collect::<Vec<_>>());
+        }
+        let guard = hp_array.register_thread().ok().unwrap();
+        assert!(q.is_empty(&guard));
+    }
+
+    // all of guard's slots held by the caller: enqueue would spin forever, try_enqueue gives up
+    #[test]
+    fn test_try_enqueue_on_exhausted_guard() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        assert!(q.try_enqueue(vec![1], &guard).is_ok());
+
+        let dummy = q.head.load(Ordering::Relaxed);
+        let mut pinned = Vec::new();
+        while let Ok(protected) = unsafe { guard.protect(dummy) } {
+            pinned.push(protected);
+        }
+        match q.try_enqueue(vec![2], &guard) {
+            Err(value) => assert_eq!(value, vec![2]),
+            Ok(()) => panic!("try_enqueue() succeeded with no free hazard slots"),
+        }
+
+        drop(pinned);
+        assert!(q.try_enqueue(vec![3], &guard).is_ok());
+        assert_eq!(q.dequeue(&guard), Some(vec![1]));
+        assert_eq!(q.dequeue(&guard), Some(vec![3]));
+        assert_eq!(q.dequeue(&guard), None);
+    }
+
+    // strong CAS is a drop-in substitute for the weak one, and vice versa
+    #[test]
+    fn test_cas_strength() {
+        let hp_array = HazardPointerArray::new();
+        let producers = 2;
+        let per_producer = 5000;
+        for cas_strength in [CasStrength::Weak, CasStrength::Strong] {
+            let mut q = MSQueue::new().with_cas_strength(cas_strength);
+            let dequeued = AtomicUsize::new(0);
+            let (q_ref, hp_array_ref, dequeued_ref) = (&q, &hp_array, &dequeued);
+            let values = std::thread::scope(|s| {
+                for p in 0..producers {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..per_producer {
+                            q_ref.enqueue(p * per_producer + i, &guard);
+                        }
+                    });
+                }
+                let consumers = (0..2)
+                    .map(|_| {
+                        s.spawn(move || {
+                            let guard = hp_array_ref.register_thread().ok().unwrap();
+                            let mut values = Vec::new();
+                            while dequeued_ref.load(Ordering::Relaxed) < producers * per_producer {
+                                if let Some(value) = q_ref.dequeue(&guard) {
+                                    dequeued_ref.fetch_add(1, Ordering::Relaxed);
+                                    values.push(value);
+                                }
+                            }
+                            values
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                consumers
+                    .into_iter()
+                    .flat_map(|consumer| consumer.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            assert_eq!(values.len(), producers * per_producer);
+            assert_eq!(
+                values.into_iter().collect::<HashSet<_>>(),
+                (0..producers * per_producer).collect()
+            );
+            assert_eq!(q.check_invariants(), 0);
+        }
+    }
+
+    #[test]
+    fn test_dequeue_timeout_expires() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::<usize>::new();
+
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert_eq!(q.dequeue_timeout(&guard, timeout), None);
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(q.waiters.load(Ordering::Relaxed), 0);
+
+        // available item is returned right away
+        q.enqueue(1, &guard);
+        assert_eq!(q.dequeue_timeout(&guard, Duration::ZERO), Some(1));
+    }
+
+    #[test]
+    fn test_dequeue_timeout_wakeup() {
+        let hp_array = HazardPointerArray::new();
+        let q = MSQueue::<usize>::new();
+        let (q_ref, hp_array_ref) = (&q, &hp_array);
+
+        let (value, elapsed) = std::thread::scope(|s| {
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                // long enough for the consumer to get parked
+                std::thread::sleep(Duration::from_millis(50));
+                q_ref.enqueue(7, &guard);
+            });
+            let guard = hp_array.register_thread().ok().unwrap();
+            let start = Instant::now();
+            let value = q.dequeue_timeout(&guard, Duration::from_secs(10));
+            (value, start.elapsed())
+        });
+        assert_eq!(value, Some(7));
+        // woken up by enqueue, not by the deadline
+        assert!(elapsed < Duration::from_secs(5));
+        assert_eq!(q.waiters.load(Ordering::Relaxed), 0);
+    }
+
+    // one producer and one consumer, replayed under deterministic interleavings
+    fn replay_enqueue_dequeue(seed: u64) -> (Vec<usize>, Option<usize>, Option<usize>) {
+        let hp_array = HazardPointerArray::new();
+        let q = MSQueue::new();
+        let (q_ref, hp_array_ref) = (&q, &hp_array);
+        let dequeued = std::sync::Mutex::new(None);
+        let dequeued_ref = &dequeued;
+
+        let trace = replay(
+            seed,
+            vec![
+                Box::new(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    q_ref.enqueue(1, &guard);
+                }),
+                Box::new(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    *dequeued_ref.lock().unwrap() = q_ref.dequeue(&guard);
+                }),
+            ],
+        );
+        let guard = hp_array.register_thread().ok().unwrap();
+        let remaining = q.dequeue(&guard);
+        (trace, dequeued.into_inner().unwrap(), remaining)
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        for seed in 0..32 {
+            assert_eq!(replay_enqueue_dequeue(seed), replay_enqueue_dequeue(seed));
+        }
+    }
+
+    // edge case: dequeue, that runs concurrently with enqueue, may linearize before it and
+    // report empty queue. once found, such interleaving is reproduced by its seed every time
+    #[test]
+    fn test_replay_empty_dequeue() {
+        let outcomes = (0..64)
+            .map(|seed| (seed, replay_enqueue_dequeue(seed)))
+            .collect::<Vec<_>>();
+        // value is never lost or duplicated
+        for (_, (_, dequeued, remaining)) in outcomes.iter() {
+            assert!(dequeued.is_some() ^ remaining.is_some());
+        }
+
+        let (empty_seed, _) = outcomes
+            .iter()
+            .find(|(_, (_, dequeued, _))| dequeued.is_none())
+            .expect("no interleaving with dequeue before enqueue");
+        assert!(outcomes.iter().any(|(_, (_, dequeued, _))| *dequeued == Some(1)));
+        for _ in 0..8 {
+            let (_, dequeued, remaining) = replay_enqueue_dequeue(*empty_seed);
+            assert_eq!((dequeued, remaining), (None, Some(1)));
+        }
+    }
+
+    /*
+        reclamation scenarios below are deterministic and take a handful of operations each, so
+        that they're cheap enough for Miri (cargo +nightly miri test reclaim), which stress tests
+        are far too slow for. Miri catches the use-after-free, that a wrong retirement leads to,
+        on the very first run, while stress tests only hit it by chance. remaining nodes are
+        freed on drop, so Miri's leak check holds as well, see test_drop_without_dequeue
+    */
+
+    // empty dequeue protects the dummy and finds no next: it must neither retire the dummy, nor
+    // leave it protected. once a dequeue has made the front node the new dummy and retired the
+    // old one, the old one is freed, while the new one stays linked and usable
+    #[test]
+    fn test_reclaim_empty_dequeue() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = MSQueue::new();
+        let value = Arc::new(());
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(guard.protected_count(), 0);
+        assert_eq!(guard.try_reclaim(), 0);
+
+        q.enqueue(value.clone(), &guard);
+        assert!(q.dequeue(&guard).is_some());
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(guard.protected_count(), 0);
+        // the old dummy, and nothing else
+        assert_eq!(guard.try_reclaim(), 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        q.enqueue(value.clone(), &guard);
+        assert_eq!(q.check_invariants(), 1);
+        assert!(q.dequeue(&guard).is_some());
+        assert_eq!(guard.try_reclaim(), 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    // enqueuer got preempted after linking its node, but before moving tail, so tail is still
+    // at the dummy. dequeue of that very node retires the dummy, so it must move tail past it
+    // first, or the next enqueue would link its node into freed memory. same lag seen by the
+    // next enqueuer instead: it helps tail along, before linking its own node
+    #[test]
+    fn test_reclaim_lagging_tail() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = MSQueue::new();
+
+        q.enqueue(1, &guard);
+        let dummy = q.head.load(Ordering::Relaxed);
+        q.tail.store(dummy, Ordering::Relaxed);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_ne!(q.tail.load(Ordering::Relaxed), dummy);
+        assert_eq!(guard.try_reclaim(), 1);
+        q.enqueue(2, &guard);
+        assert_eq!(q.dequeue(&guard), Some(2));
+
+        q.enqueue(3, &guard);
+        q.tail.store(q.head.load(Ordering::Relaxed), Ordering::Relaxed);
+        q.enqueue(4, &guard);
+        assert_eq!(q.check_invariants(), 2);
+        assert_eq!(q.dequeue(&guard), Some(3));
+        assert_eq!(q.dequeue(&guard), Some(4));
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(guard.try_reclaim(), 3);
+        assert_eq!(q.check_invariants(), 0);
+    }
+
+    // values nobody has dequeued go with the queue, and so does the dummy, lagging tail or not.
+    // nodes already retired by dequeues stay with the guard, and aren't freed twice
+    #[test]
+    fn test_drop_without_dequeue() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let value = Arc::new(());
+        let q = MSQueue::new();
+        for _ in 0..3 {
+            q.enqueue(value.clone(), &guard);
+        }
+        assert!(q.dequeue(&guard).is_some());
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(q);
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(guard.try_reclaim(), 1);
+
+        let q = MSQueue::new();
+        q.enqueue(value.clone(), &guard);
+        q.tail.store(q.head.load(Ordering::Relaxed), Ordering::Relaxed);
+        drop(q);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_dequeue_detect_empty() {
+        let hp_array = HazardPointerArray::new();
+        let q = MSQueue::new();
+        let count = 1000;
+        let drained = AtomicUsize::new(0);
+        let dequeued = AtomicUsize::new(0);
+        {
+            let guard = hp_array.register_thread().ok().unwrap();
+            for i in 0..count {
+                q.enqueue(i, &guard);
+            }
+        }
+
+        let (q_ref, hp_array_ref) = (&q, &hp_array);
+        let (drained_ref, dequeued_ref) = (&drained, &dequeued);
+        std::thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    while let Some((value, is_drained)) = q_ref.dequeue_detect_empty(&guard) {
+                        dequeued_ref.fetch_add(1, Ordering::Relaxed);
+                        if is_drained {
+                            assert_eq!(value, count - 1);
+                            drained_ref.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(dequeued.load(Ordering::Relaxed), count);
+        assert_eq!(drained.load(Ordering::Relaxed), 1);
+
+        let guard = hp_array.register_thread().ok().unwrap();
+        q.enqueue(1, &guard);
+        assert_eq!(q.dequeue_detect_empty(&guard), Some((1, true)));
+        assert_eq!(q.dequeue_detect_empty(&guard), None);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = MSQueue::new();
+        assert!(q.freeze(&guard).ok().unwrap().is_empty());
+        for i in 0..4 {
+            q.enqueue(i, &guard);
+        }
+
+        let view = q.freeze(&guard).ok().unwrap();
+        assert!(view.is_pinned());
+        // elements and dummy head
+        assert_eq!(guard.protected_count(), 5);
+        q.enqueue(4, &guard);
+        assert_eq!(view.iter().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        drop(view);
+
+        for i in 5..100 {
+            q.enqueue(i, &guard);
+        }
+        assert!(q.freeze(&guard).is_err());
+        let view = q.freeze_or_clone(&guard);
+        assert!(!view.is_pinned());
+        assert_eq!(view.len(), 100);
+        assert!(view.iter().copied().eq(0..100));
+        drop(view);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    // FIFO holds whoever proceeds the tail: single consumer sees every producer's values in order
+    #[test]
+    fn test_tail_help_policy() {
+        let hp_array = HazardPointerArray::new();
+        let producers = 3;
+        let per_producer = 3000;
+        let policies = [
+            TailHelpPolicy::Always,
+            TailHelpPolicy::OnlyWhenBlocked,
+            TailHelpPolicy::Never,
+        ];
+        for tail_help in policies {
+            let mut q = MSQueue::new().with_tail_help(tail_help);
+            let (q_ref, hp_array_ref) = (&q, &hp_array);
+            std::thread::scope(|s| {
+                for p in 0..producers {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..per_producer {
+                            q_ref.enqueue((p, i), &guard);
+                        }
+                    });
+                }
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                let mut next_expected = vec![0; producers];
+                let mut dequeued = 0;
+                while dequeued < producers * per_producer {
+                    if let Some((p, i)) = q_ref.dequeue(&guard) {
+                        assert_eq!(i, next_expected[p], "{:?}: producer {}", tail_help, p);
+                        next_expected[p] += 1;
+                        dequeued += 1;
+                    }
+                }
+            });
+            assert_eq!(q.check_invariants(), 0);
         }
     }
 }
@@ -1,8 +1,11 @@
-use crate::mechanisms::hp::*;
-use std::sync::atomic::{fence, AtomicPtr, Ordering};
+use crate::mechanisms::hp::ProtectionError;
+use crate::mechanisms::reclaim::{HazardBudget, HpReclaimer, Protected, Reclaimer, ReclaimerGuard};
+use std::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+use std::marker::PhantomData;
 use std::ptr;
 use std::default::Default;
 use crate::utils::backoff::Backoff;
+use crate::utils::interleave::yield_point;
 
 /*
     here, MSQueue is altered to solve a problem of having two CAS operations in enqueue() method:
@@ -13,9 +16,28 @@ use crate::utils::backoff::Backoff;
     latter CAS and substitute it with a regular store operation.
 */
 
-struct OMSQueue<T: Default> {
+/// ```
+/// use lock_freedom::collections::OMSQueue;
+/// use lock_freedom::mechanisms::hp::HazardPointerArray;
+///
+/// let array = HazardPointerArray::new();
+/// let guard = array.register_thread().ok().unwrap();
+/// let queue = OMSQueue::new();
+/// queue.enqueue(1, &guard);
+/// queue.enqueue(2, &guard);
+/// // first in, first out
+/// assert_eq!(queue.dequeue(&guard), Some(1));
+/// assert_eq!(queue.dequeue(&guard), Some(2));
+/// assert_eq!(queue.dequeue(&guard), None);
+/// ```
+pub struct OMSQueue<T: Default, R: Reclaimer = HpReclaimer> {
     head: AtomicPtr<QueueNode<T>>,
     tail: AtomicPtr<QueueNode<T>>,
+    // see bounded(). None for unbounded queue
+    capacity: Option<usize>,
+    // number of elements, including enqueues in flight, see approx_len()
+    len: AtomicUsize,
+    _reclaimer: PhantomData<R>,
 }
 
 struct Node<T: Default> {
@@ -27,8 +49,71 @@ struct Node<T: Default> {
 #[repr(transparent)]
 pub struct QueueNode<T: Default>(Node<T>);
 
-impl<T: Default> OMSQueue<T> {
-    pub fn new() -> OMSQueue<T> {
+/*
+    owns a freshly allocated node until enqueue links it into the queue. on any other way out of
+    enqueue (giving up, or a panic) the node is freed right away with Box::from_raw: nobody but
+    the enqueuing thread has ever seen it, so there's nothing to protect it from, and no point
+    in retiring it
+*/
+struct UnpublishedNode<T: Default>(*mut QueueNode<T>);
+
+impl<T: Default> UnpublishedNode<T> {
+    fn new(data: T) -> Self {
+        UnpublishedNode(Box::into_raw(Box::new(Node {
+            data,
+            next: AtomicPtr::new(ptr::null_mut()),
+            prev: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut QueueNode<T>)
+    }
+
+    // node is reachable from tail now, and is owned by the queue
+    fn publish(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<T: Default> Drop for UnpublishedNode<T> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.0)) };
+    }
+}
+
+impl<T: Default + Send> Default for OMSQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Default + Send> OMSQueue<T> {
+    pub fn new() -> Self {
+        Self::with_reclaimer()
+    }
+
+    /*
+        queue of at most capacity elements, for bounded-memory pipelines: try_enqueue() gives
+        the value back once the queue is full (enqueue() returns false, dropping it). every
+        enqueue reserves a place in len first: fetch_add, rolled back with fetch_sub, if it went
+        over capacity. so the bound is approximate under heavy concurrency, though only ever on
+        the safe side: len includes in-flight reservations (about to be rolled back, or not yet
+        linked enqueues) and elements, that are dequeued, but not yet accounted for. enqueue may
+        be refused with fewer than capacity elements actually in the queue then, but the queue
+        never holds more than capacity.
+        e.g. OMSQueue::<T, EbrReclaimer>::with_reclaimer().with_capacity(cap)
+    */
+    pub fn bounded(capacity: usize) -> Self {
+        Self::new().with_capacity(capacity)
+    }
+}
+
+impl<T: Default + Send, R: Reclaimer> OMSQueue<T, R> {
+    // max number of pointers a single operation protects at once (dequeue: head, tail and
+    // head's prev; fix() also keeps three: head, current node and its next; enqueue: new node,
+    // tail and, helping dequeue_back(), tail's predecessor).
+    // hazard pointer guards used with this queue need at least that many free slots
+    pub const HAZARDS_PER_OP: usize = 3;
+
+    // e.g. OMSQueue::<T, EbrReclaimer>::with_reclaimer()
+    pub fn with_reclaimer() -> Self {
         let dummy_node = Box::into_raw(Box::new(Node {
             data: T::default(),
             next: AtomicPtr::new(ptr::null_mut()),
@@ -37,6 +122,68 @@ impl<T: Default> OMSQueue<T> {
         OMSQueue {
             head: AtomicPtr::new(dummy_node),
             tail: AtomicPtr::new(dummy_node),
+            capacity: None,
+            len: AtomicUsize::new(0),
+            _reclaimer: PhantomData,
+        }
+    }
+
+    // see bounded()
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /*
+        number of elements, kept in a counter rather than counted by a walk, e.g. for monitoring.
+        exact once the queue is quiescent. under races it's a moment's view of the counter, that
+        enqueue bumps before it links its node, and dequeue lowers only after its head CAS: so it
+        may include elements about to be enqueued (or refused by bounded queue, see bounded()),
+        and ones already dequeued, but it never misses an element, that is in the queue.
+        counter is shared by all producers and consumers, the same as MSQueue's: see the cost of
+        keeping it in MSQueue::approx_len()
+    */
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    // reserves a place for one more element, see bounded(). unbounded queue never refuses, but
+    // counts it all the same, see approx_len()
+    fn reserve(&self) -> bool {
+        let len = self.len.fetch_add(1, Ordering::Relaxed);
+        match self.capacity {
+            Some(capacity) if len >= capacity => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    // gives back the place of a dequeued element
+    fn release(&self) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /*
+        no element is left in the queue, at the moment of the tail load. unlike MSQueue::is_empty(),
+        it needs neither a guard, nor a look into any node: tail never lags here, so the queue is
+        empty exactly when head and tail point to the same dummy. head is rechecked after tail,
+        since nothing is protected here: if it hasn't moved, tail was compared with the current
+        dummy, and not with some freed (and maybe reused) one. marks of dequeue_back() in
+        progress don't matter: the node, it pops, is still there until it's done
+    */
+    pub fn is_empty(&self) -> bool {
+        loop {
+            let head = self.head.load(Ordering::SeqCst);
+            let tail = self.tail.load(Ordering::SeqCst);
+            if self.head.load(Ordering::SeqCst) == head {
+                return unmarked(head) == unmarked(tail);
+            }
         }
     }
     
@@ -50,17 +197,33 @@ impl<T: Default> OMSQueue<T> {
     method
      */
     
-    pub fn enqueue(&self, data: T, guard: &HazardPointerGuard<QueueNode<T>>) -> bool {
-        let new_node = Box::into_raw(Box::new(Node {
-            data,
-            next: AtomicPtr::new(ptr::null_mut()),
-            prev: AtomicPtr::new(ptr::null_mut()),
-        })) as *mut QueueNode<T>;
+    // false if bounded queue is full, value is dropped then (see try_enqueue())
+    pub fn enqueue(&self, data: T, guard: &R::Guard<'_, QueueNode<T>>) -> bool {
+        self.try_enqueue(data, guard).is_ok()
+    }
+
+    // gives value back if bounded queue is full, see bounded()
+    pub fn try_enqueue(&self, data: T, guard: &R::Guard<'_, QueueNode<T>>) -> Result<(), T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "OMSQueue::enqueue()");
+        if !self.reserve() {
+            return Err(data);
+        }
+        let unpublished = UnpublishedNode::new(data);
+        let new_node = unpublished.0;
 
         let mut backoff = Backoff::new();
+        // once published, new node may be dequeued from the back (and retired) any moment, while
+        // we're still about to store it into older tail's prev
+        let protected_new_node = loop {
+            match unsafe { guard.protect(new_node) } {
+                Ok(ptr) => break ptr,
+                Err(ProtectionError::NoAvailableIndices) => backoff.spin(),
+                Err(ProtectionError::NullPointer) => unreachable!(),
+            }
+        };
         loop {
             let tail = self.tail.load(Ordering::Relaxed);
-            let mut protected_tail = match unsafe { guard.protect(tail) } {
+            let protected_tail = match unsafe { guard.protect(unmarked(tail)) } {
                 Ok(ptr) => {
                     fence(Ordering::Acquire);
                     ptr
@@ -74,31 +237,162 @@ impl<T: Default> OMSQueue<T> {
                 },
             };
 
+            if is_marked(tail) {
+                // dequeue_back() is in progress, and tail can't move until it's done
+                if self.tail.load(Ordering::SeqCst) == tail {
+                    self.help_dequeue_back(&protected_tail, guard);
+                }
+                continue;
+            }
+
+            // new node isn't published yet, so if CAS below fails, this store is simply
+            // overwritten on the next iteration. once CAS succeeds, node's next is never changed
             unsafe {
                 (*(new_node as *mut Node<T>)).next.store(tail, Ordering::Release);
             }
 
+            /*
+                tail CAS is the only step that links the node into the queue, hence tail can never
+                lag here: unlike pessimistic variant, there's no 'link first, swing tail later'.
+                weak CAS wouldn't break that (spurious failure writes nothing), but every failed
+                attempt costs us a re-protection of tail and a new next store, so spurious ones
+                are pure waste. in pessimistic queue, by contrast, tail CAS after linking is just
+                a hint: lagging tail gets proceeded by whoever comes next, so weak one is fine there
+            */
+            yield_point();
             if protected_tail.as_mut_ptr() != self.tail.load(Ordering::Relaxed) { continue; }
             if self.tail.compare_exchange(protected_tail.as_mut_ptr(), new_node, Ordering::Release, Ordering::Relaxed).is_ok() {
-                // attempt to store new_node in older tail prev
-                unsafe { &*protected_tail.as_mut_ptr() }.0.prev.store(new_node, Ordering::Release);
-                return true;
+                unpublished.publish();
+                /*
+                    exactly one enqueuer can win CAS from the older tail, so its prev is either
+                    still unset (null, or a mark left by dequeue_back() of some earlier successor),
+                    or has already been restored (to our node) by some dequeuer's fix(). by now,
+                    our node may also have been dequeued from the back, and older tail's prev
+                    marked, or even taken by the next enqueuer. hence CAS, never a stale store
+                */
+                yield_point();
+                loop {
+                    let prev = protected_tail.0.prev.load(Ordering::Acquire);
+                    if !prev.is_null() && !is_marked(prev) {
+                        break;
+                    }
+                    if decision(protected_new_node.0.next.load(Ordering::Acquire)) == UNLINK {
+                        break;
+                    }
+                    if protected_tail.0.prev.compare_exchange(prev, new_node, Ordering::Release, Ordering::Relaxed).is_ok() {
+                        break;
+                    }
+                }
+                return Ok(());
             }
         };
     }
     
-    pub fn dequeue(&self, guard: &HazardPointerGuard<QueueNode<T>>) -> Option<T> {
+    /*
+        single-threaded fast path, e.g. for bulk initialization before the queue is shared, or
+        for teardown. &mut self guarantees nobody else is accessing the queue, so we skip CAS
+        loops and reclamation entirely and work with plain loads and stores via get_mut().
+        bounded queue counts the element, but doesn't refuse it: it's up to the caller
+    */
+    pub fn enqueue_mut(&mut self, data: T) {
+        *self.len.get_mut() += 1;
+        let tail = self.tail.get_mut();
+        let new_node = Box::into_raw(Box::new(Node {
+            data,
+            next: AtomicPtr::new(*tail),
+            prev: AtomicPtr::new(ptr::null_mut()),
+        })) as *mut QueueNode<T>;
+        unsafe { *(**tail).0.prev.get_mut() = new_node };
+        *tail = new_node;
+    }
+
+    // see MSQueue::sync_fence() for the transition protocol between exclusive and shared use
+    pub fn sync_fence(&mut self) {
+        fence(Ordering::Release);
+    }
+
+    // see MSQueue::sync_fence(). interrupted enqueue can't be in flight anymore, but 'prev's
+    // chain may still have been left broken by one: that's fine for dequeue_mut()
+    pub fn quiesce(&mut self) {
+        fence(Ordering::Acquire);
+    }
+
+    // see enqueue_mut()
+    pub fn dequeue_mut(&mut self) -> Option<T> {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        if head == tail {
+            return None;
+        }
+        let mut head_prev = unsafe { *(*head).0.prev.get_mut() };
+        if head_prev.is_null() || is_marked(head_prev) {
+            // 'prev's chain was left broken by some interrupted concurrent enqueue (or by
+            // dequeue_back()), restore it from complete chain of 'next's, same as fix() does
+            let mut current = tail;
+            while current != head {
+                let current_next = unsafe { *(*current).0.next.get_mut() };
+                unsafe { *(*current_next).0.prev.get_mut() = current };
+                current = current_next;
+            }
+            head_prev = unsafe { *(*head).0.prev.get_mut() };
+        }
+        let data = std::mem::take(unsafe { &mut (*head_prev).0.data });
+        *self.head.get_mut() = head_prev;
+        self.release();
+        // nobody can hold the old dummy: freeing it right away
+        unsafe { drop(Box::from_raw(head)) };
+        Some(data)
+    }
+
+    /*
+        structural check for tests, run in a single-threaded phase (hence &mut self): chain of
+        'next's leads from tail to (non-null) dummy head, and every 'prev' along it is either
+        consistent with it or unset (null left by an interrupted enqueue, see fix(), or a mark left
+        by dequeue_back()). tail has no prev.
+        nodes are remembered along the walk, so a cycle is reported instead of looping forever.
+        returns number of elements (nodes before the dummy)
+    */
+    #[cfg(test)]
+    pub(crate) fn check_invariants(&mut self) -> usize {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        assert!(!head.is_null(), "OMSQueue: head is null, dummy node is missing");
+        let tail_prev = unsafe { (*tail).0.prev.load(Ordering::Relaxed) };
+        assert!(
+            tail_prev.is_null() || is_marked(tail_prev),
+            "OMSQueue: tail {:p} has prev",
+            tail
+        );
+        let mut visited = std::collections::HashSet::new();
+        let mut current = tail;
+        while current != head {
+            assert!(!current.is_null(), "OMSQueue: head {:p} isn't reachable from tail", head);
+            assert!(visited.insert(current), "OMSQueue: cycle in next chain at {:p}", current);
+            let next = unsafe { (*current).0.next.load(Ordering::Relaxed) };
+            if !next.is_null() {
+                let next_prev = unsafe { (*next).0.prev.load(Ordering::Relaxed) };
+                assert!(
+                    next_prev.is_null() || is_marked(next_prev) || next_prev == current,
+                    "OMSQueue: prev of {:p} is {:p}, while its successor in next chain is {:p}",
+                    next,
+                    next_prev,
+                    current
+                );
+            }
+            current = next;
+        }
+        visited.len()
+    }
+
+    pub fn dequeue(&self, guard: &R::Guard<'_, QueueNode<T>>) -> Option<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "OMSQueue::dequeue()");
         let mut hp_backoff = Backoff::new();
-        
-        let mut head_ptr = std::mem::MaybeUninit::<*mut QueueNode<T>>::uninit();
-        let mut tail_ptr = std::mem::MaybeUninit::<*mut QueueNode<T>>::uninit();
 
         loop {
-            head_ptr.write(self.head.load(Ordering::Relaxed));
-
-            let mut protected_head = match unsafe { guard.protect(head_ptr.assume_init_read()) } {
+            // head and tail may carry marks of dequeue_back() in progress, see there
+            let head_ptr = self.head.load(Ordering::SeqCst);
+            let protected_head = match unsafe { guard.protect(unmarked(head_ptr)) } {
                 Ok(ptr) => { fence(Ordering::Acquire); ptr },
-                // head can't be empty, ignore ProtectionError::NullPointer 
                 Err(ProtectionError::NoAvailableIndices) => {
                     hp_backoff.spin();
                     continue;
@@ -107,71 +401,254 @@ impl<T: Default> OMSQueue<T> {
                     panic!("OMSQueue::dequeue(): found null pointer while protecting head");
                 }
             };
-            
-            tail_ptr.write(self.tail.load(Ordering::Relaxed));
-            let protected_tail = match unsafe {  guard.protect(tail_ptr.assume_init_read()) }{
+
+            // tail is loaded after head: see dequeue_back() on why it matters
+            yield_point();
+            let tail_ptr = self.tail.load(Ordering::SeqCst);
+            let protected_tail = match unsafe { guard.protect(unmarked(tail_ptr)) } {
                 Ok(ptr) => { fence(Ordering::Acquire); ptr },
                 Err(ProtectionError::NoAvailableIndices) => {
                     hp_backoff.spin();
                     continue;
                 },
-                Err(ProtectionError::NullPointer) => { 
+                Err(ProtectionError::NullPointer) => {
                     panic!("OMSQueue::dequeue(): found null pointer while protecting tail");
                 },
             };
-            
-            if protected_head.as_ptr() != self.head.load(Ordering::Relaxed) ||
-                protected_tail.as_ptr() != self.tail.load(Ordering::Relaxed) { continue; }
 
-            if protected_head.as_ptr() != protected_tail.as_ptr(){
+            if head_ptr != self.head.load(Ordering::SeqCst) || tail_ptr != self.tail.load(Ordering::SeqCst) {
+                continue;
+            }
+            if is_marked(tail_ptr) {
+                self.help_dequeue_back(&protected_tail, guard);
+                continue;
+            }
+            if is_marked(head_ptr) {
+                // tail is unmarked, so dequeue_back() that has left this mark is done: clear it
+                let _ = self.head.compare_exchange(head_ptr, unmarked(head_ptr), Ordering::SeqCst, Ordering::Relaxed);
+                continue;
+            }
+            if head_ptr == tail_ptr {
+                return None;
+            }
 
-                if !protected_head.0.prev.load(Ordering::Relaxed).is_null() {
-                    // okay, behead queue and proceed to (protected) prev
-                    if protected_head.as_ptr() != self.head.load(Ordering::Relaxed) {
+            let head_prev = protected_head.0.prev.load(Ordering::Acquire);
+            if head_prev.is_null() || is_marked(head_prev) {
+                self.fix(protected_head, protected_tail, guard);
+                continue;
+            }
+            // okay, behead queue and proceed to (protected) prev
+            let mut protected_head_prev = loop {
+                match unsafe { guard.protect(head_prev) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        break ptr;
+                    },
+                    Err(ProtectionError::NoAvailableIndices) => {
+                        hp_backoff.spin();
                         continue;
+                    },
+                    // never case, checked above
+                    Err(ProtectionError::NullPointer) => {
+                        panic!("OMSQueue::dequeue(): found null pointer while protecting head_prev");
                     }
+                }
+            };
+            // head's prev could have been dequeued from the back before we've protected it. then
+            // it's no longer there: dequeue_back() leaves a mark instead
+            if protected_head.0.prev.load(Ordering::Acquire) != head_prev || self.head.load(Ordering::SeqCst) != head_ptr {
+                continue;
+            }
 
-                    let head_prev = protected_head.0.prev.load(Ordering::Relaxed);
-
-                    let mut protected_head_prev = loop {
-                        match unsafe { guard.protect(head_prev)} {
-
-                            Ok(ptr) => {
-                                fence(Ordering::Acquire);
-                                break ptr;
-                            },
-                            Err(ProtectionError::NoAvailableIndices) => {
-                                hp_backoff.spin();
-                                continue;
-                            },
-                            // never case, since head != tail and tail doesn't lag
-                            Err(ProtectionError::NullPointer) => {
-                                panic!("OMSQueue::dequeue(): found null pointer while protecting head_prev");
-                            }
-                        }
-                    };
+            yield_point();
+            if self.head.compare_exchange(head_ptr, head_prev, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                guard.retire_node(protected_head);
+                self.release();
+                return Some(std::mem::take(&mut protected_head_prev.0.data));
+            }
+        }
+    }
 
-                    if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
-                        continue;
-                    }
+    /*
+        removes the newest element, so that along with enqueue() and dequeue() the queue works as
+        a deque: enqueue() pushes to the back, dequeue() pops from the front, dequeue_back() pops
+        from the back. back is the tail, the last enqueued node, and its predecessor is simply
+        its next, so there's no need in 'prev's here at all. every operation is linearizable:
+        dequeue_back() returns the value of the last enqueue, that hasn't been dequeued from
+        either end yet, or None if there's none. when only one element is left, dequeue() and
+        dequeue_back() race for it, and exactly one of them gets it.
 
-                    if self.head.compare_exchange(protected_head.as_mut_ptr(), protected_head_prev.as_mut_ptr(), Ordering::Release, Ordering::Relaxed ).is_ok(){
-                        guard.retire_node(protected_head);
-                        return Some(std::mem::take(&mut protected_head_prev.0.data));
-                    };
+        popping node t with predecessor p means swinging tail from t back to p, while dequeue()
+        may be swinging head from p to t at the same time (if t is the only element), and
+        enqueuers swing tail from t forward. so it's done in steps, that anyone may finish:
+        - tail is marked first (CAS from t to t|MARK). marked tail can't be moved by enqueues,
+          nor by other dequeue_back()s, they all help this one to finish (help_dequeue_back()).
+          dequeue() does too: it loads tail after head, and if it sees the mark, or that tail
+          has moved back to p already, it won't swing head onto t
+        - then the way to remove t is decided, and stored in t's next bits (see UNLINK):
+          - if head is neither p nor t, dequeue() can't take t anymore: it has to pass p first,
+            and then it will see the mark. t is simply unlinked: tombstone mark is left in p's
+            prev (that pointed to t), and tail is moved back to p
+          - if head is p, t is the only element, and dequeue() may still take it with a tail
+            loaded before the mark. so t is claimed through head, exactly like dequeue() does
+            it, but with head CAS from p to t|MARK, so it's clear whose claim has won. t stays
+            as new dummy, tail goes back (unmarked) to t, and head mark is cleared after that
+          - if head is already t, dequeue() has taken it, the queue is empty
+        'prev's chain may be broken by an interrupted enqueue, as usual, and tombstones left in
+        'prev's break it the same way: dequeue() runs fix() on either. what fix() and enqueue()
+        must never do, is to write t to p's prev once t is unlinked, hence they check t's next
+        bits. until the pop is finished, tail lags by one node at most, and only while marked
+    */
+    pub fn dequeue_back(&self, guard: &R::Guard<'_, QueueNode<T>>) -> Option<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "OMSQueue::dequeue_back()");
+        let mut hp_backoff = Backoff::new();
+        let mut cas_backoff = Backoff::new();
+        loop {
+            let tail_ptr = self.tail.load(Ordering::SeqCst);
+            let mut protected_tail = match unsafe { guard.protect(unmarked(tail_ptr)) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                },
+                Err(ProtectionError::NoAvailableIndices) => {
+                    hp_backoff.spin();
+                    continue;
+                },
+                Err(ProtectionError::NullPointer) => {
+                    panic!("OMSQueue::dequeue_back(): found null pointer while protecting tail");
+                },
+            };
+            hp_backoff.reset();
+            if self.tail.load(Ordering::SeqCst) != tail_ptr {
+                continue;
+            }
+            if is_marked(tail_ptr) {
+                // someone else is popping it, help them first
+                self.help_dequeue_back(&protected_tail, guard);
+                continue;
+            }
+            if unmarked(self.head.load(Ordering::SeqCst)) == tail_ptr {
+                return None;
+            }
+            yield_point();
+            if self.tail.compare_exchange(tail_ptr, marked(tail_ptr), Ordering::SeqCst, Ordering::Relaxed).is_err() {
+                cas_backoff.spin();
+                continue;
+            }
+
+            // tail stays marked by us until the pop is finished, by us or by helpers
+            self.help_dequeue_back(&protected_tail, guard);
+            return match decision(protected_tail.0.next.load(Ordering::Acquire)) {
+                UNLINK => {
+                    let data = std::mem::take(&mut protected_tail.0.data);
+                    guard.retire_node(protected_tail);
+                    self.release();
+                    Some(data)
+                },
+                // node is the new dummy now, nobody reads its data
+                CLAIMED_WON => {
+                    self.release();
+                    Some(std::mem::take(&mut protected_tail.0.data))
+                },
+                // dequeue() has taken the node first: the queue was empty then
+                _ => None,
+            };
+        }
+    }
+
+    /*
+        finishes dequeue_back() of the node that tail is marked with (protected by caller), or
+        does nothing if it's finished already. see dequeue_back() for the steps
+    */
+    fn help_dequeue_back<'g, G: ReclaimerGuard<QueueNode<T>>>(
+        &self,
+        node: &G::Protected<'g>,
+        guard: &'g G,
+    ) {
+        let node_ptr = node.as_mut_ptr();
+        let marked_tail = marked(node_ptr);
+        // node's next never changes but for its decision bits
+        let pred_ptr = unmarked(node.0.next.load(Ordering::Acquire));
+        let mut backoff = Backoff::new();
+        // until head gets to the node (checked below), predecessor can't be retired
+        let pred = loop {
+            if self.tail.load(Ordering::SeqCst) != marked_tail {
+                return;
+            }
+            match unsafe { guard.protect(pred_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    break ptr;
+                },
+                Err(ProtectionError::NoAvailableIndices) => backoff.spin(),
+                // marked tail is never the dummy: there's always at least one node before it
+                Err(ProtectionError::NullPointer) => {
+                    panic!("OMSQueue::dequeue_back(): found null pointer while protecting tail's next");
+                },
+            }
+        };
+
+        loop {
+            yield_point();
+            if self.tail.load(Ordering::SeqCst) != marked_tail {
+                return;
+            }
+            let head = self.head.load(Ordering::SeqCst);
+            if unmarked(head) == node_ptr {
+                if is_marked(head) {
+                    // claimed through head by this pop. finish it in order: decision, tail, and
+                    // head mark last, so that no dequeue() sees unmarked head with marked tail
+                    let _ = node.0.next.compare_exchange(
+                        with_decision(pred_ptr, CLAIMED),
+                        with_decision(pred_ptr, CLAIMED_WON),
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    );
+                    let _ = self.tail.compare_exchange(marked_tail, node_ptr, Ordering::SeqCst, Ordering::Relaxed);
+                    let _ = self.head.compare_exchange(head, node_ptr, Ordering::SeqCst, Ordering::Relaxed);
+                } else {
+                    // dequeue() has taken the node, and it's the dummy now: the queue is empty
+                    let _ = self.tail.compare_exchange(marked_tail, node_ptr, Ordering::SeqCst, Ordering::Relaxed);
                 }
-                self.fix(protected_head, protected_tail, guard);
+                return;
+            }
+            if is_marked(head) {
+                // left by an earlier dequeue_back(), that is done with tail already
+                let _ = self.head.compare_exchange(head, unmarked(head), Ordering::SeqCst, Ordering::Relaxed);
                 continue;
             }
-            return None;
+            match decision(node.0.next.load(Ordering::SeqCst)) {
+                UNDECIDED => {
+                    let decision = if head == pred_ptr { CLAIMED } else { UNLINK };
+                    let _ = node.0.next.compare_exchange(
+                        pred_ptr,
+                        with_decision(pred_ptr, decision),
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    );
+                },
+                UNLINK => break,
+                // head doesn't move from the predecessor but onto the node, checked above
+                _ => {
+                    if self.head.compare_exchange(pred_ptr, marked(node_ptr), Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                        guard.retire_raw_pointer(pred_ptr);
+                    }
+                },
+            }
         }
+
+        // tombstone first, tail next: once tail is back at predecessor, its prev must not lead
+        // to the unlinked node anymore
+        pred.0.prev.store(marked(node_ptr), Ordering::Release);
+        yield_point();
+        let _ = self.tail.compare_exchange(marked_tail, pred_ptr, Ordering::SeqCst, Ordering::Relaxed);
     }
-    
-    fn fix(
+
+    fn fix<'g, G: ReclaimerGuard<QueueNode<T>>>(
         &self,
-        head: ProtectedPointer<QueueNode<T>>, 
-        tail: ProtectedPointer<QueueNode<T>>, 
-        guard: &HazardPointerGuard<QueueNode<T>>
+        head: G::Protected<'g>,
+        tail: G::Protected<'g>,
+        guard: &'g G
     ) {
         let mut backoff = Backoff::new();
         let mut current = tail;
@@ -180,7 +657,7 @@ impl<T: Default> OMSQueue<T> {
         // fixing things and likely there were several consecutive dequeues. if we ignore such a case
         // current_next might read after free
         while current.as_ptr() != head.as_ptr() && head.as_ptr() == self.head.load(Ordering::Relaxed) {
-            let current_next = match unsafe { guard.protect((*current).0.next.load(Ordering::Relaxed)) } {
+            let current_next = match unsafe { guard.protect(unmarked(current.0.next.load(Ordering::Relaxed))) } {
                 Ok(ptr) => {
                     fence(Ordering::Acquire);
                     ptr
@@ -195,36 +672,110 @@ impl<T: Default> OMSQueue<T> {
                 },
             };
             backoff.reset();
-            if  current_next.0.prev.load(Ordering::Relaxed).is_null() {
-                 current_next.0.prev.store(current.as_mut_ptr(),Ordering::Release) ;
-            } 
+            let prev = current_next.0.prev.load(Ordering::Acquire);
+            if prev.is_null() || is_marked(prev) {
+                // current may have been dequeued from the back since, then tail we've started
+                // from is stale, and it's none of our business anymore
+                if decision(current.0.next.load(Ordering::Acquire)) == UNLINK {
+                    return;
+                }
+                yield_point();
+                let _ = current_next.0.prev.compare_exchange(prev, current.as_mut_ptr(), Ordering::Release, Ordering::Relaxed);
+            }
             current = current_next;
         }
     }
 }
 
 
-unsafe impl<T: Default> Sync for OMSQueue<T> {}
+/*
+    on drop we can't rely on 'prev's: enqueue may have been interrupted right after tail CAS,
+    leaving older tail's prev null (normally fix() would repair it on dequeue). 'next's, however,
+    are always complete: new node's next is stored *before* it's published with tail CAS, and never
+    changes afterwards. so the chain of 'next's from tail down to head is always fully linked and
+    covers every node owned by the queue. note, we stop at head rather than at null: head's next
+    still points to the previous (already retired and owned by some guard) head
+*/
+impl<T: Default, R: Reclaimer> Drop for OMSQueue<T, R> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let mut current = *self.tail.get_mut();
+        loop {
+            let next = unsafe { (*current).0.next.load(Ordering::Relaxed) };
+            let is_head = current == head;
+            unsafe { drop(Box::from_raw(current)) };
+            if is_head {
+                break;
+            }
+            current = next;
+        }
+    }
+}
+
+unsafe impl<T: Default + Send, R: Reclaimer> Sync for OMSQueue<T, R> {}
+unsafe impl<T: Default + Send, R: Reclaimer> Send for OMSQueue<T, R> {}
+
+/*
+    node pointers are aligned at least to 4, which leaves two low bits for marks. MARK on tail
+    and head is dequeue_back() in progress, and on prev it's a tombstone of the node, that has
+    been dequeued from the back (see dequeue_back()). bits of popped node's next are how it's
+    removed, decided once per pop (UNDECIDED until then)
+*/
+const MARK: usize = 1;
+const UNDECIDED: usize = 0;
+// unlinked from the chain, predecessor becomes tail again
+const UNLINK: usize = 1;
+// the only element, claimed through head. CLAIMED_WON once the claim has won against dequeue()
+const CLAIMED: usize = 2;
+const CLAIMED_WON: usize = 3;
+const LOW_BITS: usize = 3;
+
+fn marked<N>(ptr: *mut N) -> *mut N {
+    (ptr as usize | MARK) as *mut N
+}
+
+fn unmarked<N>(ptr: *mut N) -> *mut N {
+    (ptr as usize & !LOW_BITS) as *mut N
+}
+
+fn is_marked<N>(ptr: *mut N) -> bool {
+    ptr as usize & MARK != 0
+}
+
+fn decision<N>(next: *mut N) -> usize {
+    next as usize & LOW_BITS
+}
+
+fn with_decision<N>(next: *mut N, decision: usize) -> *mut N {
+    (next as usize | decision) as *mut N
+}
 
 #[cfg(test)]
 mod tests {
-    use super::OMSQueue;
+    use super::{Node, OMSQueue, QueueNode};
+    use crate::utils::interleave::replay;
+    use crate::mechanisms::ebr::EbrDomain;
     use crate::mechanisms::hp::HazardPointerArray;
+    use crate::mechanisms::reclaim::{EbrReclaimer, HpReclaimer, Reclaimer};
     use std::collections::HashSet;
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
     use std::sync::LazyLock;
 
-    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(|| HazardPointerArray::new());
+    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(HazardPointerArray::new);
+    static EBR_DOMAIN: EbrDomain = EbrDomain::new();
 
     #[test]
     fn test_basic_operations() {
-        let q = OMSQueue::new();
+        let mut q = OMSQueue::new();
         let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(q.check_invariants(), 0);
 
         q.enqueue(1, &guard);
         q.enqueue(2, &guard);
         q.enqueue(3, &guard);
         q.enqueue(4, &guard);
+        assert_eq!(q.check_invariants(), 4);
 
         let results = vec![
             q.dequeue(&guard).unwrap(),
@@ -234,6 +785,125 @@ mod tests {
         ];
         
         assert_eq!(results, vec![1, 2, 3, 4]);
+        assert_eq!(q.check_invariants(), 0);
+    }
+
+    // live instances, so that both leaks and double frees show up. values made up by Default
+    // (dummies) belong to no test, and aren't counted
+    #[derive(Default)]
+    struct Tracked<'a> {
+        live: Option<&'a AtomicUsize>,
+    }
+
+    impl<'a> Tracked<'a> {
+        fn new(live: &'a AtomicUsize) -> Self {
+            live.fetch_add(1, Ordering::Relaxed);
+            Tracked { live: Some(live) }
+        }
+    }
+
+    impl Drop for Tracked<'_> {
+        fn drop(&mut self) {
+            if let Some(live) = self.live.take() {
+                assert!(live.fetch_sub(1, Ordering::Relaxed) > 0, "Tracked: dropped twice");
+            }
+        }
+    }
+
+    #[test]
+    fn test_drop_after_interrupted_enqueue() {
+        // own array as well: nothing gets orphaned to, and freed by, other tests' guards
+        let hp_array = HazardPointerArray::new();
+        let live = AtomicUsize::new(0);
+        {
+            let mut q = OMSQueue::new();
+            let guard = hp_array.register_thread().ok().unwrap();
+            q.enqueue(Tracked::new(&live), &guard);
+            q.enqueue(Tracked::new(&live), &guard);
+            q.enqueue(Tracked::new(&live), &guard);
+
+            // replay enqueue up to (and including) tail CAS, as if thread was preempted
+            // right before storing older tail's prev
+            let tail = q.tail.load(Ordering::Relaxed);
+            let new_node = Box::into_raw(Box::new(Node {
+                data: Tracked::new(&live),
+                next: AtomicPtr::new(tail),
+                prev: AtomicPtr::new(ptr::null_mut()),
+            })) as *mut QueueNode<Tracked>;
+            assert!(q
+                .tail
+                .compare_exchange(tail, new_node, Ordering::Release, Ordering::Relaxed)
+                .is_ok());
+            assert!(unsafe { &*tail }.0.prev.load(Ordering::Relaxed).is_null());
+            // broken 'prev' is tolerated, as long as 'next's are intact
+            assert_eq!(q.check_invariants(), 4);
+
+            drop(q.dequeue(&guard));
+            assert_eq!(q.check_invariants(), 3);
+            assert_eq!(live.load(Ordering::Relaxed), 3);
+        }
+        // guard has reclaimed retired head, queue has freed everything reachable from tail
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_mut_fast_path() {
+        let mut q = OMSQueue::new();
+        for i in 0..100 {
+            q.enqueue_mut(i);
+        }
+        assert_eq!(q.dequeue_mut(), Some(0));
+        q.sync_fence();
+
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        q.enqueue(100, &guard);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        drop(guard);
+        q.quiesce();
+
+        let values = std::iter::from_fn(|| q.dequeue_mut()).collect::<Vec<_>>();
+        assert_eq!(values, (2..=100).collect::<Vec<_>>());
+    }
+
+    // hammers the window between new node's next store and tail CAS, then checks that
+    // tail never lagged: chain of 'next's from tail reaches head through every enqueued node,
+    // and each producer's values appear in that chain in their enqueue order
+    #[test]
+    fn test_enqueue_tail_does_not_lag() {
+        let thread_count = 4;
+        let per_thread_ops = 256;
+        let hp_array = HazardPointerArray::new();
+
+        for _ in 0..1000 {
+            let q = OMSQueue::new();
+            let q_ref = &q;
+            let hp_array_ref = &hp_array;
+            std::thread::scope(|s| {
+                for t in 0..thread_count {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..per_thread_ops {
+                            q_ref.enqueue((t, i), &guard);
+                        }
+                    });
+                }
+            });
+
+            let head = q.head.load(Ordering::Relaxed);
+            let mut current = q.tail.load(Ordering::Relaxed);
+            assert!(unsafe { &*current }.0.prev.load(Ordering::Relaxed).is_null());
+            let mut last_seen = vec![per_thread_ops; thread_count];
+            let mut node_count = 0;
+            while current != head {
+                // walking from the newest to the oldest
+                let (t, i) = unsafe { &*current }.0.data;
+                assert!(i < last_seen[t]);
+                last_seen[t] = i;
+                node_count += 1;
+                current = unsafe { &*current }.0.next.load(Ordering::Relaxed);
+            }
+            assert_eq!(node_count, thread_count * per_thread_ops);
+        }
     }
 
     #[derive(Default)]
@@ -242,18 +912,28 @@ mod tests {
     }
 
     static NEXT_VALUE: AtomicUsize = AtomicUsize::new(0);
+    static NEXT_EBR_VALUE: AtomicUsize = AtomicUsize::new(0);
 
     impl TrackableValue {
-        fn new() -> Self {
+        fn new(next_value: &AtomicUsize) -> Self {
             TrackableValue {
-                value: NEXT_VALUE.fetch_add(1, Ordering::Relaxed),
+                value: next_value.fetch_add(1, Ordering::Relaxed),
             }
         }
     }
 
     #[test]
     fn test_concurrent() {
-        let q = OMSQueue::new();
+        run_concurrent::<HpReclaimer>(&HP_ARRAY, &NEXT_VALUE);
+    }
+
+    #[test]
+    fn test_concurrent_ebr() {
+        run_concurrent::<EbrReclaimer>(&EBR_DOMAIN, &NEXT_EBR_VALUE);
+    }
+
+    fn run_concurrent<R: Reclaimer>(domain: &R::Domain, next_value: &AtomicUsize) {
+        let q = OMSQueue::<_, R>::with_reclaimer();
         let q_ref = &q;
 
         let thread_count = 8;
@@ -267,11 +947,11 @@ mod tests {
             std::thread::scope(|scope| {
                 for _ in 0..thread_count {
                     scope.spawn(|| {
-                        let guard = HP_ARRAY.register_thread().ok().unwrap();
+                        let guard = R::register_thread(domain).ok().unwrap();
 
                         // first batch: enqueue half and eventual dequeue half
                         for _ in 0..per_thread_ops / 2 {
-                            q_ref.enqueue(TrackableValue::new(), &guard);
+                            q_ref.enqueue(TrackableValue::new(next_value), &guard);
                         }
 
                         for _ in 0..per_thread_ops / 2 {
@@ -287,7 +967,7 @@ mod tests {
 
                         // second batch
                         for _ in 0..per_thread_ops / 2 {
-                            q_ref.enqueue(TrackableValue::new(), &guard);
+                            q_ref.enqueue(TrackableValue::new(next_value), &guard);
                         }
 
                         for _ in 0..per_thread_ops / 2 {
@@ -306,7 +986,293 @@ mod tests {
                 collected_values.into_inner().unwrap().into_iter().collect();
             assert_eq!(actual_values, expected_values);
 
-            NEXT_VALUE.store(0, Ordering::Relaxed);
+            next_value.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // declared HAZARDS_PER_OP is exactly the peak of the most demanding operation
+    #[test]
+    fn test_hazards_per_op() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = OMSQueue::new();
+        let hazards = OMSQueue::<usize>::HAZARDS_PER_OP;
+
+        guard.reset_peak_protected();
+        q.enqueue(1, &guard);
+        q.enqueue(2, &guard);
+        assert!(guard.peak_protected() <= hazards);
+
+        guard.reset_peak_protected();
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(guard.peak_protected(), hazards);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    static UNPUBLISHED_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct UnpublishedCounter;
+
+    impl Drop for UnpublishedCounter {
+        fn drop(&mut self) {
+            UNPUBLISHED_DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // enqueue that panics before linking its node doesn't leak it
+    #[test]
+    fn test_panicking_enqueue_frees_node() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = OMSQueue::new();
+        // dummy node's data is never dropped through the counter below
+        let dummy = q.tail.swap(ptr::null_mut(), Ordering::Relaxed);
+        UNPUBLISHED_DROPPED.store(0, Ordering::Relaxed);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            q.enqueue(UnpublishedCounter, &guard)
+        }));
+        assert!(result.is_err());
+        assert_eq!(UNPUBLISHED_DROPPED.load(Ordering::Relaxed), 1);
+        assert_eq!(guard.protected_count(), 0);
+
+        q.tail.store(dummy, Ordering::Relaxed);
+        assert!(q.enqueue(UnpublishedCounter, &guard));
+        assert_eq!(UNPUBLISHED_DROPPED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = OMSQueue::bounded(2);
+        assert_eq!(q.capacity(), Some(2));
+        assert!(q.try_enqueue(1, &guard).is_ok());
+        assert!(q.enqueue(2, &guard));
+        assert_eq!(q.try_enqueue(3, &guard), Err(3));
+        assert!(!q.enqueue(3, &guard));
+        // failed reservations are rolled back
+        assert_eq!(q.approx_len(), 2);
+        assert_eq!(q.check_invariants(), 2);
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(q.try_enqueue(3, &guard), Ok(()));
+        assert_eq!(q.dequeue_mut(), Some(2));
+        assert_eq!(q.dequeue(&guard), Some(3));
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(q.approx_len(), 0);
+        drop(guard);
+
+        // producers are refused once the queue is full, and it never holds more than capacity
+        let capacity = 8;
+        let (producers, per_producer) = (2, 3000);
+        let q = OMSQueue::bounded(capacity);
+        let dequeued = AtomicUsize::new(0);
+        let (q_ref, hp_array_ref, dequeued_ref) = (&q, &hp_array, &dequeued);
+        std::thread::scope(|s| {
+            for p in 0..producers {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    for i in 0..per_producer {
+                        let mut value = p * per_producer + i;
+                        while let Err(refused) = q_ref.try_enqueue(value, &guard) {
+                            value = refused;
+                            std::thread::yield_now();
+                        }
+                        // len includes in-flight reservations, at most one per producer
+                        assert!(q_ref.approx_len() <= capacity + producers);
+                    }
+                });
+            }
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                let mut values = HashSet::new();
+                while values.len() < producers * per_producer {
+                    match q_ref.dequeue(&guard) {
+                        Some(value) => assert!(values.insert(value)),
+                        None => std::thread::yield_now(),
+                    }
+                    dequeued_ref.store(values.len(), Ordering::Relaxed);
+                }
+            });
+        });
+        assert_eq!(dequeued.load(Ordering::Relaxed), producers * per_producer);
+        assert_eq!(q.approx_len(), 0);
+    }
+
+    #[test]
+    fn test_dequeue_back() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = OMSQueue::bounded(8);
+        assert_eq!(q.dequeue_back(&guard), None);
+        for i in 1..=4 {
+            q.enqueue(i, &guard);
+        }
+        assert_eq!(q.dequeue_back(&guard), Some(4));
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(q.dequeue_back(&guard), Some(3));
+        assert_eq!(q.check_invariants(), 1);
+        assert_eq!(q.approx_len(), 1);
+        // the only element is taken through head, and its node stays as the dummy
+        assert_eq!(q.dequeue_back(&guard), Some(2));
+        assert_eq!(q.dequeue_back(&guard), None);
+        assert_eq!(q.dequeue(&guard), None);
+        assert_eq!(q.check_invariants(), 0);
+        q.enqueue(5, &guard);
+        q.enqueue(6, &guard);
+        assert_eq!(q.dequeue(&guard), Some(5));
+        assert_eq!(q.dequeue_back(&guard), Some(6));
+        assert_eq!(q.approx_len(), 0);
+        assert_eq!(guard.protected_count(), 0);
+
+        // 'prev's chain broken by interrupted enqueues, and tombstone, that unlinked node leaves
+        for i in 1..=3 {
+            q.enqueue(i, &guard);
+        }
+        let head = q.head.load(Ordering::Relaxed);
+        let mut current = q.tail.load(Ordering::Relaxed);
+        while current != head {
+            current = unsafe { &*current }.0.next.load(Ordering::Relaxed);
+            unsafe { &*current }.0.prev.store(ptr::null_mut(), Ordering::Relaxed);
+        }
+        assert_eq!(q.dequeue_back(&guard), Some(3));
+        let tail = q.tail.load(Ordering::Relaxed);
+        assert!(super::is_marked(unsafe { &*tail }.0.prev.load(Ordering::Relaxed)));
+        assert_eq!(q.dequeue(&guard), Some(1));
+        q.enqueue(4, &guard);
+        assert_eq!(unsafe { &*tail }.0.prev.load(Ordering::Relaxed), q.tail.load(Ordering::Relaxed));
+        assert_eq!(q.check_invariants(), 2);
+        assert_eq!(q.dequeue(&guard), Some(2));
+        assert_eq!(q.dequeue_back(&guard), Some(4));
+        assert_eq!(q.check_invariants(), 0);
+    }
+
+    #[test]
+    fn test_approx_len_and_is_empty() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let mut q = OMSQueue::new();
+        assert!(q.is_empty());
+        assert_eq!(q.approx_len(), 0);
+        q.enqueue(1, &guard);
+        q.enqueue(2, &guard);
+        q.enqueue_mut(3);
+        assert!(!q.is_empty());
+        assert_eq!(q.approx_len(), 3);
+        assert_eq!(q.dequeue_back(&guard), Some(3));
+        assert_eq!(q.dequeue_mut(), Some(1));
+        assert_eq!(q.approx_len(), 1);
+        assert!(!q.is_empty());
+        assert_eq!(q.dequeue(&guard), Some(2));
+        assert!(q.is_empty());
+        assert_eq!(q.approx_len(), 0);
+
+        // element, that dequeue_back() is popping (tail is marked), is there until it's done
+        q.enqueue(4, &guard);
+        let tail = q.tail.load(Ordering::Relaxed);
+        q.tail.store(super::marked(tail), Ordering::Relaxed);
+        assert!(!q.is_empty());
+        q.tail.store(tail, Ordering::Relaxed);
+        assert_eq!(q.dequeue_back(&guard), Some(4));
+        assert!(q.is_empty());
+        assert_eq!(q.approx_len(), 0);
+    }
+
+    // every value, that's been in the queue, is either taken once, from either end, or left
+    #[test]
+    fn test_replay_dequeue_back() {
+        for seed in 0..2000 {
+            let hp_array = HazardPointerArray::new();
+            let mut q = OMSQueue::new();
+            q.enqueue_mut(0);
+            let taken = std::sync::Mutex::new(Vec::new());
+            let (q_ref, hp_array_ref, taken_ref) = (&q, &hp_array, &taken);
+            // enqueues values, then dequeues as many times from the back or from the front
+            let thread = |values: &'static [usize], dequeues: usize, back: bool| -> Box<dyn FnOnce() + Send + '_> {
+                Box::new(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    for &value in values {
+                        q_ref.enqueue(value, &guard);
+                    }
+                    for _ in 0..dequeues {
+                        let value = if back { q_ref.dequeue_back(&guard) } else { q_ref.dequeue(&guard) };
+                        taken_ref.lock().unwrap().extend(value);
+                    }
+                    assert_eq!(guard.protected_count(), 0);
+                })
+            };
+            replay(
+                seed,
+                vec![
+                    thread(&[1], 2, true),
+                    thread(&[2], 1, false),
+                    thread(&[3, 5], 1, true),
+                    thread(&[4], 2, false),
+                ],
+            );
+            let mut values = taken.into_inner().unwrap();
+            assert_eq!(q.check_invariants(), 6 - values.len(), "seed {}", seed);
+            values.extend(std::iter::from_fn(|| q.dequeue_mut()));
+            values.sort();
+            assert_eq!(values, (0..6).collect::<Vec<_>>(), "seed {}", seed);
+        }
+    }
+
+    // producers against front and back consumers: every value is taken exactly once, and front
+    // takes each producer's values in order
+    #[test]
+    fn test_dequeue_back_concurrent() {
+        let hp_array = HazardPointerArray::new();
+        let (producers, per_producer) = (2, 2000);
+        for _ in 0..20 {
+            let mut q = OMSQueue::new();
+            let taken = std::sync::Mutex::new(Vec::new());
+            let produced = AtomicUsize::new(0);
+            let (q_ref, hp_array_ref, taken_ref, produced_ref) = (&q, &hp_array, &taken, &produced);
+            std::thread::scope(|s| {
+                for p in 0..producers {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..per_producer {
+                            q_ref.enqueue((p, i), &guard);
+                        }
+                        produced_ref.fetch_add(1, Ordering::Release);
+                    });
+                }
+                for back in [false, true] {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let mut values = Vec::new();
+                        let mut last_front = [None; 2];
+                        loop {
+                            let done = produced_ref.load(Ordering::Acquire) == producers;
+                            let value = if back {
+                                q_ref.dequeue_back(&guard)
+                            } else {
+                                q_ref.dequeue(&guard)
+                            };
+                            match value {
+                                Some((p, i)) => {
+                                    if !back {
+                                        assert!(last_front[p] < Some(i));
+                                        last_front[p] = Some(i);
+                                    }
+                                    values.push((p, i));
+                                }
+                                None if done => break,
+                                None => std::thread::yield_now(),
+                            }
+                        }
+                        taken_ref.lock().unwrap().extend(values);
+                    });
+                }
+            });
+            let taken = taken.into_inner().unwrap();
+            assert_eq!(taken.len(), producers * per_producer);
+            let taken: HashSet<_> = taken.into_iter().collect();
+            assert_eq!(taken.len(), producers * per_producer);
+            assert_eq!(q.check_invariants(), 0);
         }
     }
 }
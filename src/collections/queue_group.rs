@@ -0,0 +1,206 @@
+use crate::collections::ms_queue::{MSQueue, QueueNode};
+use crate::mechanisms::reclaim::{HpReclaimer, Reclaimer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+    work-distribution layer over several MSQueues (shards), e.g. for a pool of workers, that
+    take tasks from whichever shard has some. queues don't keep their length, so every shard
+    is paired with a counter of its own:
+    - enqueue bumps it before enqueueing, dequeue drops it after dequeueing, so a counter is
+      never below the actual length of its shard, and never underflows; total_len() sums them
+      up, and may overcount by the number of operations in flight (same as a snapshot of
+      several counters can be stale in general);
+    - enqueue_balanced() picks the shard with the smallest counter. concurrent enqueues may
+      pick the same one, so it's balanced up to the number of those;
+    - both enqueue_balanced() and dequeue_any() start scanning from a rotating index, so that
+      ties and empty shards don't always send everybody to shard 0.
+    all shards are of the same node type, so the same guard serves any of them
+*/
+
+// keep shards (queue ends and counter) on separate cache lines
+#[repr(align(128))]
+struct Shard<T, R: Reclaimer> {
+    queue: MSQueue<T, R>,
+    len: AtomicUsize,
+}
+
+pub struct QueueGroup<T, R: Reclaimer = HpReclaimer> {
+    shards: Box<[Shard<T, R>]>,
+    next_start: AtomicUsize,
+}
+
+impl<T: Send> QueueGroup<T> {
+    pub fn new(shard_count: usize) -> Self {
+        Self::with_reclaimer(shard_count)
+    }
+}
+
+impl<T, R> QueueGroup<T, R>
+where
+    T: Send,
+    R: Reclaimer,
+{
+    // e.g. QueueGroup::<T, EbrReclaimer>::with_reclaimer(4)
+    pub fn with_reclaimer(shard_count: usize) -> Self {
+        assert!(shard_count > 0, "QueueGroup: there must be at least one shard");
+        Self {
+            shards: (0..shard_count)
+                .map(|_| Shard {
+                    queue: MSQueue::with_reclaimer(),
+                    len: AtomicUsize::new(0),
+                })
+                .collect(),
+            next_start: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn total_len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.len.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    // user should register thread to obtain guard
+    pub fn enqueue_balanced(&self, value: T, guard: &R::Guard<'_, QueueNode<T>>) -> bool {
+        let start = self.rotate();
+        let mut target = &self.shards[start];
+        let mut min_len = target.len.load(Ordering::Relaxed);
+        for offset in 1..self.shards.len() {
+            if min_len == 0 {
+                break;
+            }
+            let shard = &self.shards[(start + offset) % self.shards.len()];
+            let len = shard.len.load(Ordering::Relaxed);
+            if len < min_len {
+                target = shard;
+                min_len = len;
+            }
+        }
+        target.len.fetch_add(1, Ordering::Relaxed);
+        let enqueued = target.queue.enqueue(value, guard);
+        if !enqueued {
+            target.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        enqueued
+    }
+
+    // None only if every shard was empty at the moment it was tried
+    pub fn dequeue_any(&self, guard: &R::Guard<'_, QueueNode<T>>) -> Option<T> {
+        let start = self.rotate();
+        for offset in 0..self.shards.len() {
+            let shard = &self.shards[(start + offset) % self.shards.len()];
+            if let Some(value) = shard.queue.dequeue(guard) {
+                shard.len.fetch_sub(1, Ordering::Relaxed);
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn rotate(&self) -> usize {
+        self.next_start.fetch_add(1, Ordering::Relaxed) % self.shards.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueueGroup;
+    use crate::mechanisms::hp::HazardPointerArray;
+    use std::collections::HashSet;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Barrier, LazyLock};
+
+    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(HazardPointerArray::new);
+
+    fn shard_lens<T>(group: &QueueGroup<T>) -> Vec<usize> {
+        group
+            .shards
+            .iter()
+            .map(|shard| shard.len.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    #[test]
+    fn test_basic_operations() {
+        let group = QueueGroup::new(3);
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(group.dequeue_any(&guard), None);
+
+        for i in 0..7 {
+            assert!(group.enqueue_balanced(i, &guard));
+        }
+        assert_eq!(group.total_len(), 7);
+        let mut lens = shard_lens(&group);
+        lens.sort();
+        assert_eq!(lens, vec![2, 2, 3]);
+
+        let mut values = Vec::new();
+        while let Some(value) = group.dequeue_any(&guard) {
+            values.push(value);
+        }
+        values.sort();
+        assert_eq!(values, (0..7).collect::<Vec<_>>());
+        assert_eq!(group.total_len(), 0);
+
+        // emptied shard is the first to be refilled
+        group.enqueue_balanced(1, &guard);
+        group.enqueue_balanced(2, &guard);
+        group.enqueue_balanced(3, &guard);
+        group.shards[1].queue.dequeue(&guard).unwrap();
+        group.shards[1].len.fetch_sub(1, Ordering::Relaxed);
+        group.enqueue_balanced(4, &guard);
+        assert_eq!(shard_lens(&group), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let thread_count = 4;
+        let shard_count = 4;
+        let per_thread_ops = 1000;
+        let group = QueueGroup::new(shard_count);
+        let barrier = Barrier::new(thread_count);
+
+        // every thread enqueues its share, then they all dequeue whatever they find
+        let dequeued: Vec<Vec<usize>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..thread_count)
+                .map(|t| {
+                    let (group, barrier) = (&group, &barrier);
+                    s.spawn(move || {
+                        let guard = HP_ARRAY.register_thread().ok().unwrap();
+                        for i in 0..per_thread_ops {
+                            assert!(group.enqueue_balanced(t * per_thread_ops + i, &guard));
+                        }
+                        barrier.wait();
+                        // all enqueues are done: racing picks may have overshot the least
+                        // loaded shard, but only by as many as there were racers
+                        if t == 0 {
+                            let lens = shard_lens(group);
+                            let (min, max) = (lens.iter().min(), lens.iter().max());
+                            assert!(max.unwrap() - min.unwrap() <= thread_count, "{:?}", lens);
+                            assert_eq!(group.total_len(), thread_count * per_thread_ops);
+                        }
+                        barrier.wait();
+                        let mut values = Vec::new();
+                        while let Some(value) = group.dequeue_any(&guard) {
+                            values.push(value);
+                        }
+                        values
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut seen = HashSet::new();
+        for value in dequeued.into_iter().flatten() {
+            assert!(seen.insert(value), "value {} dequeued twice", value);
+        }
+        assert_eq!(seen.len(), thread_count * per_thread_ops);
+        assert_eq!(group.total_len(), 0);
+    }
+}
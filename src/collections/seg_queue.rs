@@ -0,0 +1,336 @@
+use crate::mechanisms::hp::ProtectionError;
+use crate::mechanisms::reclaim::{HazardBudget, HpReclaimer, Protected, Reclaimer, ReclaimerGuard};
+use crate::utils::backoff::Backoff;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+/*
+    segmented queue for small Copy values. MSQueue allocates a node per element, which dominates
+    the cost for small T. here, values are stored inline in fixed-size arrays of slots (segments),
+    and segments are linked MS-queue-style. most enqueues just bump segment's enqueue index and
+    write into the reserved slot, and a new segment is allocated only once per SEGMENT_SIZE values.
+    hazard pointers protect segments, not elements.
+    note: dequeuer that has claimed a slot, which is reserved but not yet written by its enqueuer,
+    waits for the write to complete, so (as in crossbeam's SegQueue) algorithm isn't strictly
+    lock-free around that window
+*/
+
+const SEGMENT_SIZE: usize = 32;
+
+// slot states
+const EMPTY: u8 = 0;
+const WRITTEN: u8 = 1;
+
+pub struct SegQueue<T: Copy, R: Reclaimer = HpReclaimer> {
+    head: AtomicPtr<Segment<T>>,
+    tail: AtomicPtr<Segment<T>>,
+    _reclaimer: PhantomData<R>,
+}
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+}
+
+pub struct Segment<T> {
+    slots: [Slot<T>; SEGMENT_SIZE],
+    // may grow past SEGMENT_SIZE: every failed reservation in a full segment still increments it
+    enqueue_idx: AtomicUsize,
+    dequeue_idx: AtomicUsize,
+    next: AtomicPtr<Segment<T>>,
+}
+
+impl<T> Segment<T> {
+    fn new() -> Self {
+        Segment {
+            slots: std::array::from_fn(|_| Slot {
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicU8::new(EMPTY),
+            }),
+            enqueue_idx: AtomicUsize::new(0),
+            dequeue_idx: AtomicUsize::new(0),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T: Copy + Send> Default for SegQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + Send> SegQueue<T> {
+    pub fn new() -> Self {
+        Self::with_reclaimer()
+    }
+}
+
+impl<T: Copy + Send, R: Reclaimer> SegQueue<T, R> {
+    // max number of pointers a single operation protects at once (either head or tail segment).
+    // hazard pointer guards used with this queue need at least that many free slots
+    pub const HAZARDS_PER_OP: usize = 1;
+
+    // e.g. SegQueue::<T, EbrReclaimer>::with_reclaimer()
+    pub fn with_reclaimer() -> Self {
+        let segment = Box::into_raw(Box::new(Segment::new()));
+        SegQueue {
+            head: AtomicPtr::new(segment),
+            tail: AtomicPtr::new(segment),
+            _reclaimer: PhantomData,
+        }
+    }
+
+    // user should register thread to obtain guard
+    pub fn enqueue(&self, value: T, guard: &R::Guard<'_, Segment<T>>) {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "SegQueue::enqueue()");
+        let mut backoff = Backoff::new();
+        loop {
+            let tail_ptr = self.tail.load(Ordering::Relaxed);
+            let protected_tail = match unsafe { guard.protect(tail_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("SegQueue::enqueue(): found null pointer while protecting tail");
+                }
+            };
+            if self.tail.load(Ordering::Acquire) != protected_tail.as_mut_ptr() {
+                continue;
+            }
+
+            // fast path: reserve a slot in current segment
+            let idx = protected_tail.enqueue_idx.fetch_add(1, Ordering::AcqRel);
+            if idx < SEGMENT_SIZE {
+                let slot = &protected_tail.slots[idx];
+                unsafe { (*slot.value.get()).write(value) };
+                slot.state.store(WRITTEN, Ordering::Release);
+                return;
+            }
+
+            // segment is full: either help to proceed tail, or link a new segment
+            let tail_next = protected_tail.next.load(Ordering::Acquire);
+            if !tail_next.is_null() {
+                _ = self.tail.compare_exchange(
+                    protected_tail.as_mut_ptr(),
+                    tail_next,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                );
+                continue;
+            }
+
+            // new segment comes with our value already in its first slot
+            let new_segment = Segment::new();
+            unsafe { (*new_segment.slots[0].value.get()).write(value) };
+            new_segment.slots[0].state.store(WRITTEN, Ordering::Relaxed);
+            new_segment.enqueue_idx.store(1, Ordering::Relaxed);
+            let new_segment = Box::into_raw(Box::new(new_segment));
+
+            if protected_tail
+                .next
+                .compare_exchange(ptr::null_mut(), new_segment, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // on fail, tail will be proceeded by others
+                _ = self.tail.compare_exchange(
+                    protected_tail.as_mut_ptr(),
+                    new_segment,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                );
+                return;
+            }
+            // someone else has linked their segment first, ours was never published
+            unsafe { drop(Box::from_raw(new_segment)) };
+            backoff.spin();
+        }
+    }
+
+    // user should register thread to obtain guard
+    pub fn dequeue(&self, guard: &R::Guard<'_, Segment<T>>) -> Option<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "SegQueue::dequeue()");
+        let mut backoff = Backoff::new();
+        loop {
+            let head_ptr = self.head.load(Ordering::Relaxed);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("SegQueue::dequeue(): found null pointer while protecting head");
+                }
+            };
+            if self.head.load(Ordering::Acquire) != protected_head.as_mut_ptr() {
+                continue;
+            }
+
+            let idx = protected_head.dequeue_idx.load(Ordering::Acquire);
+            if idx >= SEGMENT_SIZE {
+                // segment is exhausted, move on to the next one
+                let head_next = protected_head.next.load(Ordering::Acquire);
+                if head_next.is_null() {
+                    return None;
+                }
+                // tail must not keep pointing to segment we're about to retire
+                if self.tail.load(Ordering::Acquire) == protected_head.as_mut_ptr() {
+                    _ = self.tail.compare_exchange(
+                        protected_head.as_mut_ptr(),
+                        head_next,
+                        Ordering::Release,
+                        Ordering::Relaxed,
+                    );
+                    continue;
+                }
+                if self
+                    .head
+                    .compare_exchange(
+                        protected_head.as_mut_ptr(),
+                        head_next,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    guard.retire_node(protected_head);
+                }
+                continue;
+            }
+
+            if idx >= protected_head.enqueue_idx.load(Ordering::Acquire) {
+                return None;
+            }
+            if protected_head
+                .dequeue_idx
+                .compare_exchange_weak(idx, idx + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_err()
+            {
+                backoff.spin();
+                continue;
+            }
+
+            // slot is ours, but its enqueuer may still be writing
+            let slot = &protected_head.slots[idx];
+            let mut write_backoff = Backoff::new();
+            while slot.state.load(Ordering::Acquire) != WRITTEN {
+                write_backoff.spin_yield();
+            }
+            return Some(unsafe { (*slot.value.get()).assume_init() });
+        }
+    }
+}
+
+impl<T: Copy, R: Reclaimer> Drop for SegQueue<T, R> {
+    fn drop(&mut self) {
+        // values are Copy, so only segments themselves need to be freed
+        let mut current = *self.head.get_mut();
+        while !current.is_null() {
+            let segment = unsafe { Box::from_raw(current) };
+            current = segment.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl<T: Copy + Send, R: Reclaimer> Sync for SegQueue<T, R> {}
+unsafe impl<T: Copy + Send, R: Reclaimer> Send for SegQueue<T, R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegQueue, SEGMENT_SIZE};
+    use crate::mechanisms::hp::HazardPointerArray;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_basic_operations() {
+        let hp_array = HazardPointerArray::new();
+        let q = SegQueue::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        assert_eq!(q.dequeue(&guard), None);
+
+        // spans several segments
+        let count = SEGMENT_SIZE * 3 + 5;
+        for i in 0..count {
+            q.enqueue(i, &guard);
+        }
+        let results = std::iter::from_fn(|| q.dequeue(&guard)).collect::<Vec<_>>();
+        assert_eq!(results, (0..count).collect::<Vec<_>>());
+
+        q.enqueue(count, &guard);
+        assert_eq!(q.dequeue(&guard), Some(count));
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let hp_array = HazardPointerArray::new();
+        let producers = 2;
+        let consumers = 2;
+        let per_producer = 5000;
+        let expected_values: HashSet<usize> = (0..producers * per_producer).collect();
+
+        for _ in 0..50 {
+            let q = SegQueue::new();
+            let q_ref = &q;
+            let hp_array_ref = &hp_array;
+            let collected_values = std::sync::Mutex::new(Vec::new());
+            let values_ref = &collected_values;
+
+            std::thread::scope(|s| {
+                for p in 0..producers {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..per_producer {
+                            q_ref.enqueue(p * per_producer + i, &guard);
+                        }
+                    });
+                }
+                for _ in 0..consumers {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let mut values = Vec::new();
+                        while values.len() < per_producer {
+                            if let Some(value) = q_ref.dequeue(&guard) {
+                                values.push(value);
+                            }
+                        }
+                        values_ref.lock().unwrap().append(&mut values);
+                    });
+                }
+            });
+
+            let actual_values: HashSet<usize> =
+                collected_values.into_inner().unwrap().into_iter().collect();
+            assert_eq!(actual_values, expected_values);
+        }
+    }
+
+    // declared HAZARDS_PER_OP is exactly the peak of the most demanding operation
+    #[test]
+    fn test_hazards_per_op() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let q = SegQueue::new();
+        let hazards = SegQueue::<usize>::HAZARDS_PER_OP;
+
+        guard.reset_peak_protected();
+        q.enqueue(1, &guard);
+        assert_eq!(guard.peak_protected(), hazards);
+
+        guard.reset_peak_protected();
+        assert_eq!(q.dequeue(&guard), Some(1));
+        assert_eq!(guard.peak_protected(), hazards);
+        assert_eq!(guard.protected_count(), 0);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::utils::cache_padded::CachePadded;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/*
+    bounded single-producer single-consumer ring buffer, e.g. for an audio thread feeding
+    a worker. slots are preallocated, so there are no nodes, and no hazard pointers, at all.
+    each index has exactly one writer, so there's no CAS either:
+    - head (next slot to write) is owned by producer, tail (next slot to read) by consumer;
+    - producer writes the slot, and only then publishes it with a Release store of head;
+      consumer Acquire-loads head, so the value is there by the time it reads the slot;
+    - the other way around, consumer moves the value out first, and only then frees the slot
+      with a Release store of tail, which producer Acquire-loads before reusing it.
+    indices grow forever (wrapping), slot is index & (capacity - 1), so capacity must be a power
+    of two. ring is full when head - tail == capacity, and empty when they are equal.
+    indices live on separate cache lines, so that producer and consumer don't invalidate each
+    other's line on every operation
+*/
+
+struct SpscRing<T> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+}
+
+// the only one of each, so neither is Clone, nor Sync: own index is never written by two threads
+pub struct Producer<T> {
+    ring: Arc<SpscRing<T>>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<SpscRing<T>>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+pub fn spsc_ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(
+        capacity.is_power_of_two(),
+        "spsc_ring(): capacity must be a power of two"
+    );
+    let ring = Arc::new(SpscRing {
+        buffer: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        mask: capacity - 1,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (
+        Producer {
+            ring: ring.clone(),
+            _not_sync: PhantomData,
+        },
+        Consumer {
+            ring,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Producer<T> {
+    // gives value back if ring is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        // own index, nobody else writes it
+        let head = ring.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(ring.tail.load(Ordering::Acquire)) == ring.buffer.len() {
+            return Err(value);
+        }
+        unsafe { (*ring.buffer[head & ring.mask].get()).write(value) };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.buffer.len()
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail == ring.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*ring.buffer[tail & ring.mask].get()).assume_init_read() };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    // exact for consumer itself, that only sees it grow under concurrent pushes
+    pub fn len(&self) -> usize {
+        let ring = &*self.ring;
+        ring.head
+            .load(Ordering::Acquire)
+            .wrapping_sub(ring.tail.load(Ordering::Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.buffer.len()
+    }
+}
+
+// both ends are gone by now, values between tail and head are still there
+impl<T> Drop for SpscRing<T> {
+    fn drop(&mut self) {
+        let head = *self.head.0.get_mut();
+        let mut tail = *self.tail.0.get_mut();
+        while tail != head {
+            unsafe { self.buffer[tail & self.mask].get_mut().assume_init_drop() };
+            tail = tail.wrapping_add(1);
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for SpscRing<T> {}
+unsafe impl<T: Send> Sync for SpscRing<T> {}
+// may be moved to another thread, but not shared
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::spsc_ring;
+
+    #[test]
+    fn test_basic_operations() {
+        let (mut producer, mut consumer) = spsc_ring(2);
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.push(1).is_ok());
+        assert!(producer.push(2).is_ok());
+        // full
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.len(), 2);
+        assert_eq!(consumer.pop(), Some(1));
+        assert!(producer.push(3).is_ok());
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        // empty
+        assert_eq!(consumer.pop(), None);
+        assert!(consumer.is_empty());
+
+        // leftovers are dropped with the ring
+        producer.push(4).unwrap();
+        drop((producer, consumer));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be a power of two")]
+    fn test_capacity_not_power_of_two() {
+        let _ = spsc_ring::<usize>(3);
+    }
+
+    // indices wrap around the ring many times, values arrive exactly once and in order
+    #[test]
+    fn test_producer_consumer() {
+        let (mut producer, mut consumer) = spsc_ring(16);
+        let count = 100_000;
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..count {
+                    let mut value = vec![i];
+                    while let Err(rejected) = producer.push(value) {
+                        value = rejected;
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            let mut expected = 0;
+            while expected < count {
+                match consumer.pop() {
+                    Some(value) => {
+                        assert_eq!(value, vec![expected]);
+                        expected += 1;
+                        assert!(consumer.len() <= consumer.capacity());
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert_eq!(consumer.pop(), None);
+    }
+}
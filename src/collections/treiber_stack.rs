@@ -1,44 +1,388 @@
-use crate::mechanisms::hp::{HazardPointerGuard, ProtectionError};
-use crate::utils::backoff::Backoff;
-use std::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+use crate::collections::cas::CasStrength;
+use crate::collections::frozen::{FrozenView, InsufficientSlots, PinnedView};
+use crate::mechanisms::hp::ProtectionError;
+use crate::mechanisms::reclaim::{HazardBudget, HpReclaimer, Protected, Reclaimer, ReclaimerGuard};
+use crate::utils::backoff::{Backoff, Contention};
+use crate::utils::cache_padded::CachePadded;
+use crate::utils::interleave::yield_point;
+use crate::utils::spin_wait::SpinWait;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use std::time::Duration;
 
 use rand::prelude::*;
+use rand::rngs::StdRng;
 
-const ELIMINATION_ARRAY_SIZE: usize = 8;
+// default number of elimination slots, see TreiberStack's ELIM
+const DEFAULT_ELIMINATION_SLOTS: usize = 8;
+// initial number of failed CASes before an operation falls back to elimination. the effective
+// threshold then adapts to contention, one step at a time, within the bounds below
 const ELIMINATION_THRESHOLD: u8 = 4;
+const MIN_ELIMINATION_THRESHOLD: u8 = 1;
+const MAX_ELIMINATION_THRESHOLD: u8 = 16;
+// how long a thread that has occupied an elimination slot waits for its counterpart. measured in
+// time rather than in loop iterations, so that it doesn't depend on how fast the core spins
+const ELIMINATION_WINDOW: Duration = Duration::from_nanos(500);
+// fallback bound on the wait, e.g. if reading the clock is unusually slow
+const ELIMINATION_MAX_SPINS: usize = DEFAULT_ELIMINATION_SLOTS * 64;
 
 // elimination array may have following states:
 const EMPTY: usize = 0;
 const POP: usize = 1;
 // + non-const state: pointer to node placed by push attempt - case slot was EMPTY
 // + non-const state: point to node placed by push attempt with LSB 1 - case slot was POP
+/*
+    and each state is tagged: slot's upper TAG_BITS count the writes to it, every write puts
+    the next tag there, so all CASes on a slot compare the whole tagged word. a node, that was
+    taken out of the slot and freed, may have its address reused by the next node placed into
+    the same slot, but with a different tag, so the pusher still waiting for the first one (or
+    giving up on it) no longer mistakes the second one for its own. addresses fit into the lower
+    48 bits on x86_64 and aarch64 (see StampedPtr), a node, that doesn't, is never placed into
+    a slot (see try_elimination_push). 32-bit targets have no bits to spare, so there slots stay
+    untagged (see elimination_versions for the diagnostics). tag wraps around after 2^16 writes,
+    same as StampedPtr's stamp
+*/
+const TAG_BITS: u32 = if usize::BITS == 64 { 16 } else { 0 };
+const STATE_MASK: usize = usize::MAX >> TAG_BITS;
+// 0 with no tag bits at all
+const TAG_ONE: usize = STATE_MASK.wrapping_add(1);
 
-pub struct TreiberStack<T> {
+/*
+    ELIM is the number of elimination slots: fewer slots make a push and a pop more likely to
+    pick the same one, when there are just a few threads to meet (e.g. on a low-core target),
+    more slots keep many threads from fighting over the same ones (e.g. on a 64-core box).
+    e.g. TreiberStack::<T, HpReclaimer, 2>::with_reclaimer()
+*/
+pub struct TreiberStack<T, R: Reclaimer = HpReclaimer, const ELIM: usize = DEFAULT_ELIMINATION_SLOTS> {
     head: AtomicPtr<StackNode<T>>,
-    elimination_array: [AtomicUsize; ELIMINATION_ARRAY_SIZE],
+    /*
+        values pushed minus values popped, see len(). updated right after the operation has
+        taken effect, so a pop may get there before the push of the very value it has popped:
+        signed, so that the count may go below zero for a moment, instead of wrapping around.
+        own cache line, so that updating it doesn't slow down the CAS on head
+    */
+    len: CachePadded<AtomicIsize>,
+    // every slot on its own cache line: threads meeting at different slots don't contend
+    elimination_array: [CachePadded<AtomicUsize>; ELIM],
+    /*
+        recent contention signal shared by all operations on the stack: every successful
+        elimination lowers it by one (contention is high enough for threads to meet, so go for
+        elimination sooner), every failed one raises it by one (not enough partners, stick to CAS
+        longer). since it moves gradually and isn't reset per operation, a single lucky or unlucky
+        elimination doesn't flip the stack between CAS-only and elimination modes
+    */
+    elimination_threshold: AtomicU8,
+    // false: plain Treiber stack, push and pop only ever retry CAS on head, see without_elimination()
+    elimination: bool,
+    // for retried CASes only, see CasStrength
+    cas_strength: CasStrength,
+    // retries of recent pushes and pops, if their backoff adapts to it, see with_adaptive_backoff()
+    contention: Option<Contention>,
+    /*
+        ABA diagnostics, debug builds only. pusher waits for its node to be taken out of the slot,
+        comparing slot with node's address. if popper takes the node (and frees it), and another
+        push allocates its node at the same address and places it into the same slot while we
+        weren't looking, the slot looks untouched to us: we'd take that other node out of the slot
+        on give-up, and push our own (long gone) node again. so every node taken out of a slot by
+        a popper bumps slot's version, and a pusher, that has given up, checks that the version is
+        the same it saw after placing its node. slot tags (see TAG_BITS) rule that out, unless the
+        tag has wrapped around in the meantime, or the target has no bits for it, so this is what
+        is left to catch. release builds skip all of it
+    */
+    #[cfg(debug_assertions)]
+    elimination_versions: [AtomicUsize; ELIM],
+    #[cfg(debug_assertions)]
+    aba_detected: AtomicUsize,
+    _reclaimer: PhantomData<R>,
 }
 
+/*
+    value is moved out of the node right after the node is unlinked, while the node itself is
+    freed later, by reclamation, so data is never dropped along with the node. moving out with
+    a bitwise read (instead of mem::take()) is what lets T be anything, including types without
+    Default, like Box<dyn Fn()>: node pointer, that pop CASes and hp protects, is thin anyway,
+    whatever is inside
+*/
 struct Node<T> {
-    data: T,
+    data: ManuallyDrop<T>,
     next: AtomicPtr<StackNode<T>>,
 }
 
 #[repr(transparent)]
 pub struct StackNode<T>(Node<T>);
 
-impl<T> TreiberStack<T>
+impl<T: Send> Default for TreiberStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> TreiberStack<T> {
+    pub fn new() -> Self {
+        Self::with_reclaimer()
+    }
+
+    /*
+        plain Treiber stack, for debugging: push and pop never fall back to elimination, and just
+        keep retrying CAS on head (with backoff), so the elimination array is never touched. e.g.
+        to tell whether a bug is in elimination, or in the stack itself
+    */
+    pub fn without_elimination() -> Self {
+        let mut stack = Self::with_reclaimer();
+        stack.elimination = false;
+        stack
+    }
+}
+
+impl<T, R, const ELIM: usize> TreiberStack<T, R, ELIM>
 where
-    T: Default,
+    T: Send,
+    R: Reclaimer,
 {
-    pub fn new() -> Self {
+    // max number of pointers a single operation protects at once (pop: head only).
+    // hazard pointer guards used with this stack need at least that many free slots
+    pub const HAZARDS_PER_OP: usize = 1;
+
+    // e.g. TreiberStack::<T, EbrReclaimer>::with_reclaimer()
+    pub fn with_reclaimer() -> Self {
+        const { assert!(ELIM > 0, "TreiberStack: there must be at least one elimination slot") };
         Self {
             head: AtomicPtr::new(std::ptr::null_mut()),
-            elimination_array: [const { AtomicUsize::new(0) }; ELIMINATION_ARRAY_SIZE],
+            len: CachePadded(AtomicIsize::new(0)),
+            elimination_array: [const { CachePadded(AtomicUsize::new(0)) }; ELIM],
+            elimination_threshold: AtomicU8::new(ELIMINATION_THRESHOLD),
+            elimination: true,
+            cas_strength: CasStrength::Weak,
+            contention: None,
+            #[cfg(debug_assertions)]
+            elimination_versions: [const { AtomicUsize::new(0) }; ELIM],
+            #[cfg(debug_assertions)]
+            aba_detected: AtomicUsize::new(0),
+            _reclaimer: PhantomData,
+        }
+    }
+
+    // number of elimination ABA occurrences detected so far, see elimination_versions
+    #[cfg(debug_assertions)]
+    pub fn aba_detected(&self) -> usize {
+        self.aba_detected.load(Ordering::Relaxed)
+    }
+
+    // e.g. TreiberStack::new().with_cas_strength(CasStrength::Strong)
+    pub fn with_cas_strength(mut self, cas_strength: CasStrength) -> Self {
+        self.cas_strength = cas_strength;
+        self
+    }
+
+    // e.g. TreiberStack::new().with_elimination_threshold(8): where the adaptive threshold
+    // (see elimination_threshold) starts from, instead of ELIMINATION_THRESHOLD
+    pub fn with_elimination_threshold(mut self, threshold: u8) -> Self {
+        assert!(
+            (MIN_ELIMINATION_THRESHOLD..=MAX_ELIMINATION_THRESHOLD).contains(&threshold),
+            "TreiberStack: elimination threshold must be within {}..={}",
+            MIN_ELIMINATION_THRESHOLD,
+            MAX_ELIMINATION_THRESHOLD
+        );
+        *self.elimination_threshold.get_mut() = threshold;
+        self
+    }
+
+    /*
+        e.g. TreiberStack::new().with_adaptive_backoff(): push and pop size their backoff between
+        failed CASes on head by how many retries recent operations on this stack have needed (see
+        Backoff::adaptive()), instead of always starting from a single spin. costs a relaxed load
+        and a relaxed store of the shared average per operation
+    */
+    pub fn with_adaptive_backoff(mut self) -> Self {
+        self.contention = Some(Contention::new());
+        self
+    }
+
+    // backoff between failed CASes on head
+    fn head_backoff(&self) -> Backoff {
+        match &self.contention {
+            Some(contention) => Backoff::adaptive(contention),
+            None => Backoff::new(),
+        }
+    }
+
+    // called once push or pop has succeeded, whether by CAS or by elimination
+    fn record_contention(&self, backoff: &Backoff) {
+        if let Some(contention) = &self.contention {
+            contention.record(backoff.retries());
+        }
+    }
+
+    /*
+        single-threaded fast path, e.g. for bulk initialization before the stack is shared, or
+        for teardown. &mut self guarantees nobody else is accessing the stack, so we skip CAS
+        loops, backoff and reclamation entirely and work with plain loads and stores via get_mut()
+    */
+    pub fn push_mut(&mut self, data: T) {
+        let head = self.head.get_mut();
+        let new_node = Box::into_raw(Box::new(Node {
+            data: ManuallyDrop::new(data),
+            next: AtomicPtr::new(*head),
+        })) as *mut StackNode<T>;
+        *head = new_node;
+        *self.len.0.get_mut() += 1;
+    }
+
+    // see push_mut()
+    pub fn pop_mut(&mut self) -> Option<T> {
+        let head = self.head.get_mut();
+        if head.is_null() {
+            return None;
+        }
+        // nobody can hold the node we're taking off: freeing it right away
+        let mut node = unsafe { Box::from_raw(*head as *mut Node<T>) };
+        *head = *node.next.get_mut();
+        *self.len.0.get_mut() -= 1;
+        Some(ManuallyDrop::into_inner(node.data))
+    }
+
+    /*
+        approximate number of values in the stack, e.g. for logging how deep it gets under load.
+        it's a separate counter, that every push and pop updates right after it has taken effect,
+        not a walk of the stack, so it's cheap, but under concurrent operations it's only a hint:
+        it may lag behind by as many operations as there are in flight (and reads 0, if pops are
+        ahead of the pushes, that they've popped from). an eliminated pair counts as a push and
+        a pop, same as if it went through the stack. exact, once operations are over
+    */
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed).max(0) as usize
+    }
+
+    // unlike len(), exact at the moment of the head load
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    /*
+        structural check for tests, run in a single-threaded phase (hence &mut self): head chain
+        must be acyclic. every node is remembered, so a cycle is reported as soon as the walk
+        gets back to a visited node, instead of looping forever. returns number of nodes
+    */
+    #[cfg(test)]
+    pub(crate) fn check_invariants(&mut self) -> usize {
+        let mut visited = std::collections::HashSet::new();
+        let mut current = *self.head.get_mut();
+        while !current.is_null() {
+            assert!(visited.insert(current), "TreiberStack: cycle in head chain at {:p}", current);
+            current = unsafe { (*current).0.next.load(Ordering::Relaxed) };
+        }
+        visited.len()
+    }
+
+    // see MSQueue::sync_fence() for the transition protocol between exclusive and shared use
+    pub fn sync_fence(&mut self) {
+        fence(Ordering::Release);
+    }
+
+    // see MSQueue::sync_fence()
+    pub fn quiesce(&mut self) {
+        fence(Ordering::Acquire);
+        debug_assert!(
+            self.elimination_array
+                .iter_mut()
+                .all(|slot| *slot.0.get_mut() & STATE_MASK == EMPTY),
+            "TreiberStack::quiesce(): elimination exchange is still in flight"
+        );
+    }
+
+    /*
+        batch push with a single successful CAS: nodes are linked into a local chain first (no
+        atomics contended), then the whole chain is spliced on top of the stack at once, so other
+        threads see either none or all of the items. ordering within the batch is the same as
+        if items were pushed one by one: the last item ends up on top and is popped first.
+        elimination isn't tried, since a single pop can't take the whole batch
+    */
+    pub fn push_all(&self, items: impl IntoIterator<Item = T>) {
+        let Some((top, bottom, count)) = Self::build_chain(items) else {
+            return;
+        };
+
+        let mut backoff = Backoff::new();
+        loop {
+            let head = self.head.load(Ordering::Relaxed);
+            unsafe { (*bottom).0.next.store(head, Ordering::Relaxed) };
+            if self
+                .cas_strength
+                .compare_exchange(&self.head, head, top, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.len.fetch_add(count as isize, Ordering::Relaxed);
+                return;
+            }
+            backoff.spin();
+        }
+    }
+
+    /*
+        replaces the whole stack at once, e.g. for double-buffering: other threads see either the
+        old contents or the new ones (built the same way as in push_all(), the last item on top),
+        never a mix. an empty items clears the stack. returns the old contents, top to bottom.
+        old nodes are unlinked by a single swap of head, but concurrent pops may have protected
+        the old top just before and still read its next, so the nodes themselves can't be freed
+        right away: values are moved out of them, and nodes are retired with guard. hence guard,
+        though nothing is protected here. same as with pop, values are moved out of nodes, that
+        an alive FrozenView may pin (hence it only pins Copy values)
+    */
+    pub fn swap_all(
+        &self,
+        guard: &R::Guard<'_, StackNode<T>>,
+        items: impl IntoIterator<Item = T>,
+    ) -> Vec<T> {
+        let (new_top, new_count) = Self::build_chain(items)
+            .map_or((std::ptr::null_mut(), 0), |(top, _, count)| (top, count));
+        let mut current = self.head.swap(new_top, Ordering::AcqRel);
+        let mut old_contents = Vec::new();
+        while !current.is_null() {
+            // unreachable from head now: we are the only ones to take values out
+            let node = unsafe { &mut (*current).0 };
+            old_contents.push(unsafe { ManuallyDrop::take(&mut node.data) });
+            let next = node.next.load(Ordering::Relaxed);
+            guard.retire_raw_pointer(current);
+            current = next;
         }
+        self.len.fetch_add(new_count as isize - old_contents.len() as isize, Ordering::Relaxed);
+        old_contents
+    }
+
+    // local chain of the items, the last one on top: (top, bottom, count), None for no items
+    fn build_chain(
+        items: impl IntoIterator<Item = T>,
+    ) -> Option<(*mut StackNode<T>, *mut StackNode<T>, usize)> {
+        let mut items = items.into_iter();
+        let mut count = 1;
+        let bottom = Box::into_raw(Box::new(Node {
+            data: ManuallyDrop::new(items.next()?),
+            next: AtomicPtr::new(std::ptr::null_mut()),
+        })) as *mut StackNode<T>;
+        let top = items.fold(bottom, |top, data| {
+            count += 1;
+            Box::into_raw(Box::new(Node {
+                data: ManuallyDrop::new(data),
+                next: AtomicPtr::new(top),
+            })) as *mut StackNode<T>
+        });
+        Some((top, bottom, count))
     }
 
     // no safe reclamation needed for push method, since we don't dereference pointers here
     pub fn push(&self, data: T) {
+        self.push_detect_empty(data);
+    }
+
+    /*
+        push, that tells whether it has made the stack non-empty: true iff the head its successful
+        CAS has replaced was null. e.g. a producer may wake a parked consumer on that edge only.
+        an eliminated push never counts as one: its value goes right to a concurrent pop and never
+        shows up in the stack, whatever the stack looked like at that moment
+    */
+    pub fn push_detect_empty(&self, data: T) -> bool {
         // assert_ne!(
         //     align_of::<T>() & 1,
         //     1,
@@ -46,11 +390,11 @@ where
         // );
 
         let new_node = Box::into_raw(Box::new(Node {
-            data,
+            data: ManuallyDrop::new(data),
             next: AtomicPtr::new(std::ptr::null_mut()),
         })) as *mut StackNode<T>;
 
-        let mut backoff = Backoff::new();
+        let mut backoff = self.head_backoff();
         let mut loop_counter = 0;
 
         loop {
@@ -59,32 +403,108 @@ where
 
             // try to swap in our node as the new head
             if self
-                .head
-                .compare_exchange_weak(head, new_node, Ordering::Release, Ordering::Relaxed)
+                .cas_strength
+                .compare_exchange(&self.head, head, new_node, Ordering::Release, Ordering::Relaxed)
                 .is_ok()
             {
-                return;
+                self.len.fetch_add(1, Ordering::Relaxed);
+                self.record_contention(&backoff);
+                return head.is_null();
             }
-            if loop_counter < ELIMINATION_THRESHOLD {
+            if !self.elimination {
+                backoff.spin();
+            } else if loop_counter < self.elimination_threshold.load(Ordering::Relaxed) {
                 backoff.spin();
                 loop_counter += 1;
             } else {
-                match self.try_elimination_push(new_node as *mut Node<T>) {
-                    Ok(_) => return,
-                    // actual error doesn't matter here, we just start again
-                    Err(_) => {
-                        loop_counter = 0;
-                        backoff.reset();
-                    }
+                let eliminated = self.try_elimination_push(new_node as *mut Node<T>).is_ok();
+                self.adapt_elimination_threshold(eliminated);
+                if eliminated {
+                    // counted here, and then by the popper, that has taken it, as any other pop
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                    self.record_contention(&backoff);
+                    return false;
                 }
+                // actual error doesn't matter here, we just retry. keep half of the progress
+                // towards the next elimination attempt, instead of starting from scratch
+                loop_counter /= 2;
+                backoff.reset();
             }
         }
     }
 
     // user should register thread for hp guard obtaining
-    pub fn pop(&self, guard: &HazardPointerGuard<StackNode<T>>) -> Option<T> {
+    pub fn pop(&self, guard: &R::Guard<'_, StackNode<T>>) -> Option<T> {
+        self.pop_with_policy(guard, PopPolicy::ReturnEmpty)
+    }
+
+    /*
+        pop paired with a mandatory handler, e.g. for returning a pooled buffer: f gets the value
+        by value, exactly once, if there was one, and isn't called at all on an empty stack.
+        returns whether a value was popped. the value is off the stack before f runs, so if f
+        panics, the value is dropped during unwinding (as part of f's arguments), and never
+        goes back to the stack
+    */
+    pub fn pop_scoped(&self, guard: &R::Guard<'_, StackNode<T>>, f: impl FnOnce(T)) -> bool {
+        match self.pop(guard) {
+            Some(value) => {
+                f(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /*
+        single pop attempt for callers with a scheduling policy of their own, e.g. a polling
+        scheduler, that would rather move on to other work than spin here: one protection of
+        head, one strong CAS (spurious failure would be reported as contention), no backoff and
+        no elimination. unlike PopPolicy::NonBlocking, tells empty stack (Ok(None)) apart from
+        a lost race (Contended), so the caller knows whether trying again makes sense right away
+    */
+    pub fn try_pop(&self, guard: &R::Guard<'_, StackNode<T>>) -> Result<Option<T>, TryPopError> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "TreiberStack::try_pop()");
+        yield_point();
+        let head_ptr = self.head.load(Ordering::Relaxed);
+        let mut protected_head = match unsafe { guard.protect(head_ptr) } {
+            Ok(ptr) => ptr,
+            Err(ProtectionError::NoAvailableIndices) => {
+                return Err(TryPopError::NoAvailableIndices)
+            }
+            Err(ProtectionError::NullPointer) => return Ok(None),
+        };
+        if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
+            return Err(TryPopError::Contended);
+        }
+
+        let next = protected_head.0.next.load(Ordering::Relaxed);
+        yield_point();
+        match self.head.compare_exchange(
+            protected_head.as_mut_ptr(),
+            next,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                let data = unsafe { ManuallyDrop::take(&mut protected_head.0.data) };
+                guard.retire_node(protected_head);
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                Ok(Some(data))
+            }
+            Err(_) => Err(TryPopError::Contended),
+        }
+    }
+
+    // see PopPolicy for what happens on empty and contended stack
+    pub fn pop_with_policy(
+        &self,
+        guard: &R::Guard<'_, StackNode<T>>,
+        policy: PopPolicy,
+    ) -> Option<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "TreiberStack::pop()");
         let mut hp_backoff = Backoff::new();
-        let mut cas_backoff = Backoff::new();
+        let mut cas_backoff = self.head_backoff();
+        let mut empty_backoff = Backoff::new();
         let mut loop_couter = 0;
 
         loop {
@@ -94,103 +514,401 @@ where
             let mut protected_head = match unsafe { guard.protect(head_ptr) } {
                 Ok(ptr) => ptr,
                 Err(ProtectionError::NoAvailableIndices) => {
+                    if policy == PopPolicy::NonBlocking {
+                        return None;
+                    }
                     hp_backoff.spin();
                     continue; // no hazard pointer slots available, retry
                 }
-                Err(ProtectionError::NullPointer) => return None,
+                Err(ProtectionError::NullPointer) => {
+                    if policy == PopPolicy::SpinUntilItem {
+                        empty_backoff.spin_yield();
+                        continue;
+                    }
+                    return None;
+                }
             };
             hp_backoff.reset();
+            empty_backoff.reset();
 
             // recheck head hasn't changed
             if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
+                if policy == PopPolicy::NonBlocking {
+                    return None;
+                }
                 continue;
             }
 
             // safely read the next pointer of the head node
-            let next = (*protected_head).0.next.load(Ordering::Relaxed);
+            let next = protected_head.0.next.load(Ordering::Relaxed);
 
-            // try to update the head to the next node
-            if self
-                .head
-                .compare_exchange_weak(
+            // try to update the head to the next node.
+            // single-attempt pop can't afford spurious failures, so it goes with strong CAS
+            let cas_result = if policy == PopPolicy::NonBlocking {
+                self.head.compare_exchange(
                     protected_head.as_mut_ptr(),
                     next,
                     Ordering::AcqRel,
                     Ordering::Relaxed,
                 )
-                .is_ok()
-            {
+            } else {
+                self.cas_strength.compare_exchange(
+                    &self.head,
+                    protected_head.as_mut_ptr(),
+                    next,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+            };
+            if cas_result.is_ok() {
                 // successfully popped the node
                 // now return data and retirement
-                let data = std::mem::take(&mut (*protected_head).0.data);
+                let data = unsafe { ManuallyDrop::take(&mut protected_head.0.data) };
                 guard.retire_node(protected_head);
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                self.record_contention(&cas_backoff);
                 return Some(data);
             }
 
-            if loop_couter < ELIMINATION_THRESHOLD {
+            if policy == PopPolicy::NonBlocking {
+                return None;
+            }
+
+            if !self.elimination {
+                cas_backoff.spin();
+            } else if loop_couter < self.elimination_threshold.load(Ordering::Relaxed) {
                 loop_couter += 1;
                 cas_backoff.spin();
             } else {
-                match self.try_elimination_pop() {
-                    Ok(data) => return Some(data),
+                let eliminated = self.try_elimination_pop();
+                self.adapt_elimination_threshold(eliminated.is_ok());
+                if let Ok(data) = eliminated {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    self.record_contention(&cas_backoff);
+                    return Some(data);
+                }
+                loop_couter /= 2;
+                cas_backoff.reset();
+            }
+        }
+    }
+
+    // identity of the current top node (null for empty stack), e.g. for pop_if_top_is().
+    // it's only meant for comparison: must not be dereferenced, unless protected with guard first
+    pub fn top_node(&self) -> *const StackNode<T> {
+        self.head.load(Ordering::Acquire)
+    }
+
+    /*
+        calls f on the top value without popping it, e.g. for a monitoring thread, and returns
+        what f returns, or None if the stack is empty. head is protected and rechecked the same
+        way pop does it, so the node can't be freed while f looks at it. it may be popped in the
+        meantime though: f sees the value, that was on top at some point during the call.
+        hence T: Copy. protection keeps the node, not the value: a concurrent pop moves the value
+        out while f may still be reading it, and whatever a non-Copy value owns (a String's
+        buffer, say) could be freed by the popper right under f. moving a Copy value out is
+        just another read
+    */
+    pub fn peek<U>(&self, guard: &R::Guard<'_, StackNode<T>>, f: impl FnOnce(&T) -> U) -> Option<U>
+    where
+        T: Copy,
+    {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "TreiberStack::peek()");
+        let mut hp_backoff = Backoff::new();
+        loop {
+            let head_ptr = self.head.load(Ordering::Acquire);
+            let protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => ptr,
+                Err(ProtectionError::NoAvailableIndices) => {
+                    hp_backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => return None,
+            };
+            // recheck head hasn't changed
+            if self.head.load(Ordering::Acquire) != protected_head.as_mut_ptr() {
+                continue;
+            }
+            return Some(f(&protected_head.0.data));
+        }
+    }
+
+    /*
+        calls f on the values of the top k nodes, top first, and returns how many it has visited:
+        fewer than k, if the stack is shorter. nothing is cloned, nodes are walked hand-over-hand
+        (next one is protected before the current one is released), with the top node protected
+        all the way, so that its address isn't reused. walk is valid for as long as head stays
+        the same: nodes below the top can't be popped before the top itself is. weakly consistent
+        though: if head moves before anything is visited, walk starts over, but once f has seen
+        some values, a concurrent push or pop ends it early, since a restart would show them to
+        f twice. protects up to 3 pointers at once. T: Copy for the same reason as in peek():
+        a visited value may be popped while f is reading it
+    */
+    pub fn peek_top_k(
+        &self,
+        guard: &R::Guard<'_, StackNode<T>>,
+        k: usize,
+        mut f: impl FnMut(&T),
+    ) -> usize
+    where
+        T: Copy,
+    {
+        if k == 0 {
+            return 0;
+        }
+        let mut backoff = Backoff::new();
+        let (top, protected_top) = loop {
+            let top = self.head.load(Ordering::Acquire);
+            let protected_top = match unsafe { guard.protect(top) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => return 0,
+            };
+            if self.head.load(Ordering::Acquire) == top {
+                break (top, protected_top);
+            }
+        };
+        f(&protected_top.0.data);
+        let mut visited = 1;
+        let mut current = None;
+        let mut next = protected_top.0.next.load(Ordering::Acquire);
+        while visited < k && !next.is_null() {
+            let protected_next = match unsafe { guard.protect(next) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(_) => {
+                    backoff.spin();
+                    continue;
+                }
+            };
+            if self.head.load(Ordering::Acquire) != top {
+                break;
+            }
+            f(&protected_next.0.data);
+            visited += 1;
+            next = protected_next.0.next.load(Ordering::Acquire);
+            current = Some(protected_next);
+        }
+        drop(current);
+        visited
+    }
+
+    /*
+        conditional pop for protocols built on top of the stack: pops only if the top node is
+        still the expected one (null expects empty stack, then Ok(None) is returned), otherwise
+        fails with CasFailed without retrying. it's keyed on node identity, not on value, so
+        pushing an equal value in the meantime doesn't fool it. address reuse still could: if
+        expected node gets popped and freed, a new node may be allocated at the same address.
+        protecting expected node with guard for as long as it's held rules that out, since
+        protected node is never freed.
+        no elimination here: exchanged value never was the top node
+    */
+    pub fn pop_if_top_is(
+        &self,
+        guard: &R::Guard<'_, StackNode<T>>,
+        expected: *const StackNode<T>,
+    ) -> Result<Option<T>, CasFailed> {
+        let _budget =
+            HazardBudget::new(guard, Self::HAZARDS_PER_OP, "TreiberStack::pop_if_top_is()");
+        let mut hp_backoff = Backoff::new();
+        loop {
+            let head_ptr = self.head.load(Ordering::Relaxed);
+            if !std::ptr::eq(head_ptr, expected) {
+                return Err(CasFailed);
+            }
+            let mut protected_head = match unsafe { guard.protect(head_ptr) } {
+                Ok(ptr) => ptr,
+                Err(ProtectionError::NoAvailableIndices) => {
+                    hp_backoff.spin();
+                    continue;
+                }
+                // empty stack was expected
+                Err(ProtectionError::NullPointer) => return Ok(None),
+            };
+            if self.head.load(Ordering::Relaxed) != protected_head.as_mut_ptr() {
+                return Err(CasFailed);
+            }
+
+            let next = protected_head.0.next.load(Ordering::Relaxed);
+            // strong CAS: spurious failure would be reported as a lost race
+            return match self.head.compare_exchange(
+                protected_head.as_mut_ptr(),
+                next,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    let data = unsafe { ManuallyDrop::take(&mut protected_head.0.data) };
+                    guard.retire_node(protected_head);
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    Ok(Some(data))
+                }
+                Err(_) => Err(CasFailed),
+            };
+        }
+    }
+
+    /*
+        consistent snapshot of the whole stack, top to bottom, with every node pinned for as long
+        as the view lives (see FrozenView). nodes below the top are never changed while they are
+        in the stack, so it's enough to check, that head stayed the same while we walked down:
+        if it did, none of the walked nodes has been popped (and freed) before we protected it.
+        top node is pinned as well, so its address can't be reused by another push in the meantime.
+        any pop during the walk restarts it, pushes only do, if they land before the final check.
+        needs a free hazard slot per element, otherwise gives up with InsufficientSlots.
+        T: Copy, since pinned values may still be popped, see FrozenView
+    */
+    pub fn freeze<'g, 'a>(
+        &self,
+        guard: &'g R::Guard<'a, StackNode<T>>,
+    ) -> Result<PinnedView<'g, 'a, T, StackNode<T>, R>, InsufficientSlots>
+    where
+        T: Copy,
+    {
+        let mut backoff = Backoff::new();
+        'snapshot: loop {
+            let top = self.head.load(Ordering::Acquire);
+            let mut pinned = Vec::new();
+            let mut current = top;
+            while !current.is_null() {
+                let protected = match unsafe { guard.protect(current) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
+                    Err(ProtectionError::NoAvailableIndices) => return Err(InsufficientSlots),
+                    Err(ProtectionError::NullPointer) => unreachable!(),
+                };
+                if self.head.load(Ordering::Acquire) != top {
+                    backoff.spin();
+                    continue 'snapshot;
+                }
+                current = protected.0.next.load(Ordering::Acquire);
+                pinned.push(protected);
+            }
+            if self.head.load(Ordering::Acquire) != top {
+                backoff.spin();
+                continue;
+            }
+            let items = pinned.iter().map(|node| &*node.0.data as *const T).collect();
+            return Ok(FrozenView::pinned(pinned, items));
+        }
+    }
+
+    // freeze(), that falls back to cloning values out when there are not enough slots to pin
+    // them all. cloned snapshot is just as consistent, but only takes 3 slots at a time
+    pub fn freeze_or_clone<'g, 'a>(
+        &self,
+        guard: &'g R::Guard<'a, StackNode<T>>,
+    ) -> PinnedView<'g, 'a, T, StackNode<T>, R>
+    where
+        T: Copy,
+    {
+        if let Ok(view) = self.freeze(guard) {
+            return view;
+        }
+        let mut backoff = Backoff::new();
+        'snapshot: loop {
+            let top = self.head.load(Ordering::Acquire);
+            // top is kept protected all the way, so that its address isn't reused
+            let protected_top = match unsafe { guard.protect(top) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => return FrozenView::cloned(Vec::new()),
+            };
+            if self.head.load(Ordering::Acquire) != top {
+                continue;
+            }
+            let mut values = vec![*protected_top.0.data];
+            // hand-over-hand: next node is protected before the current one is released
+            let mut current = None;
+            let mut next = protected_top.0.next.load(Ordering::Acquire);
+            while !next.is_null() {
+                let protected_next = match unsafe { guard.protect(next) } {
+                    Ok(ptr) => {
+                        fence(Ordering::Acquire);
+                        ptr
+                    }
                     Err(_) => {
-                        loop_couter = 0;
-                        cas_backoff.reset();
+                        backoff.spin();
+                        continue 'snapshot;
                     }
+                };
+                if self.head.load(Ordering::Acquire) != top {
+                    continue 'snapshot;
                 }
+                values.push(*protected_next.0.data);
+                next = protected_next.0.next.load(Ordering::Acquire);
+                current = Some(protected_next);
+            }
+            drop(current);
+            if self.head.load(Ordering::Acquire) != top {
+                continue;
             }
+            return FrozenView::cloned(values);
         }
     }
 
-    fn try_elimination_push(&self, node: *mut Node<T>) -> Result<(), EliminationError> {
-        let mut rng = rand::rng();
+    // see elimination_threshold
+    fn adapt_elimination_threshold(&self, eliminated: bool) {
+        _ = self
+            .elimination_threshold
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |threshold| {
+                if eliminated {
+                    Some(threshold.saturating_sub(1).max(MIN_ELIMINATION_THRESHOLD))
+                } else {
+                    Some((threshold + 1).min(MAX_ELIMINATION_THRESHOLD))
+                }
+            });
+    }
 
-        for _ in 0..ELIMINATION_ARRAY_SIZE {
-            let slot_id: usize = rng.random_range(0..ELIMINATION_ARRAY_SIZE);
-            match self.elimination_array[slot_id].load(Ordering::Relaxed) {
+    fn try_elimination_push(&self, node: *mut Node<T>) -> Result<(), EliminationError> {
+        // address beyond 48 bits (e.g. 5-level paging) would be cut by the tag: such a node
+        // skips elimination and goes through the stack itself
+        if node as usize & !STATE_MASK != 0 {
+            return Err(EliminationError::NoSlotsAvailable);
+        }
+        for _ in 0..ELIM {
+            let slot_id = random_slot(ELIM);
+            let slot_value = self.elimination_array[slot_id].load(Ordering::Relaxed);
+            match slot_value & STATE_MASK {
                 EMPTY => {
                     fence(Ordering::Acquire);
+                    let placed = next_tag(slot_value) | node as usize;
                     if self.elimination_array[slot_id]
                         .compare_exchange(
-                            EMPTY,
-                            node as usize,
+                            slot_value,
+                            placed,
                             Ordering::Release,
                             Ordering::Relaxed,
                         )
                         .is_ok()
                     {
+                        let version = self.elimination_version(slot_id);
                         std::thread::yield_now();
-                        let attempts = ELIMINATION_ARRAY_SIZE * 4;
-                        for _ in 0..attempts {
-                            let slot_value =
-                                self.elimination_array[slot_id].load(Ordering::Relaxed);
-
-                            // nano chances are we can face ABA here
-                            if slot_value == node as usize {
-                                std::hint::spin_loop();
-                                continue;
-                            }
-                            fence(Ordering::Acquire);
-                            return Ok(());
-                        }
-
-                        return match self.elimination_array[slot_id].compare_exchange(
-                            node as usize,
-                            EMPTY,
-                            Ordering::Release,
-                            Ordering::Relaxed,
-                        ) {
-                            Ok(_) => Err(EliminationError::NoRendezvous),
-                            Err(_) => Ok(()),
-                        };
+                        return self.await_elimination_pop(slot_id, placed, version);
                     }
                 }
                 POP => {
                     fence(Ordering::Acquire);
                     if self.elimination_array[slot_id]
                         .compare_exchange(
-                            POP,
-                            node as usize | 1,
+                            slot_value,
+                            next_tag(slot_value) | node as usize | 1,
                             Ordering::Release,
                             Ordering::Relaxed,
                         )
@@ -208,47 +926,103 @@ where
         Err(EliminationError::NoSlotsAvailable)
     }
 
-    fn try_elimination_pop(&self) -> Result<T, EliminationError> {
-        let mut rng = rand::rng();
+    // pusher's side of the rendezvous, once its node is placed (tagged) into the EMPTY slot.
+    // a reused address comes with another tag, see TAG_BITS, and elimination_versions for
+    // the case, where the tag has wrapped around in the meantime
+    fn await_elimination_pop(
+        &self,
+        slot_id: usize,
+        placed: usize,
+        version: usize,
+    ) -> Result<(), EliminationError> {
+        if await_rendezvous(&self.elimination_array[slot_id], placed).is_some() {
+            return Ok(());
+        }
+        match self.elimination_array[slot_id].compare_exchange(
+            placed,
+            next_tag(placed) | EMPTY,
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                self.check_elimination_aba(slot_id, version);
+                Err(EliminationError::NoRendezvous)
+            }
+            Err(_) => Ok(()),
+        }
+    }
+
+    // popper's side: takes node placed by pusher out of the slot
+    fn try_take_pushed(&self, slot_id: usize, slot_value: usize) -> bool {
+        if self.elimination_array[slot_id]
+            .compare_exchange(
+                slot_value,
+                next_tag(slot_value) | EMPTY,
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            self.bump_elimination_version(slot_id);
+            return true;
+        }
+        false
+    }
+
+    // always 0 in release builds
+    fn elimination_version(&self, _slot_id: usize) -> usize {
+        #[cfg(debug_assertions)]
+        return self.elimination_versions[_slot_id].load(Ordering::Acquire);
+        #[cfg(not(debug_assertions))]
+        0
+    }
+
+    fn bump_elimination_version(&self, _slot_id: usize) {
+        #[cfg(debug_assertions)]
+        self.elimination_versions[_slot_id].fetch_add(1, Ordering::Release);
+    }
 
-        for _ in 0..ELIMINATION_ARRAY_SIZE {
-            let slot_id: usize = rng.random_range(0..ELIMINATION_ARRAY_SIZE);
-            match self.elimination_array[slot_id].load(Ordering::Relaxed) {
+    // called by pusher, that has taken a node with its node's address out of the slot. a version
+    // mismatch means its node was taken by a popper, and another node at the same address has
+    // taken its place: counted only, for tests (and users) to check with aba_detected()
+    fn check_elimination_aba(&self, _slot_id: usize, _version: usize) {
+        #[cfg(debug_assertions)]
+        if self.elimination_version(_slot_id) != _version {
+            self.aba_detected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn try_elimination_pop(&self) -> Result<T, EliminationError> {
+        for _ in 0..ELIM {
+            let slot_id = random_slot(ELIM);
+            let slot_value = self.elimination_array[slot_id].load(Ordering::Relaxed);
+            match slot_value & STATE_MASK {
                 EMPTY => {
                     fence(Ordering::Acquire);
+                    let placed = next_tag(slot_value) | POP;
                     if self.elimination_array[slot_id]
-                        .compare_exchange(EMPTY, POP, Ordering::Release, Ordering::Relaxed)
+                        .compare_exchange(slot_value, placed, Ordering::Release, Ordering::Relaxed)
                         .is_ok()
                     {
                         std::thread::yield_now();
-                        let attempts = ELIMINATION_ARRAY_SIZE * 4;
                         // how we are waiting if some push updated the slot
-                        for _ in 0..attempts {
-                            let slot_value =
-                                self.elimination_array[slot_id].load(Ordering::Relaxed);
-                            if slot_value == POP {
-                                std::hint::spin_loop();
-                                continue;
-                            }
-                            fence(Ordering::Acquire);
-                            let node_ptr = (slot_value & !1) as *mut Node<T>;
-                            self.elimination_array[slot_id].store(EMPTY, Ordering::Release);
-                            return Ok(unsafe { Box::from_raw(node_ptr) }.data);
+                        if let Some(slot_value) =
+                            await_rendezvous(&self.elimination_array[slot_id], placed)
+                        {
+                            return Ok(self.take_handed_over(slot_id, slot_value));
                         }
 
                         //okay, give up, if nothing changed
                         match self.elimination_array[slot_id].compare_exchange(
-                            POP,
-                            EMPTY,
+                            placed,
+                            next_tag(placed) | EMPTY,
                             Ordering::Release,
                             Ordering::Relaxed,
                         ) {
                             Ok(_) => return Err(EliminationError::NoRendezvous),
                             Err(slot_value) => {
                                 fence(Ordering::Acquire);
-                                let node_ptr = (slot_value & !1) as *mut Node<T>;
-                                self.elimination_array[slot_id].store(EMPTY, Ordering::Release);
-                                return Ok(unsafe { Box::from_raw(node_ptr) }.data);
+                                return Ok(self.take_handed_over(slot_id, slot_value));
                             }
                         }
                     }
@@ -256,15 +1030,12 @@ where
                 // covers POP case as well
                 // can be enhanced for the case when slot contains a tagged pointer
                 // like try to cas(&slot, tagged_ptr, POP, .., ..)
-                ptr if (ptr & 1 == 1) => (),
-                ptr => {
+                state if (state & 1 == 1) => (),
+                _ => {
                     fence(Ordering::Acquire);
-                    if self.elimination_array[slot_id]
-                        .compare_exchange(ptr, EMPTY, Ordering::Release, Ordering::Relaxed)
-                        .is_ok()
-                    {
-                        let node_ptr = (ptr & !1) as *mut Node<T>;
-                        return Ok(unsafe { Box::from_raw(node_ptr) }.data);
+                    if self.try_take_pushed(slot_id, slot_value) {
+                        let node_ptr = slot_node::<T>(slot_value);
+                        return Ok(ManuallyDrop::into_inner(unsafe { Box::from_raw(node_ptr) }.data));
                     }
                 }
             };
@@ -272,9 +1043,123 @@ where
 
         Err(EliminationError::NoSlotsAvailable)
     }
+
+    // popper's side, once a pusher has put its node into the slot, that popper has set to POP.
+    // pusher doesn't touch the slot after that, so a plain store empties it
+    fn take_handed_over(&self, slot_id: usize, slot_value: usize) -> T {
+        let node_ptr = slot_node::<T>(slot_value);
+        self.bump_elimination_version(slot_id);
+        self.elimination_array[slot_id].store(next_tag(slot_value) | EMPTY, Ordering::Release);
+        ManuallyDrop::into_inner(unsafe { Box::from_raw(node_ptr) }.data)
+    }
+}
+
+thread_local! {
+    // seeded once per thread: elimination attempts are on the contended path, so they only
+    // draw from it, instead of setting up a generator (rand::rng()) on every call
+    static ELIMINATION_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_os_rng());
+}
+
+// uniform in 0..slot_count
+fn random_slot(slot_count: usize) -> usize {
+    ELIMINATION_RNG.with_borrow_mut(|rng| rng.random_range(0..slot_count))
+}
+
+// slot value's tag, advanced by one, to go with the state, that is written next, see TAG_BITS
+fn next_tag(slot_value: usize) -> usize {
+    (slot_value & !STATE_MASK).wrapping_add(TAG_ONE)
+}
+
+// node placed into the slot, without tag and LSB
+fn slot_node<T>(slot_value: usize) -> *mut Node<T> {
+    (slot_value & STATE_MASK & !1) as *mut Node<T>
+}
+
+// spins until slot no longer holds the value we've put there, for at most ELIMINATION_WINDOW.
+// returns the new slot value, or None if nobody has shown up in time
+fn await_rendezvous(slot: &AtomicUsize, placed: usize) -> Option<usize> {
+    let mut slot_value = placed;
+    // single spin_loop() between checks: partner either shows up within the window or not at all
+    let arrived = SpinWait::new()
+        .spinning()
+        .with_backoff(Backoff::with_params(1, 1, None))
+        .with_max_spins(ELIMINATION_MAX_SPINS)
+        .with_timeout(ELIMINATION_WINDOW)
+        .wait_until(|| {
+            slot_value = slot.load(Ordering::Relaxed);
+            slot_value != placed
+        });
+    if !arrived {
+        return None;
+    }
+    fence(Ordering::Acquire);
+    Some(slot_value)
+}
+
+unsafe impl<T: Send, R: Reclaimer, const ELIM: usize> Sync for TreiberStack<T, R, ELIM> {}
+unsafe impl<T: Send, R: Reclaimer, const ELIM: usize> Send for TreiberStack<T, R, ELIM> {}
+
+// needs no T: Send, unlike the rest: nothing gets retired, so Drop may use it as well
+impl<T, R: Reclaimer, const ELIM: usize> TreiberStack<T, R, ELIM> {
+    // with exclusive access there's neither concurrent pop, nor pending elimination exchange,
+    // so nodes can be taken off the list and freed right away, without any reclamation
+    pub(crate) fn take_all(&mut self) -> Vec<T> {
+        let mut values = Vec::new();
+        let mut current = std::mem::replace(self.head.get_mut(), std::ptr::null_mut());
+        *self.len.0.get_mut() = 0;
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current as *mut Node<T>) };
+            current = node.next.load(Ordering::Relaxed);
+            values.push(ManuallyDrop::into_inner(node.data));
+        }
+        values
+    }
+}
+
+/*
+    &mut self: no push or pop is in flight, so nothing is protected, and values still in the
+    stack are dropped along with their nodes right away. a node is never both in the head chain
+    and in an elimination slot: pusher places it into a slot only after its CAS on head has
+    failed (so it's not linked), and gets it back before trying the CAS again, or hands it over
+    to a popper instead of linking it at all. and once the operations are over, slots should be
+    empty anyway: a pusher, whose node is still in a slot, is waiting for a popper, and a popper
+    takes a node out of a slot before it returns. so the sweep is a safety net, that frees what
+    a bug (or an operation, that panicked halfway) may have left there
+*/
+impl<T, R: Reclaimer, const ELIM: usize> Drop for TreiberStack<T, R, ELIM> {
+    fn drop(&mut self) {
+        drop(self.take_all());
+        for slot in self.elimination_array.iter_mut() {
+            let slot_value = std::mem::replace(slot.0.get_mut(), EMPTY);
+            if !matches!(slot_value & STATE_MASK, EMPTY | POP) {
+                let node = unsafe { Box::from_raw(slot_node::<T>(slot_value)) };
+                drop(ManuallyDrop::into_inner(node.data));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PopPolicy {
+    // single attempt: None if stack is empty or if attempt lost the race to another thread
+    NonBlocking,
+    // spins (with yielding) until there's something to pop
+    SpinUntilItem,
+    // None on empty stack, retries on contention. default pop() behavior
+    ReturnEmpty,
 }
 
-unsafe impl<T> Sync for TreiberStack<T> {}
+// top of the stack isn't the expected node anymore, see pop_if_top_is()
+pub struct CasFailed;
+
+// why try_pop() has given up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryPopError {
+    // head has changed between its load and the CAS: another push or pop got there first
+    Contended,
+    // guard had no free hazard slot to protect head with
+    NoAvailableIndices,
+}
 
 enum EliminationError {
     NoSlotsAvailable,
@@ -283,26 +1168,522 @@ enum EliminationError {
 
 #[cfg(test)]
 mod tests {
-    use crate::collections::treiber_stack::TreiberStack;
+    use crate::collections::treiber_stack::{
+        CasFailed, EliminationError, Node, PopPolicy, StackNode, TreiberStack, TryPopError,
+        next_tag, slot_node, DEFAULT_ELIMINATION_SLOTS, ELIMINATION_THRESHOLD, EMPTY, POP, STATE_MASK, MAX_ELIMINATION_THRESHOLD, MIN_ELIMINATION_THRESHOLD,
+    };
+    use crate::collections::cas::CasStrength;
+    use crate::mechanisms::ebr::EbrDomain;
     use crate::mechanisms::hp::HazardPointerArray;
+    use crate::mechanisms::reclaim::{EbrReclaimer, HpReclaimer, Reclaimer};
+    use crate::utils::interleave::replay;
     use std::collections::HashSet;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::LazyLock;
+    use std::mem::ManuallyDrop;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+    use std::sync::{Arc, LazyLock};
+    use std::time::{Duration, Instant};
 
-    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(|| HazardPointerArray::new());
+    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(HazardPointerArray::new);
+    static EBR_DOMAIN: EbrDomain = EbrDomain::new();
 
     #[test]
     fn test_basic_operations() {
-        let stack = TreiberStack::new();
+        let mut stack = TreiberStack::new();
         stack.push(-1);
         stack.push(2);
         stack.push(33);
+        assert_eq!(stack.check_invariants(), 3);
         let mut pop_results = Vec::new();
         let guard = HP_ARRAY.register_thread().ok().unwrap();
         pop_results.push(stack.pop(&guard).unwrap());
         pop_results.push(stack.pop(&guard).unwrap());
+        assert_eq!(stack.check_invariants(), 1);
         pop_results.push(stack.pop(&guard).unwrap());
         assert_eq!(pop_results, vec![33, 2, -1]);
+        assert_eq!(stack.check_invariants(), 0);
+    }
+
+    #[test]
+    fn test_push_all() {
+        let mut stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        stack.push_all(Vec::new());
+        assert_eq!(stack.check_invariants(), 0);
+        stack.push(0);
+        stack.push_all(1..=3);
+        assert_eq!(stack.check_invariants(), 4);
+        // same order, as if pushed one by one
+        let popped = std::iter::from_fn(|| stack.pop(&guard)).collect::<Vec<_>>();
+        assert_eq!(popped, vec![3, 2, 1, 0]);
+
+        let hp_array = HazardPointerArray::new();
+        let batches = 200;
+        let batch_size = 10;
+        let pushers = 2;
+        let total = pushers * batches * batch_size;
+        let popped_count = AtomicUsize::new(0);
+        let (stack_ref, hp_array_ref, popped_count_ref) = (&stack, &hp_array, &popped_count);
+        let popped = std::thread::scope(|s| {
+            for p in 0..pushers {
+                s.spawn(move || {
+                    for b in 0..batches {
+                        let start = (p * batches + b) * batch_size;
+                        stack_ref.push_all(start..start + batch_size);
+                    }
+                });
+            }
+            let poppers = (0..2)
+                .map(|_| {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let mut values = Vec::new();
+                        while popped_count_ref.load(Ordering::Relaxed) < total {
+                            if let Some(value) = stack_ref.pop(&guard) {
+                                popped_count_ref.fetch_add(1, Ordering::Relaxed);
+                                values.push(value);
+                            }
+                        }
+                        values
+                    })
+                })
+                .collect::<Vec<_>>();
+            poppers
+                .into_iter()
+                .flat_map(|popper| popper.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(popped.len(), total);
+        assert_eq!(popped.into_iter().collect::<HashSet<_>>(), (0..total).collect());
+        assert_eq!(stack.check_invariants(), 0);
+    }
+
+    #[test]
+    fn test_pop_scoped() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let mut handled = Vec::new();
+        assert!(!stack.pop_scoped(&guard, |value| handled.push(value)));
+        assert!(handled.is_empty());
+
+        stack.push_all(0..3);
+        while stack.pop_scoped(&guard, |value| handled.push(value)) {}
+        assert_eq!(handled, vec![2, 1, 0]);
+
+        // panicking handler still consumes the value: it's neither handled nor back on the stack
+        stack.push(3);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            stack.pop_scoped(&guard, |_| panic!("handler failed"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(stack.pop(&guard), None);
+    }
+
+    #[test]
+    fn test_peek_top_k() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let mut seen = Vec::new();
+        assert_eq!(stack.peek_top_k(&guard, 3, |value| seen.push(*value)), 0);
+        assert!(seen.is_empty());
+
+        stack.push_all(0..5);
+        assert_eq!(stack.peek_top_k(&guard, 3, |value| seen.push(*value)), 3);
+        assert_eq!(seen, vec![4, 3, 2]);
+        seen.clear();
+        assert_eq!(stack.peek_top_k(&guard, 10, |value| seen.push(*value)), 5);
+        assert_eq!(seen, vec![4, 3, 2, 1, 0]);
+        assert_eq!(stack.peek_top_k(&guard, 0, |_| unreachable!()), 0);
+        // nothing is left protected, and nothing is taken off the stack
+        assert_eq!(guard.protected_count(), 0);
+        assert_eq!(stack.pop(&guard), Some(4));
+    }
+
+    #[test]
+    fn test_peek() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(stack.peek(&guard, |_: &(usize, bool)| unreachable!()), None::<()>);
+        stack.push((1, false));
+        stack.push((2, true));
+        assert_eq!(stack.peek(&guard, |&(value, _)| value), Some(2));
+        assert_eq!(guard.protected_count(), 0);
+        assert_eq!(stack.len(), 2);
+
+        // monitor keeps looking at the top, while it's being popped and freed
+        let hp_array = HazardPointerArray::new();
+        let (stack_ref, hp_array_ref) = (&stack, &hp_array);
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                for i in 0..2000 {
+                    stack_ref.push((i + 3, false));
+                    assert!(stack_ref.pop(&guard).is_some());
+                }
+            });
+            s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                for _ in 0..2000 {
+                    let top = stack_ref.peek(&guard, |&top| top).unwrap();
+                    assert!(top == (2, true) || (top.0 > 2 && !top.1), "{:?}", top);
+                }
+            });
+        });
+        assert_eq!(stack.peek(&guard, |&top| top), Some((2, true)));
+    }
+
+    // no Default for trait objects: heterogeneous tasks go in as they are
+    #[test]
+    fn test_trait_objects() {
+        let stack: TreiberStack<Box<dyn Fn() -> usize + Send>> = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let captured = Arc::new(String::from("captured"));
+        let len = captured.clone();
+        stack.push(Box::new(|| 1usize));
+        stack.push(Box::new(move || len.len()));
+        stack.push_all([Box::new(|| 2usize) as Box<dyn Fn() -> usize + Send>, Box::new(|| 3)]);
+
+        let mut results = Vec::new();
+        while let Some(task) = stack.pop(&guard) {
+            results.push(task());
+        }
+        assert_eq!(results, vec![3, 2, 8, 1]);
+        // closure (and its capture) is dropped exactly once, by whoever popped it
+        assert_eq!(Arc::strong_count(&captured), 1);
+
+        let mut stack = stack;
+        let len = captured.clone();
+        stack.push_mut(Box::new(move || len.len()));
+        assert_eq!(stack.pop_mut().map(|task| task()), Some(8));
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    // no pops: no elimination either, so exactly the first push to land sees the stack empty
+    #[test]
+    fn test_push_detect_empty() {
+        let mut stack = TreiberStack::new();
+        assert!(stack.push_detect_empty(0));
+        assert!(!stack.push_detect_empty(1));
+        assert_eq!(stack.take_all().len(), 2);
+
+        let (threads, per_thread) = (4, 1000);
+        let transitions = AtomicUsize::new(0);
+        for _ in 0..50 {
+            let (stack_ref, transitions_ref) = (&stack, &transitions);
+            std::thread::scope(|s| {
+                for t in 0..threads {
+                    s.spawn(move || {
+                        for i in 0..per_thread {
+                            if stack_ref.push_detect_empty(t * per_thread + i) {
+                                transitions_ref.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    });
+                }
+            });
+            assert_eq!(transitions.swap(0, Ordering::Relaxed), 1);
+            assert_eq!(stack.take_all().len(), threads * per_thread);
+        }
+    }
+
+    #[test]
+    fn test_swap_all() {
+        let mut stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert!(stack.swap_all(&guard, 0..3).is_empty());
+        assert_eq!(stack.swap_all(&guard, 3..5), vec![2, 1, 0]);
+        assert_eq!(stack.check_invariants(), 2);
+        assert_eq!(stack.swap_all(&guard, None), vec![4, 3]);
+        assert_eq!(stack.check_invariants(), 0);
+        drop(guard);
+
+        // every value ends up either popped, swapped out, or left in the stack, exactly once
+        let hp_array = HazardPointerArray::new();
+        let (workers, per_worker, swaps) = (2, 5000, 500);
+        let next_batch = AtomicUsize::new(0);
+        let (stack_ref, hp_array_ref, next_batch_ref) = (&stack, &hp_array, &next_batch);
+        let batch_size = 4;
+        let batch_base = workers * per_worker;
+        let mut collected = std::thread::scope(|s| {
+            let handles = (0..workers)
+                .map(|w| {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        let mut values = Vec::new();
+                        for i in 0..per_worker {
+                            stack_ref.push(w * per_worker + i);
+                            if i % 2 == 1 {
+                                values.extend(stack_ref.pop(&guard));
+                            }
+                        }
+                        values
+                    })
+                })
+                .collect::<Vec<_>>();
+            let swapper = s.spawn(move || {
+                let guard = hp_array_ref.register_thread().ok().unwrap();
+                let mut values = Vec::new();
+                for _ in 0..swaps {
+                    let batch = next_batch_ref.fetch_add(1, Ordering::Relaxed);
+                    let start = batch_base + batch * batch_size;
+                    values.extend(stack_ref.swap_all(&guard, start..start + batch_size));
+                }
+                values
+            });
+            handles
+                .into_iter()
+                .chain(std::iter::once(swapper))
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        collected.extend(stack.take_all());
+        let total = batch_base + swaps * batch_size;
+        assert_eq!(collected.len(), total);
+        assert_eq!(collected.into_iter().collect::<HashSet<_>>(), (0..total).collect());
+    }
+
+    // strong CAS is a drop-in substitute for the weak one, and vice versa
+    #[test]
+    fn test_cas_strength() {
+        let hp_array = HazardPointerArray::new();
+        let threads = 3;
+        let per_thread = 5000;
+        for cas_strength in [CasStrength::Weak, CasStrength::Strong] {
+            let mut stack = TreiberStack::new().with_cas_strength(cas_strength);
+            let (stack_ref, hp_array_ref) = (&stack, &hp_array);
+            // every thread pushes and pops in turns, so pops race with pushes of others
+            let popped = std::thread::scope(|s| {
+                let handles = (0..threads)
+                    .map(|t| {
+                        s.spawn(move || {
+                            let guard = hp_array_ref.register_thread().ok().unwrap();
+                            let mut values = Vec::new();
+                            for i in 0..per_thread {
+                                stack_ref.push(t * per_thread + i);
+                                values.extend(stack_ref.pop(&guard));
+                            }
+                            values
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            let mut values = stack.take_all();
+            values.extend(popped);
+            assert_eq!(values.len(), threads * per_thread);
+            assert_eq!(
+                values.into_iter().collect::<HashSet<_>>(),
+                (0..threads * per_thread).collect()
+            );
+            assert_eq!(stack.check_invariants(), 0);
+        }
+    }
+
+    #[test]
+    fn test_len() {
+        let mut stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(stack.len(), 0);
+        assert!(stack.is_empty());
+
+        stack.push(1);
+        stack.push_all(2..5);
+        assert_eq!(stack.len(), 4);
+        assert_eq!(stack.pop(&guard), Some(4));
+        assert!(matches!(stack.pop_if_top_is(&guard, stack.top_node()), Ok(Some(3))));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.swap_all(&guard, 5..10).len(), 2);
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack.swap_all(&guard, []).len(), 5);
+        assert!(stack.is_empty());
+        assert_eq!(stack.len(), 0);
+
+        stack.push_mut(1);
+        stack.push_mut(2);
+        assert_eq!(stack.pop_mut(), Some(2));
+        assert_eq!(stack.len(), 1);
+        stack.push(3);
+        assert_eq!(stack.take_all().len(), 2);
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.pop(&guard), None);
+        assert_eq!(stack.len(), 0);
+    }
+
+    #[test]
+    fn test_try_pop() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert_eq!(stack.try_pop(&guard), Ok(None));
+
+        stack.push_all(0..2);
+        assert_eq!(stack.try_pop(&guard), Ok(Some(1)));
+        assert_eq!(stack.len(), 1);
+        assert_eq!(guard.protected_count(), 0);
+
+        // all slots are taken: gives up instead of waiting for one
+        let mut held = Vec::new();
+        while let Ok(protected) = unsafe { guard.protect(stack.top_node() as *mut StackNode<i32>) } {
+            held.push(protected);
+        }
+        assert_eq!(stack.try_pop(&guard), Err(TryPopError::NoAvailableIndices));
+        drop(held);
+        assert_eq!(stack.try_pop(&guard), Ok(Some(0)));
+        assert_eq!(stack.try_pop(&guard), Ok(None));
+    }
+
+    // try_pop() racing with a push under deterministic interleavings (see utils::interleave):
+    // push landing between try_pop()'s load and CAS makes it report Contended, and take nothing
+    #[test]
+    fn test_try_pop_contended() {
+        let outcomes = (0..32)
+            .map(|seed| {
+                let hp_array = HazardPointerArray::new();
+                let stack = TreiberStack::new();
+                stack.push(1);
+                let popped = std::sync::Mutex::new(None);
+                let (stack_ref, hp_array_ref, popped_ref) = (&stack, &hp_array, &popped);
+                replay(
+                    seed,
+                    vec![
+                        Box::new(move || {
+                            let guard = hp_array_ref.register_thread().ok().unwrap();
+                            *popped_ref.lock().unwrap() = Some(stack_ref.try_pop(&guard));
+                        }),
+                        Box::new(move || stack_ref.push(2)),
+                    ],
+                );
+                let guard = hp_array.register_thread().ok().unwrap();
+                let mut remaining = Vec::new();
+                while let Some(value) = stack.pop(&guard) {
+                    remaining.push(value);
+                }
+                (popped.into_inner().unwrap().unwrap(), remaining)
+            })
+            .collect::<Vec<_>>();
+
+        for (popped, remaining) in outcomes.iter() {
+            match popped {
+                Ok(Some(value)) => assert_eq!(remaining, &vec![3 - value]),
+                Err(TryPopError::Contended) => assert_eq!(remaining, &vec![2, 1]),
+                other => panic!("unexpected try_pop() outcome: {:?}", other),
+            }
+        }
+        assert!(outcomes.iter().any(|(popped, _)| *popped == Err(TryPopError::Contended)));
+        assert!(outcomes.iter().any(|(popped, _)| popped.is_ok()));
+    }
+
+    #[test]
+    fn test_pop_if_top_is() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        assert!(matches!(stack.pop_if_top_is(&guard, std::ptr::null()), Ok(None)));
+
+        stack.push(1);
+        stack.push(2);
+        let top = stack.top_node();
+        assert!(matches!(stack.pop_if_top_is(&guard, top), Ok(Some(2))));
+        // the same node can't be popped twice
+        assert!(matches!(stack.pop_if_top_is(&guard, top), Err(CasFailed)));
+        assert!(matches!(stack.pop_if_top_is(&guard, std::ptr::null()), Err(CasFailed)));
+
+        // another thread pushes in between
+        let top = stack.top_node();
+        let stack_ref = &stack;
+        std::thread::scope(|s| {
+            s.spawn(move || stack_ref.push(3));
+        });
+        assert!(matches!(stack.pop_if_top_is(&guard, top), Err(CasFailed)));
+        assert_eq!(stack.pop(&guard), Some(3));
+
+        // equal value on top, but another node. expected node is kept protected, so its address
+        // can't be reused by the new one
+        let top = stack.top_node();
+        let protected_top = unsafe { guard.protect(top as *mut StackNode<i32>) }.ok().unwrap();
+        assert_eq!(stack.pop(&guard), Some(1));
+        stack.push(1);
+        assert!(matches!(stack.pop_if_top_is(&guard, top), Err(CasFailed)));
+        drop(protected_top);
+        assert!(matches!(stack.pop_if_top_is(&guard, stack.top_node()), Ok(Some(1))));
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle in head chain")]
+    fn test_check_invariants_detects_cycle() {
+        // corrupted stack is leaked: drop would walk the cycle, freeing its nodes over again
+        let mut stack = ManuallyDrop::new(TreiberStack::new());
+        for i in 0..3 {
+            stack.push_mut(i);
+        }
+        // corrupt the stack: the bottom node points back to the top
+        let head = *stack.head.get_mut();
+        let mut bottom = head;
+        while let Some(next) = unsafe { (*bottom).0.next.load(Ordering::Relaxed).as_mut() } {
+            bottom = next;
+        }
+        unsafe { (*bottom).0.next.store(head, Ordering::Relaxed) };
+        stack.check_invariants();
+    }
+
+    #[test]
+    fn test_pop_policies() {
+        let stack = TreiberStack::new();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+
+        assert_eq!(stack.pop_with_policy(&guard, PopPolicy::ReturnEmpty), None);
+        assert_eq!(stack.pop_with_policy(&guard, PopPolicy::NonBlocking), None);
+
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.pop_with_policy(&guard, PopPolicy::NonBlocking), Some(2));
+        assert_eq!(stack.pop_with_policy(&guard, PopPolicy::ReturnEmpty), Some(1));
+
+        let stack_ref = &stack;
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                stack_ref.push(3);
+            });
+            // would return None right away with any other policy
+            assert_eq!(stack.pop_with_policy(&guard, PopPolicy::SpinUntilItem), Some(3));
+        });
+    }
+
+    #[test]
+    fn test_mut_fast_path() {
+        let mut stack = TreiberStack::new();
+        let thread_count = 4;
+        let per_thread_ops = 10_000;
+        for i in 0..thread_count * per_thread_ops {
+            stack.push_mut(i);
+        }
+        assert_eq!(stack.pop_mut(), Some(thread_count * per_thread_ops - 1));
+        stack.push_mut(thread_count * per_thread_ops - 1);
+        stack.sync_fence();
+
+        // now share it. own array, so that we don't compete for slots with test_concurrent
+        let hp_array = HazardPointerArray::new();
+        let hp_array_ref = &hp_array;
+        let collected_values = std::sync::Mutex::new(Vec::new());
+        let stack_ref = &stack;
+        let values_ref = &collected_values;
+        std::thread::scope(|s| {
+            for _ in 0..thread_count {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread().ok().unwrap();
+                    let mut values = Vec::new();
+                    for _ in 0..per_thread_ops {
+                        values.push(stack_ref.pop(&guard).unwrap());
+                    }
+                    values_ref.lock().unwrap().append(&mut values);
+                });
+            }
+        });
+        stack.quiesce();
+        assert_eq!(stack.pop_mut(), None);
+        let actual_values: HashSet<usize> = collected_values.into_inner().unwrap().into_iter().collect();
+        assert_eq!(actual_values, (0..thread_count * per_thread_ops).collect());
     }
 
     #[derive(Default)]
@@ -311,18 +1692,28 @@ mod tests {
     }
 
     static NEXT_VALUE: AtomicUsize = AtomicUsize::new(0);
+    static NEXT_EBR_VALUE: AtomicUsize = AtomicUsize::new(0);
 
     impl TrackableValue {
-        fn new() -> Self {
+        fn new(next_value: &AtomicUsize) -> Self {
             TrackableValue {
-                value: NEXT_VALUE.fetch_add(1, Ordering::Relaxed),
+                value: next_value.fetch_add(1, Ordering::Relaxed),
             }
         }
     }
 
     #[test]
     fn test_concurrent() {
-        let stack = TreiberStack::new();
+        run_concurrent::<HpReclaimer>(&HP_ARRAY, &NEXT_VALUE);
+    }
+
+    #[test]
+    fn test_concurrent_ebr() {
+        run_concurrent::<EbrReclaimer>(&EBR_DOMAIN, &NEXT_EBR_VALUE);
+    }
+
+    fn run_concurrent<R: Reclaimer>(domain: &R::Domain, next_value: &AtomicUsize) {
+        let stack = TreiberStack::<_, R>::with_reclaimer();
         let stack_ref = &stack;
 
         let thread_count = 4;
@@ -336,11 +1727,11 @@ mod tests {
             std::thread::scope(|s| {
                 (0..thread_count).for_each(|_| {
                     s.spawn(|| {
-                        let guard = HP_ARRAY.register_thread().ok().unwrap();
+                        let guard = R::register_thread(domain).ok().unwrap();
 
                         // first batch of pushes and pops
                         for  _ in 0..per_thread_ops/2 {
-                            stack_ref.push(TrackableValue::new());
+                            stack_ref.push(TrackableValue::new(next_value));
                         }
 
                         for  _ in 0..per_thread_ops/2 {
@@ -351,7 +1742,7 @@ mod tests {
 
                         // second batch
                         for  _ in per_thread_ops / 2..per_thread_ops {
-                            stack_ref.push(TrackableValue::new());
+                            stack_ref.push(TrackableValue::new(next_value));
                         }
 
                         for  _ in per_thread_ops / 2..per_thread_ops {
@@ -365,7 +1756,417 @@ mod tests {
 
             let actual_values = HashSet::<usize>::from_iter(collected_values.into_inner().unwrap());
             assert_eq!(actual_values, expected_values);
-            NEXT_VALUE.store(0, Ordering::Relaxed)
+            // eliminated pairs included, every push and pop is counted exactly once
+            assert_eq!(stack.len(), 0);
+            next_value.store(0, Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_adaptive_backoff() {
+        let stack = TreiberStack::new().with_adaptive_backoff();
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let contention = stack.contention.as_ref().unwrap();
+        for _ in 0..64 {
+            contention.record(32);
+        }
+        assert_eq!(contention.average_retries(), 32);
+
+        // contention is over: operations succeed right away, and the average follows
+        for i in 0..32 {
+            stack.push(i);
         }
+        assert!(contention.average_retries() < 32);
+        for i in (0..32).rev() {
+            assert_eq!(stack.pop(&guard), Some(i));
+        }
+        assert_eq!(contention.average_retries(), 0);
+        assert_eq!(stack.pop(&guard), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_drop() {
+        let value = Arc::new(());
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let stack = TreiberStack::new();
+        for _ in 0..10 {
+            stack.push(value.clone());
+        }
+        stack.pop(&guard).unwrap();
+        // partially drained: the rest goes with the stack
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        // leftovers in elimination slots, plain and tagged, are freed as well
+        let mut stack = TreiberStack::new();
+        stack.push_mut(value.clone());
+        for (slot_id, tag) in [(0, 0), (5, 1)] {
+            let node = Box::into_raw(Box::new(Node {
+                data: ManuallyDrop::new(value.clone()),
+                next: AtomicPtr::new(std::ptr::null_mut()),
+            }));
+            stack.elimination_array[slot_id].store(node as usize | tag, Ordering::Relaxed);
+        }
+        stack.elimination_array[2].store(POP, Ordering::Relaxed);
+        assert_eq!(Arc::strong_count(&value), 4);
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    // declared HAZARDS_PER_OP is exactly the peak of the most demanding operation
+    #[test]
+    fn test_hazards_per_op() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let stack = TreiberStack::new();
+        stack.push(1);
+
+        guard.reset_peak_protected();
+        assert_eq!(stack.pop(&guard), Some(1));
+        assert_eq!(guard.peak_protected(), TreiberStack::<i32>::HAZARDS_PER_OP);
+        assert_eq!(guard.protected_count(), 0);
+    }
+
+    // lone pusher gives up on elimination shortly and leaves its slot empty,
+    // while a pusher and a popper, that show up together, eventually meet
+    #[test]
+    fn test_elimination_window() {
+        let stack = TreiberStack::<usize>::new();
+        let node = Box::into_raw(Box::new(Node {
+            data: ManuallyDrop::new(1),
+            next: AtomicPtr::new(std::ptr::null_mut()),
+        }));
+        let start = Instant::now();
+        assert!(matches!(
+            stack.try_elimination_push(node),
+            Err(EliminationError::NoRendezvous)
+        ));
+        // lenient: window itself is sub-microsecond, but thread yields once before waiting
+        assert!(start.elapsed() < Duration::from_millis(100));
+        // EMPTY again, but under a new tag
+        assert!(stack
+            .elimination_array
+            .iter()
+            .all(|slot| slot.load(Ordering::Relaxed) & STATE_MASK == EMPTY));
+        unsafe { drop(Box::from_raw(node)) };
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let stack_ref = &stack;
+        let (pushed, popped) = std::thread::scope(|s| {
+            let pusher = s.spawn(move || {
+                let node = Box::into_raw(Box::new(Node {
+                    data: ManuallyDrop::new(2),
+                    next: AtomicPtr::new(std::ptr::null_mut()),
+                }));
+                while Instant::now() < deadline {
+                    if stack_ref.try_elimination_push(node).is_ok() {
+                        return true;
+                    }
+                }
+                unsafe { drop(Box::from_raw(node)) };
+                false
+            });
+            let popper = s.spawn(move || {
+                while Instant::now() < deadline {
+                    if let Ok(value) = stack_ref.try_elimination_pop() {
+                        return Some(value);
+                    }
+                }
+                None
+            });
+            (pusher.join().unwrap(), popper.join().unwrap())
+        });
+        assert!(pushed);
+        assert_eq!(popped, Some(2));
+    }
+
+    // sizes other than the default: a single slot (every rendezvous is at slot 0), and a
+    // large array, both under contention
+    #[test]
+    fn test_elimination_slots() {
+        let stack = TreiberStack::<usize, HpReclaimer, 1>::with_reclaimer();
+        assert_eq!(stack.elimination_array.len(), 1);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let stack_ref = &stack;
+        let popped = std::thread::scope(|s| {
+            s.spawn(move || {
+                let node = Box::into_raw(Box::new(Node {
+                    data: ManuallyDrop::new(3),
+                    next: AtomicPtr::new(std::ptr::null_mut()),
+                }));
+                while Instant::now() < deadline {
+                    if stack_ref.try_elimination_push(node).is_ok() {
+                        return;
+                    }
+                }
+                unsafe { drop(Box::from_raw(node)) };
+            });
+            s.spawn(move || {
+                while Instant::now() < deadline {
+                    if let Ok(value) = stack_ref.try_elimination_pop() {
+                        return Some(value);
+                    }
+                }
+                None
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(popped, Some(3));
+
+        let stack = TreiberStack::<usize, HpReclaimer, 64>::with_reclaimer()
+            .with_elimination_threshold(MIN_ELIMINATION_THRESHOLD);
+        let hp_array = HazardPointerArray::new();
+        let (stack_ref, hp_array_ref) = (&stack, &hp_array);
+        let popped: Vec<usize> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        (0..500)
+                            .map(|i| {
+                                stack_ref.push(t * 500 + i);
+                                stack_ref.pop(&guard).unwrap()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(popped.iter().collect::<HashSet<_>>().len(), 2000);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_with_elimination_threshold() {
+        let stack = TreiberStack::<usize>::new().with_elimination_threshold(MAX_ELIMINATION_THRESHOLD);
+        assert_eq!(stack.elimination_threshold.load(Ordering::Relaxed), MAX_ELIMINATION_THRESHOLD);
+        let result = std::panic::catch_unwind(|| {
+            TreiberStack::<usize>::new().with_elimination_threshold(MAX_ELIMINATION_THRESHOLD + 1)
+        });
+        assert!(result.is_err());
+    }
+
+    // under contention, neither the elimination array nor the threshold is ever touched
+    #[test]
+    fn test_without_elimination() {
+        let stack = TreiberStack::<usize>::without_elimination();
+        let hp_array = HazardPointerArray::new();
+        let (stack_ref, hp_array_ref) = (&stack, &hp_array);
+        let popped: Vec<usize> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        (0..1000)
+                            .map(|i| {
+                                stack_ref.push(t * 1000 + i);
+                                stack_ref.pop(&guard).unwrap()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(popped.iter().collect::<HashSet<_>>().len(), 4000);
+        assert!(stack.is_empty());
+        assert_eq!(stack.elimination_threshold.load(Ordering::Relaxed), ELIMINATION_THRESHOLD);
+        assert!(stack
+            .elimination_array
+            .iter()
+            .all(|slot| slot.load(Ordering::Relaxed) == EMPTY));
+    }
+
+    #[test]
+    fn test_elimination_threshold_hysteresis() {
+        let stack = TreiberStack::<usize>::new();
+        let threshold = || stack.elimination_threshold.load(Ordering::Relaxed);
+        assert_eq!(threshold(), ELIMINATION_THRESHOLD);
+
+        // burst of failed eliminations moves threshold up step by step
+        for step in 1..=3 {
+            stack.adapt_elimination_threshold(false);
+            assert_eq!(threshold(), ELIMINATION_THRESHOLD + step);
+        }
+        // alternating outcomes make it hover instead of jumping between extremes
+        for _ in 0..10 {
+            stack.adapt_elimination_threshold(true);
+            assert_eq!(threshold(), ELIMINATION_THRESHOLD + 2);
+            stack.adapt_elimination_threshold(false);
+            assert_eq!(threshold(), ELIMINATION_THRESHOLD + 3);
+        }
+        for _ in 0..2 * MAX_ELIMINATION_THRESHOLD {
+            stack.adapt_elimination_threshold(false);
+        }
+        assert_eq!(threshold(), MAX_ELIMINATION_THRESHOLD);
+        for _ in 0..2 * MAX_ELIMINATION_THRESHOLD {
+            stack.adapt_elimination_threshold(true);
+        }
+        assert_eq!(threshold(), MIN_ELIMINATION_THRESHOLD);
+
+        // bursts of contention keep it within bounds, while stack stays consistent
+        let hp_array = HazardPointerArray::new();
+        let stack_ref = &stack;
+        let hp_array_ref = &hp_array;
+        for _ in 0..20 {
+            std::thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(move || {
+                        let guard = hp_array_ref.register_thread().ok().unwrap();
+                        for i in 0..500 {
+                            stack_ref.push(i);
+                            assert!(stack_ref.pop(&guard).is_some());
+                        }
+                    });
+                }
+            });
+            let current = threshold();
+            assert!((MIN_ELIMINATION_THRESHOLD..=MAX_ELIMINATION_THRESHOLD).contains(&current));
+        }
+        let guard = hp_array.register_thread().ok().unwrap();
+        assert!(stack.pop(&guard).is_none());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let stack = TreiberStack::new();
+        assert!(stack.freeze(&guard).ok().unwrap().is_empty());
+        for i in 0..4 {
+            stack.push(i);
+        }
+
+        let view = stack.freeze(&guard).ok().unwrap();
+        assert!(view.is_pinned());
+        assert_eq!(guard.protected_count(), 4);
+        // pushes after the snapshot don't show up in it
+        stack.push(4);
+        assert_eq!(view.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1, 0]);
+        let mut sum = 0;
+        for value in &view {
+            sum += value;
+        }
+        assert_eq!(sum, 6);
+        drop(view);
+        assert_eq!(guard.protected_count(), 0);
+
+        // way more elements than hazard slots: pinning gives up, cloning doesn't
+        for i in 5..100 {
+            stack.push(i);
+        }
+        assert!(stack.freeze(&guard).is_err());
+        assert_eq!(guard.protected_count(), 0);
+        let view = stack.freeze_or_clone(&guard);
+        assert!(!view.is_pinned());
+        assert_eq!(view.iter().copied().collect::<Vec<_>>(), (0..100).rev().collect::<Vec<_>>());
+    }
+
+    /*
+        elimination rendezvous, deterministic and small enough for Miri (see ms_queue's reclaim
+        tests): every slot holds a waiting pusher's node, so popper's random pick can't miss.
+        popper takes the node over and frees it right away, with no reclamation at all, so the
+        pusher, whose slot got emptied, must treat its node as gone, while the others give up
+        and get their nodes back
+    */
+    #[test]
+    fn test_reclaim_elimination_rendezvous() {
+        let stack = TreiberStack::<Arc<()>>::new();
+        let value = Arc::new(());
+        let nodes = (0..DEFAULT_ELIMINATION_SLOTS)
+            .map(|slot_id| {
+                let node = Box::into_raw(Box::new(Node {
+                    data: ManuallyDrop::new(value.clone()),
+                    next: AtomicPtr::new(std::ptr::null_mut()),
+                }));
+                stack.elimination_array[slot_id].store(node as usize, Ordering::Release);
+                (node, stack.elimination_version(slot_id))
+            })
+            .collect::<Vec<_>>();
+
+        let popped = stack.try_elimination_pop().ok().unwrap();
+        drop(popped);
+        assert_eq!(Arc::strong_count(&value), DEFAULT_ELIMINATION_SLOTS);
+
+        let mut handed_over = 0;
+        for (slot_id, (node, version)) in nodes.into_iter().enumerate() {
+            match stack.await_elimination_pop(slot_id, node as usize, version) {
+                Ok(()) => handed_over += 1,
+                Err(_) => drop(ManuallyDrop::into_inner(unsafe { Box::from_raw(node) }.data)),
+            }
+        }
+        assert_eq!(handed_over, 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert!(stack
+            .elimination_array
+            .iter()
+            .all(|slot| slot.load(Ordering::Relaxed) & STATE_MASK == EMPTY));
+        #[cfg(debug_assertions)]
+        assert_eq!(stack.aba_detected(), 0);
+    }
+
+    // plays pusher, popper and the second pusher by hand on a single slot. the second node
+    // is "allocated" at the very address of the first one, as allocator may well do, once the
+    // first one is freed by its popper
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_elimination_aba_detected() {
+        let stack: TreiberStack<usize> = TreiberStack::new();
+        let node = Box::into_raw(Box::new(Node {
+            data: ManuallyDrop::new(1),
+            next: AtomicPtr::new(std::ptr::null_mut()),
+        }));
+        let slot_id = 3;
+
+        // no ABA: pusher gives up on its own node
+        let placed = node as usize;
+        stack.elimination_array[slot_id].store(placed, Ordering::Relaxed);
+        let version = stack.elimination_version(slot_id);
+        assert!(matches!(
+            stack.await_elimination_pop(slot_id, placed, version),
+            Err(EliminationError::NoRendezvous)
+        ));
+        assert_eq!(stack.aba_detected(), 0);
+
+        // popper takes the node, and then the same address gets pushed into the same slot:
+        // under another tag, so the first pusher knows its node is gone, and leaves the second
+        // one alone
+        let placed = next_tag(stack.elimination_array[slot_id].load(Ordering::Relaxed)) | node as usize;
+        stack.elimination_array[slot_id].store(placed, Ordering::Relaxed);
+        let version = stack.elimination_version(slot_id);
+        assert!(stack.try_take_pushed(slot_id, placed));
+        let replaced = next_tag(stack.elimination_array[slot_id].load(Ordering::Relaxed)) | node as usize;
+        stack.elimination_array[slot_id].store(replaced, Ordering::Relaxed);
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert_ne!(replaced, placed);
+            assert!(stack.await_elimination_pop(slot_id, placed, version).is_ok());
+            assert_eq!(stack.aba_detected(), 0);
+            assert_eq!(stack.elimination_array[slot_id].load(Ordering::Relaxed), replaced);
+        }
+
+        // tag has wrapped around (or there's no tag at all): only diagnostics can tell
+        stack.elimination_array[slot_id].store(placed, Ordering::Relaxed);
+        assert!(matches!(
+            stack.await_elimination_pop(slot_id, placed, version),
+            Err(EliminationError::NoRendezvous)
+        ));
+        assert_eq!(stack.aba_detected(), 1);
+        assert_eq!(stack.elimination_array[slot_id].load(Ordering::Relaxed) & STATE_MASK, EMPTY);
+        unsafe { drop(Box::from_raw(node)) };
+    }
+
+    // every write to a slot moves its tag, wrapping around at the top
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_elimination_tags() {
+        let node_addr = 0x7f00_dead_bee0_usize;
+        let tagged = next_tag(EMPTY) | node_addr;
+        assert_eq!(tagged & STATE_MASK, node_addr);
+        assert_eq!(slot_node::<usize>(tagged | 1) as usize, node_addr);
+        assert_ne!(next_tag(tagged), next_tag(EMPTY));
+        assert_eq!(next_tag(usize::MAX) | EMPTY, EMPTY);
+    }
+}
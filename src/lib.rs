@@ -1,3 +1,4 @@
 pub mod mechanisms;
 pub mod collections;
 pub mod sync;
+pub mod utils;
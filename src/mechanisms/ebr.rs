@@ -0,0 +1,459 @@
+use crate::mechanisms::hp::{ProtectionError, RegisterThreadError};
+use std::cell::{Cell, RefCell};
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/*
+    epoch-based reclamation. comparing to hazard pointers, readers don't announce every pointer
+    they access, but only the fact they are active (pinned) along with the global epoch they've
+    observed. retired pointers are bucketed by the epoch of retirement and can be freed once global
+    epoch moved two steps further: global epoch advances only when every pinned thread has observed
+    it, so nobody could still hold a reference obtained before retirement.
+    cheaper reads, but a single stalled reader blocks all reclamation
+*/
+
+// limited by EbrDomain's thread_registry bitmap size, i.e. 64.
+// per-thread state is a single word here, so we can afford more threads than hp does
+const MAX_THREADS: usize = 64;
+const ADVANCE_THRESHOLD: usize = 64;
+// epochs are stored shifted by one, LSB marks thread as pinned
+const PINNED: usize = 1;
+const EPOCH_STEP: usize = 2;
+// there can be only three epochs with not yet reclaimed garbage: current, and two previous ones
+const LIMBO_BAGS: usize = 3;
+
+pub struct EbrDomain {
+    global_epoch: AtomicUsize,
+    local_epochs: [AtomicUsize; MAX_THREADS],
+    // in this bitmap, 1's stand for ready-to-use slots in local_epochs
+    thread_registry: AtomicU64,
+    /*
+        limbo bags left unfreed by dropped guards, adopted by the next collect() of any guard,
+        just like hp's orphans. type-erased, since guards of the same domain may be registered
+        for different T
+    */
+    orphans: AtomicPtr<OrphanBag>,
+}
+
+impl Default for EbrDomain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EbrDomain {
+    pub const fn new() -> Self {
+        const { assert!(MAX_THREADS <= 64, "MAX_THREADS must be less or equal to 64") };
+        let thread_registry = !0 >> (64 - MAX_THREADS);
+
+        Self {
+            global_epoch: AtomicUsize::new(0),
+            local_epochs: [const { AtomicUsize::new(0) }; MAX_THREADS],
+            thread_registry: AtomicU64::new(thread_registry),
+            orphans: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    pub fn register_thread<T>(&self) -> Result<EbrGuard<'_, T>, RegisterThreadError> {
+        loop {
+            let thread_registry = self.thread_registry.load(Ordering::Relaxed);
+            if thread_registry == 0 {
+                return Err(RegisterThreadError::NoAvailableIndices);
+            }
+            let first_slot = thread_registry.trailing_zeros() as usize;
+            if self
+                .thread_registry
+                .compare_exchange_weak(
+                    thread_registry,
+                    thread_registry ^ (1 << first_slot),
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return Ok(EbrGuard {
+                    domain: self,
+                    index: first_slot,
+                    pin_count: Cell::new(0),
+                    limbo: RefCell::new([const { (0, Vec::new()) }; LIMBO_BAGS]),
+                    retired_since_advance: Cell::new(0),
+                });
+            }
+        }
+    }
+
+    // global epoch can proceed only if all pinned threads have already observed current one
+    fn try_advance(&self) -> usize {
+        let global_epoch = self.global_epoch.load(Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        for local_epoch in self.local_epochs.iter() {
+            let local_epoch = local_epoch.load(Ordering::Relaxed);
+            if local_epoch & PINNED == PINNED && local_epoch & !PINNED != global_epoch {
+                return global_epoch;
+            }
+        }
+        fence(Ordering::Acquire);
+        match self.global_epoch.compare_exchange(
+            global_epoch,
+            global_epoch + EPOCH_STEP,
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => global_epoch + EPOCH_STEP,
+            Err(actual) => actual,
+        }
+    }
+
+    fn push_orphan(&self, orphan: *mut OrphanBag) {
+        let mut head = self.orphans.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*orphan).next = head };
+            match self
+                .orphans
+                .compare_exchange_weak(head, orphan, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    // takes the whole orphan list, so that no other guard adopts the same bag concurrently
+    fn take_orphans(&self) -> *mut OrphanBag {
+        if self.orphans.load(Ordering::Relaxed).is_null() {
+            return std::ptr::null_mut();
+        }
+        self.orphans.swap(std::ptr::null_mut(), Ordering::Acquire)
+    }
+}
+
+impl Drop for EbrDomain {
+    fn drop(&mut self) {
+        // guards borrow the domain, so none of them is alive, and nobody is pinned anymore
+        let mut current = *self.orphans.get_mut();
+        while !current.is_null() {
+            let orphan = unsafe { Box::from_raw(current) };
+            current = orphan.next;
+            orphan.free();
+        }
+    }
+}
+
+unsafe impl Send for EbrDomain {}
+// no Send impl for EbrGuard since it is supposed for static usage
+
+pub struct EbrGuard<'a, T> {
+    domain: &'a EbrDomain,
+    index: usize,
+    // protected pointers currently alive; thread is pinned while it's non-zero
+    pin_count: Cell<usize>,
+    // retired pointers along with the epoch they were retired in
+    limbo: RefCell<[(usize, Vec<*mut T>); LIMBO_BAGS]>,
+    retired_since_advance: Cell<usize>,
+}
+
+impl<T> EbrGuard<'_, T> {
+    /// # Safety
+    ///
+    /// `data_ptr` must be valid, i.e. it must not have been freed or retired before this call.
+    /// as with hazard pointers, pointer should be rechecked against its source after protection,
+    /// and is only safe to dereference once that recheck succeeds
+    pub unsafe fn protect(
+        &self,
+        data_ptr: *mut T,
+    ) -> Result<EbrProtectedPointer<'_, T>, ProtectionError> {
+        let Some(non_null_ptr) = NonNull::new(data_ptr) else {
+            return Err(ProtectionError::NullPointer);
+        };
+        self.pin();
+        Ok(EbrProtectedPointer {
+            ptr: non_null_ptr,
+            guard: self,
+        })
+    }
+
+    // just for the sake of completeness
+    pub fn unregister_thread(self) {
+        drop(self);
+    }
+
+    fn pin(&self) {
+        let pin_count = self.pin_count.get();
+        if pin_count == 0 {
+            let global_epoch = self.domain.global_epoch.load(Ordering::Relaxed);
+            self.domain.local_epochs[self.index].store(global_epoch | PINNED, Ordering::Relaxed);
+            // announcement must be visible before we read any shared pointer
+            fence(Ordering::SeqCst);
+        }
+        self.pin_count.set(pin_count + 1);
+    }
+
+    fn unpin(&self) {
+        let pin_count = self.pin_count.get() - 1;
+        self.pin_count.set(pin_count);
+        if pin_count == 0 {
+            self.domain.local_epochs[self.index].store(0, Ordering::Release);
+        }
+    }
+
+    /*
+        expired bags are taken out of limbo first, and freed after it's no longer borrowed:
+        T's Drop may well retire with this very guard (e.g. a node, that owns another one),
+        and such retire() simply lands in limbo, instead of panicking on a borrowed RefCell
+    */
+    fn collect(&self) {
+        let global_epoch = self.domain.try_advance();
+        let expired = self
+            .limbo
+            .borrow_mut()
+            .iter_mut()
+            .filter(|(bag_epoch, _)| *bag_epoch + 2 * EPOCH_STEP <= global_epoch)
+            .map(|(_, bag)| std::mem::take(bag))
+            .collect::<Vec<_>>();
+        for bag in expired {
+            Self::free_bag(bag);
+        }
+        self.adopt_orphans(global_epoch);
+    }
+
+    // orphans are freed with their own free functions, whatever T of this guard is
+    fn adopt_orphans(&self, global_epoch: usize) {
+        let mut current = self.domain.take_orphans();
+        while !current.is_null() {
+            let orphan = unsafe { Box::from_raw(current) };
+            current = orphan.next;
+            if orphan.epoch + 2 * EPOCH_STEP <= global_epoch {
+                orphan.free();
+            } else {
+                // still too young, back to the domain
+                self.domain.push_orphan(Box::into_raw(orphan));
+            }
+        }
+    }
+
+    fn free_bag(bag: Vec<*mut T>) {
+        for ptr in bag {
+            unsafe { drop(Box::from_raw(ptr)) };
+        }
+    }
+}
+
+// retired pointers may be freed on another thread (see EbrDomain::orphans), so only Send ones
+// can be retired
+impl<T: Send> EbrGuard<'_, T> {
+    pub fn retire_node(&self, protected_pointer: EbrProtectedPointer<T>) {
+        let ptr = protected_pointer.as_mut_ptr();
+        drop(protected_pointer);
+        self.retire_raw_pointer(ptr);
+    }
+
+    pub fn retire_raw_pointer(&self, ptr: *mut T) {
+        // pointer has been unlinked before retirement, so epoch we tag it with
+        // must not be older than what any thread pinned after unlinking could observe
+        fence(Ordering::SeqCst);
+        let global_epoch = self.domain.global_epoch.load(Ordering::Relaxed);
+        let expired = {
+            let mut limbo = self.limbo.borrow_mut();
+            let (bag_epoch, bag) = &mut limbo[(global_epoch / EPOCH_STEP) % LIMBO_BAGS];
+            // bag still holds garbage of some older epoch, which is at least two epochs behind
+            let expired = if *bag_epoch != global_epoch {
+                *bag_epoch = global_epoch;
+                std::mem::take(bag)
+            } else {
+                Vec::new()
+            };
+            bag.push(ptr);
+            expired
+        };
+        // see collect()
+        Self::free_bag(expired);
+
+        let retired = self.retired_since_advance.get() + 1;
+        if retired > ADVANCE_THRESHOLD {
+            self.retired_since_advance.set(0);
+            self.collect();
+        } else {
+            self.retired_since_advance.set(retired);
+        }
+    }
+}
+
+impl<'a, T> Drop for EbrGuard<'a, T> {
+    fn drop(&mut self) {
+        // give it a couple of tries: every advance may let us free one more bag.
+        // whatever remains after that is handed over to the domain's orphans, just like
+        // hp guard's unreclaimed d_list. only Send pointers are ever retired, see retire_node()
+        for _ in 0..LIMBO_BAGS {
+            self.collect();
+        }
+        for (epoch, bag) in std::mem::take(self.limbo.get_mut()) {
+            if !bag.is_empty() {
+                self.domain.push_orphan(Box::into_raw(Box::new(OrphanBag {
+                    epoch,
+                    ptrs: bag.into_iter().map(|ptr| ptr as *mut ()).collect(),
+                    free: free_erased::<T>,
+                    next: std::ptr::null_mut(),
+                })));
+            }
+        }
+        self.domain.local_epochs[self.index].store(0, Ordering::Release);
+        self.domain
+            .thread_registry
+            .fetch_or(1 << self.index, Ordering::Release);
+    }
+}
+
+// type-erased limbo bag, see EbrDomain::orphans
+struct OrphanBag {
+    epoch: usize,
+    ptrs: Vec<*mut ()>,
+    // monomorphized for the T of guard that has retired the pointers
+    free: unsafe fn(*mut ()),
+    next: *mut OrphanBag,
+}
+
+impl OrphanBag {
+    fn free(self) {
+        for ptr in self.ptrs {
+            unsafe { (self.free)(ptr) };
+        }
+    }
+}
+
+unsafe fn free_erased<T>(ptr: *mut ()) {
+    unsafe { drop(Box::from_raw(ptr as *mut T)) };
+}
+
+pub struct EbrProtectedPointer<'a, T> {
+    ptr: NonNull<T>,
+    guard: &'a EbrGuard<'a, T>,
+}
+
+impl<'a, T> EbrProtectedPointer<'a, T> {
+    // see ProtectedPointer::as_ptr(): address stays protected, nothing is transferred
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<'a, T> std::ops::Deref for EbrProtectedPointer<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for EbrProtectedPointer<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'a, T> Drop for EbrProtectedPointer<'a, T> {
+    fn drop(&mut self) {
+        self.guard.unpin();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EbrDomain, EbrGuard, ADVANCE_THRESHOLD, LIMBO_BAGS};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // payload, that retires its successor with the same guard, that is freeing it
+    struct Reentrant {
+        guard: *const EbrGuard<'static, Reentrant>,
+        next: *mut Reentrant,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    // raw pointers to the guard, so it's only ever freed on the guard's own thread here
+    unsafe impl Send for Reentrant {}
+
+    impl Drop for Reentrant {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            if !self.next.is_null() {
+                unsafe { (*self.guard).retire_raw_pointer(self.next) };
+            }
+        }
+    }
+
+    #[test]
+    fn test_reentrant_retire_from_destructor() {
+        let domain = EbrDomain::new();
+        let guard = domain.register_thread::<Reentrant>().ok().unwrap();
+        let guard_ptr = &guard as *const EbrGuard<Reentrant> as *const _;
+        let dropped = Arc::new(AtomicUsize::new(0));
+        // enough chains for epoch to advance a few times, so that bags are freed from within
+        // both collect() and retire_raw_pointer(), while chains keep retiring into them
+        let (chains, chain_len) = (8 * ADVANCE_THRESHOLD, 3);
+        for _ in 0..chains {
+            let head = (0..chain_len).fold(std::ptr::null_mut(), |next, _| {
+                Box::into_raw(Box::new(Reentrant {
+                    guard: guard_ptr,
+                    next,
+                    dropped: dropped.clone(),
+                }))
+            });
+            guard.retire_raw_pointer(head);
+        }
+        assert!(dropped.load(Ordering::Relaxed) > 0);
+    }
+
+    struct DropFlag(Arc<AtomicUsize>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_orphans() {
+        let domain = EbrDomain::new();
+        let drops = Arc::new(AtomicUsize::new(0));
+        // pinned reader keeps epoch from advancing, so retirer can't free anything before it's gone
+        let reader = domain.register_thread::<usize>().ok().unwrap();
+        let pinned = unsafe { reader.protect(Box::into_raw(Box::new(0))) }.ok().unwrap();
+        let retirer = domain.register_thread::<DropFlag>().ok().unwrap();
+        for _ in 0..3 {
+            retirer.retire_raw_pointer(Box::into_raw(Box::new(DropFlag(drops.clone()))));
+        }
+        drop(retirer);
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        assert!(!domain.orphans.load(Ordering::Relaxed).is_null());
+
+        // adopted by a guard of another type, once epoch has moved far enough
+        let reader_ptr = pinned.as_mut_ptr();
+        drop(pinned);
+        for _ in 0..LIMBO_BAGS * (ADVANCE_THRESHOLD + 1) {
+            reader.retire_raw_pointer(Box::into_raw(Box::new(0)));
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+        assert!(domain.orphans.load(Ordering::Relaxed).is_null());
+        reader.retire_raw_pointer(reader_ptr);
+        drop(reader);
+
+        // whatever is left on domain's drop is freed there
+        let domain = EbrDomain::new();
+        let reader = domain.register_thread::<usize>().ok().unwrap();
+        let pinned = unsafe { reader.protect(Box::into_raw(Box::new(0))) }.ok().unwrap();
+        let retirer = domain.register_thread::<DropFlag>().ok().unwrap();
+        retirer.retire_raw_pointer(Box::into_raw(Box::new(DropFlag(drops.clone()))));
+        drop(retirer);
+        // reader stays pinned till its own drop, which doesn't get to advance epoch either
+        let reader_ptr = pinned.as_mut_ptr();
+        std::mem::forget(pinned);
+        drop(reader);
+        drop(unsafe { Box::from_raw(reader_ptr) });
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+        drop(domain);
+        assert_eq!(drops.load(Ordering::Relaxed), 4);
+    }
+}
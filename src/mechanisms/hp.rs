@@ -1,80 +1,545 @@
-use std::cell::{Cell, RefCell};
-use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
-
-// limited by HazardPointerArray's thread_registry bitmap size, i.e. 64
-const MAX_THREADS: usize = 4;
-// limited by HazardPointerGuard's available_indices bitmap size, i.e. 64
-const HP_PER_THREAD: usize = 16;
-const SCAN_THRESHOLD: usize = 2 * HP_PER_THREAD;
-
-pub struct HazardPointerArray {
-    // unit type pointers, so that we could use HazardPointerArray as a static
-    p_list: [AtomicPtr<()>; MAX_THREADS * HP_PER_THREAD],
+#[cfg(feature = "scan-stats")]
+use crate::mechanisms::scan_stats::{ScanCounters, ScanStats};
+use crate::utils::backoff::Backoff;
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/*
+    MAX_THREADS is limited by thread_registry bitmap size, and HP_PER_THREAD by guard's
+    available_indices bitmap size, i.e. both by 64 (checked at compile time, see with_limits())
+*/
+/// ```compile_fail
+/// use lock_freedom::mechanisms::hp::HazardPointerArray;
+///
+/// // thread_registry has no room for the 65th thread slot
+/// let array = HazardPointerArray::<65>::with_limits();
+/// let guard = array.register_thread::<usize>();
+/// ```
+pub struct HazardPointerArray<const MAX_THREADS: usize = 64, const HP_PER_THREAD: usize = 16> {
+    // unit type pointers, so that we could use HazardPointerArray as a static.
+    // sub-array per thread slot
+    p_list: [[AtomicPtr<()>; HP_PER_THREAD]; MAX_THREADS],
+    // see local_slots(). per thread slot, allocated by its first registering thread, and used
+    // instead of the corresponding p_list sub-array
+    local_chunks: [AtomicPtr<SlotChunk<HP_PER_THREAD>>; MAX_THREADS],
+    local_slots: bool,
     // in this bitmap, 1's stand for ready-to-use slots (sub-arrays) in p_array
     thread_registry: AtomicU64,
+    // approximate size of retired, but not yet freed nodes across all guards' d_lists
+    pending_bytes: AtomicUsize,
+    memory_ceiling: usize,
+    /*
+        nodes left unreclaimed by dropped guards, adopted by the next scan of any guard.
+        guards of the same array may be registered for different T, so orphans are type-erased:
+        each one carries the reclaim function of its original guard's T (and its own destructor,
+        if it was retired with retire_boxed()), so adopting guard never assumes its own T
+    */
+    orphans: AtomicPtr<Orphan>,
+    // see fair_registration()
+    fair_registration: bool,
+    next_slot: AtomicUsize,
+    // see global_scan(). per thread slot, d_list of the guard registered there
+    global_scan: bool,
+    shared_d_lists: [Mutex<Option<SharedDList<MAX_THREADS, HP_PER_THREAD>>>; MAX_THREADS],
+    #[cfg(feature = "scan-stats")]
+    scan_counters: ScanCounters,
+}
+
+impl Default for HazardPointerArray {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HazardPointerArray {
     pub const fn new() -> Self {
-        const NULL_PTR: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
-        let pointers: [AtomicPtr<()>; MAX_THREADS * HP_PER_THREAD] =
-            [NULL_PTR; MAX_THREADS * HP_PER_THREAD];
+        Self::with_limits()
+    }
 
-        assert!(MAX_THREADS <= 64, "MAX_THREADS must be less or equal to 64");
+    // see memory_ceiling()
+    pub const fn with_memory_ceiling(memory_ceiling: usize) -> Self {
+        Self::with_limits().memory_ceiling(memory_ceiling)
+    }
+}
+
+impl<const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    HazardPointerArray<MAX_THREADS, HP_PER_THREAD>
+{
+    /*
+        array of non-default size, e.g. a small one per collection, or a larger guard budget
+        for collections with many hazards per operation:
+        static ARRAY: HazardPointerArray<8, 32> = HazardPointerArray::with_limits();
+        or HazardPointerArray::<8>::with_limits() elsewhere. new() and with_memory_ceiling()
+        build the default HazardPointerArray<64, 16> only, so that plain
+        HazardPointerArray::new() needs no annotations
+    */
+    pub const fn with_limits() -> Self {
+        const {
+            assert!(
+                MAX_THREADS > 0 && MAX_THREADS <= 64,
+                "MAX_THREADS must be between 1 and 64"
+            );
+            assert!(
+                HP_PER_THREAD > 0 && HP_PER_THREAD <= 64,
+                "HP_PER_THREAD must be between 1 and 64"
+            );
+        };
         let thread_registry = !0 >> (64 - MAX_THREADS);
 
         Self {
-            p_list: pointers,
+            p_list: [const { [const { AtomicPtr::new(std::ptr::null_mut()) }; HP_PER_THREAD] };
+                MAX_THREADS],
+            local_chunks: [const { AtomicPtr::new(std::ptr::null_mut()) }; MAX_THREADS],
+            local_slots: false,
             thread_registry: AtomicU64::new(thread_registry),
+            pending_bytes: AtomicUsize::new(0),
+            memory_ceiling: usize::MAX,
+            orphans: AtomicPtr::new(std::ptr::null_mut()),
+            fair_registration: false,
+            next_slot: AtomicUsize::new(0),
+            global_scan: false,
+            shared_d_lists: [const { Mutex::new(None) }; MAX_THREADS],
+            #[cfg(feature = "scan-stats")]
+            scan_counters: ScanCounters::new(),
         }
     }
 
-    pub fn register_thread<T>(&self) -> Result<HazardPointerGuard<T>, RegisterThreadError> {
+    /*
+        backpressure for reclamation: once pending_bytes() exceeds memory_ceiling, retiring
+        thread blocks (scanning with backoff) until it manages to free some of its own retired
+        nodes. without it, a single stalled reader lets retained memory grow unbounded.
+        guard only frees its own d_list (unless global_scan() is on), so a thread with nothing of
+        its own left to free doesn't block: the bound is approximately memory_ceiling + MAX_THREADS * size_of::<T>().
+        note, that retiring thread may block for as long as the reader is stalled, so a thread
+        must not retire while holding protections that some other retiring thread waits for
+    */
+    pub const fn memory_ceiling(mut self, memory_ceiling: usize) -> Self {
+        self.memory_ceiling = memory_ceiling;
+        self
+    }
+
+    /*
+        by default register_thread() claims the lowest free slot, so under thread churn the same
+        few low slots are reused over and over, and concurrently registering threads all race
+        for the same lowest bit. with fair registration, every registration starts searching
+        from the next slot in round-robin order (wrapping around), so assignments spread over
+        all slots and racing threads mostly aim at different bits.
+        e.g. HazardPointerArray::with_memory_ceiling(limit).fair_registration()
+    */
+    pub const fn fair_registration(mut self) -> Self {
+        self.fair_registration = true;
+        self
+    }
+
+    /*
+        on NUMA systems, p_list lives on whichever node first touched its pages (with a static,
+        the one of the thread that happened to run first), so most threads' protect() and
+        unprotect() go across nodes. with local slots, each thread slot's hazard pointers live in
+        a page of their own instead, allocated and zeroed by the first thread registering to
+        that slot. platform assumptions:
+        - first-touch page placement (linux default policy, also windows): a page is placed on
+          the node of the thread that first writes to it. with interleaving or explicit
+          membind policies, this option changes nothing;
+        - registering thread stays on its node (is pinned, or at least scheduler keeps it
+          there), otherwise locality is lost on migration anyway;
+        - allocator hands out fresh pages for page-aligned page-sized allocations. that's
+          typical (glibc, jemalloc), but a recycled page may well live on another node already.
+        chunks are never moved: a later thread registering to the same slot gets the chunk
+        of the first one, wherever that is, so it works best with long-lived registrations
+        (e.g. a thread pool, registering once per worker). chunks are freed with the array.
+        scan() reads every slot either way, so it's cross-node with both layouts.
+        e.g. static ARRAY: HazardPointerArray = HazardPointerArray::new().local_slots();
+    */
+    pub const fn local_slots(mut self) -> Self {
+        self.local_slots = true;
+        self
+    }
+
+    // hazard pointers of thread slot, local ones or p_list's sub-array. for a local slot,
+    // it must have been claimed at least once already (see guard_for_slot())
+    fn hazards(&self, slot: usize) -> &[AtomicPtr<()>] {
+        if self.local_slots {
+            let chunk = self.local_chunks[slot].load(Ordering::Acquire);
+            debug_assert!(!chunk.is_null());
+            return unsafe { &(*chunk).0 };
+        }
+        &self.p_list[slot]
+    }
+
+    /*
+        hazard pointers of registered thread slots, so that scan cost follows the number of
+        threads actually registered, rather than MAX_THREADS. free slots are skipped safely:
+        - guard clears its hazards before giving its slot back, so a slot seen free has nothing
+          protected;
+        - a slot claimed after the registry is loaded starts clean, and whatever its guard
+          protects is loaded after the pointers being scanned for had been unlinked, so it
+          fails the recheck against its source. hence SeqCst, both here and on claiming.
+        local slots, that have been claimed, but not allocated yet, are skipped too
+    */
+    fn all_hazards(&self) -> impl Iterator<Item = &AtomicPtr<()>> {
+        let free_slots = self.thread_registry.load(Ordering::SeqCst);
+        (0..MAX_THREADS)
+            .filter(move |&slot| free_slots & (1 << slot) == 0)
+            .filter(|&slot| {
+                !self.local_slots || !self.local_chunks[slot].load(Ordering::Acquire).is_null()
+            })
+            .flat_map(|slot| self.hazards(slot))
+    }
+
+    /*
+        scan() is thread-local: a guard frees its own d_list only. so if a thread retires a few
+        nodes (below SCAN_THRESHOLD) and goes idle, its backlog stays until it's back, however
+        hard other threads work. with global scan, a guard, whose own scan freed little (say,
+        its nodes are still held by readers, or it's try_reclaim()ing a nearly empty d_list),
+        goes on to reclaim other guards' backlogs too.
+        that takes d_lists to be shared: each guard keeps its d_list behind a mutex (it does so
+        regardless of this option, though it's uncontended then), and publishes it in
+        shared_d_lists on its first retirement. synchronization added:
+        - owner locks its d_list on every retire and scan. helper only ever try_locks, both
+          the shared slot and the d_list, skipping whatever is busy, so it never blocks anyone
+          and never waits itself;
+        - helper takes its p_list snapshot only after it has locked the d_list: every pointer
+          in there has been retired (unlinked) before the snapshot, just like with owner's scan;
+        - unprotected pointers are taken out under the lock, but freed after it's released,
+          so owner's retire() never waits for foreign destructors;
+        - guard unpublishes its d_list on drop (and erase()), blocking on the shared slot,
+          which helper holds for as long as it works with that d_list.
+        note, that pointers (and their destructors) may be freed on another thread then, just
+        like orphans are. e.g. HazardPointerArray::new().global_scan()
+    */
+    pub const fn global_scan(mut self) -> Self {
+        self.global_scan = true;
+        self
+    }
+
+    // sorted non-null hazard pointers of all guards
+    fn p_list_snapshot(&self) -> Vec<*mut ()> {
+        let mut p_list_snapshot = self
+            .all_hazards()
+            .map(|e| e.load(Ordering::Acquire))
+            .filter(|ptr| !ptr.is_null())
+            .collect::<Vec<_>>();
+        p_list_snapshot.sort();
+        p_list_snapshot.dedup();
+        p_list_snapshot
+    }
+
+    // see global_scan(). d_lists, that are busy (or being unpublished), are skipped
+    fn help_scan(&self, own_slot: usize) -> usize {
+        (0..MAX_THREADS)
+            .filter(|&slot| slot != own_slot)
+            .map(|slot| {
+                let Ok(shared) = self.shared_d_lists[slot].try_lock() else {
+                    return 0;
+                };
+                match &*shared {
+                    Some(shared) => unsafe { (shared.help)(shared.d_list, self) },
+                    None => 0,
+                }
+            })
+            .sum()
+    }
+
+    // approximate: counts size_of::<T>() per retired node, whatever its destructor actually frees
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes.load(Ordering::Relaxed)
+    }
+
+    // scan durations and reclamation latency so far, e.g. for tuning SCAN_THRESHOLD or finding
+    // out what keeps nodes unreclaimed. see scan_stats module
+    #[cfg(feature = "scan-stats")]
+    pub fn scan_stats(&self) -> ScanStats {
+        self.scan_counters.snapshot()
+    }
+
+    /*
+        health snapshot of the whole array, for all collections that share it. relaxed loads
+        only, no locking: fields are read one by one while threads keep working, so they're
+        only consistent with each other up to what's in flight
+    */
+    pub fn diagnostics(&self) -> Diagnostics {
+        // thread_registry bit is set for a free slot
+        let free_thread_slots = self.thread_registry.load(Ordering::Relaxed).count_ones() as usize;
+        #[cfg(feature = "scan-stats")]
+        let scan_stats = self.scan_counters.snapshot();
+        Diagnostics {
+            registered_threads: MAX_THREADS - free_thread_slots,
+            free_thread_slots,
+            protected: self
+                .all_hazards()
+                .filter(|e| !e.load(Ordering::Relaxed).is_null())
+                .count(),
+            pending_bytes: self.pending_bytes(),
+            #[cfg(feature = "scan-stats")]
+            scans: scan_stats.scans,
+            #[cfg(feature = "scan-stats")]
+            reclaimed: scan_stats.reclaimed,
+        }
+    }
+
+    pub fn register_thread<T>(
+        &self,
+    ) -> Result<HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD>, RegisterThreadError> {
         loop {
             let thread_registry = self.thread_registry.load(Ordering::Relaxed);
             if thread_registry == 0 {
                 return Err(RegisterThreadError::NoAvailableIndices);
             } else {
-                let tr_first_slot = thread_registry.trailing_zeros() as usize;
+                let tr_first_slot = self.pick_slot(thread_registry);
                 if self
                     .thread_registry
                     .compare_exchange_weak(
                         thread_registry,
                         thread_registry ^ (1 << tr_first_slot),
-                        Ordering::AcqRel,
+                        Ordering::SeqCst,
                         Ordering::Relaxed,
                     )
                     .is_ok()
                 {
-                    return Ok(HazardPointerGuard {
-                        array: &self,
-                        starting_idx: tr_first_slot * HP_PER_THREAD,
-                        available_indices: Cell::new(!0 >> (64 - HP_PER_THREAD)),
-                        d_list: RefCell::new(Vec::new()),
-                    });
+                    return Ok(self.guard_for_slot(tr_first_slot));
                 }
             }
         }
     }
+
+    /*
+        registers n guards at once, e.g. for a thread pool coordinator to hand one to each worker:
+        all n slots are claimed with a single CAS, instead of n registrations racing each other
+        on thread_registry. all or nothing: if fewer than n slots are free, nothing is claimed.
+        takes the lowest free slots, regardless of fair_registration()
+    */
+    pub fn register_pool<T>(
+        &self,
+        n: usize,
+    ) -> Result<Vec<HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD>>, RegisterThreadError> {
+        loop {
+            let thread_registry = self.thread_registry.load(Ordering::Relaxed);
+            if (thread_registry.count_ones() as usize) < n {
+                return Err(RegisterThreadError::NoAvailableIndices);
+            }
+            let mut claimed = 0u64;
+            for _ in 0..n {
+                claimed |= 1 << (thread_registry & !claimed).trailing_zeros();
+            }
+            if self
+                .thread_registry
+                .compare_exchange_weak(
+                    thread_registry,
+                    thread_registry ^ claimed,
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return Ok((0..MAX_THREADS)
+                    .filter(|slot| claimed & (1 << slot) != 0)
+                    .map(|slot| self.guard_for_slot(slot))
+                    .collect());
+            }
+        }
+    }
+
+    // slot must be claimed in thread_registry already
+    fn guard_for_slot<T>(
+        &self,
+        slot: usize,
+    ) -> HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD> {
+        if self.local_slots && self.local_chunks[slot].load(Ordering::Relaxed).is_null() {
+            // first touch happens here, on the registering thread. slot is ours, so nobody
+            // else allocates it concurrently, and scanners only read it once it's published
+            let chunk = Box::into_raw(Box::new(SlotChunk::new()));
+            self.local_chunks[slot].store(chunk, Ordering::Release);
+        }
+        HazardPointerGuard {
+            array: self,
+            starting_idx: slot * HP_PER_THREAD,
+            available_indices: Cell::new(!0 >> (64 - HP_PER_THREAD)),
+            peak_protected: Cell::new(0),
+            d_list: Box::new(Mutex::new(Vec::new())),
+            backlog_hook: Cell::new(None),
+            d_list_published: Cell::new(false),
+        }
+    }
+
+    // first free slot in thread_registry (non-empty), see fair_registration()
+    fn pick_slot(&self, thread_registry: u64) -> usize {
+        if !self.fair_registration {
+            return thread_registry.trailing_zeros() as usize;
+        }
+        let start = self.next_slot.fetch_add(1, Ordering::Relaxed) % MAX_THREADS;
+        let from_start = thread_registry & (!0 << start);
+        if from_start != 0 {
+            from_start.trailing_zeros() as usize
+        } else {
+            thread_registry.trailing_zeros() as usize
+        }
+    }
+
+    // pushes a chain of orphans (first..=last) at once
+    fn push_orphans(&self, first: *mut Orphan, last: *mut Orphan) {
+        let mut head = self.orphans.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*last).next = head };
+            match self
+                .orphans
+                .compare_exchange_weak(head, first, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    // takes the whole orphan list, so that no other guard adopts the same orphan concurrently
+    fn take_orphans(&self) -> *mut Orphan {
+        if self.orphans.load(Ordering::Relaxed).is_null() {
+            return std::ptr::null_mut();
+        }
+        self.orphans.swap(std::ptr::null_mut(), Ordering::Acquire)
+    }
 }
 
-unsafe impl Send for HazardPointerArray {}
-// no Send impl for HazardPointerGuard since it is supposed for static usage
+impl<const MAX_THREADS: usize, const HP_PER_THREAD: usize> Drop
+    for HazardPointerArray<MAX_THREADS, HP_PER_THREAD>
+{
+    fn drop(&mut self) {
+        // guards borrow the array, so none of them is alive, and nothing is protected anymore
+        let mut current = *self.orphans.get_mut();
+        while !current.is_null() {
+            let orphan = unsafe { Box::from_raw(current) };
+            current = orphan.next;
+            orphan.reclaim();
+        }
+        for chunk in &mut self.local_chunks {
+            let chunk = *chunk.get_mut();
+            if !chunk.is_null() {
+                drop(unsafe { Box::from_raw(chunk) });
+            }
+        }
+    }
+}
+
+unsafe impl<const MAX_THREADS: usize, const HP_PER_THREAD: usize> Send
+    for HazardPointerArray<MAX_THREADS, HP_PER_THREAD>
+{
+}
 
-pub struct HazardPointerGuard<'a, T> {
-    array: &'a HazardPointerArray,
+// guard's d_list, published for other guards to help with, see global_scan()
+struct SharedDList<const MAX_THREADS: usize, const HP_PER_THREAD: usize> {
+    // boxed Mutex<Vec<Retired<T>>> of the guard, that stays put until it's unpublished
+    d_list: *const (),
+    // monomorphized for the guard's T
+    help: unsafe fn(*const (), &HazardPointerArray<MAX_THREADS, HP_PER_THREAD>) -> usize,
+}
+
+// only dereferenced under shared_d_lists lock, while the owning guard is alive
+unsafe impl<const MAX_THREADS: usize, const HP_PER_THREAD: usize> Send
+    for SharedDList<MAX_THREADS, HP_PER_THREAD>
+{
+}
+
+// see global_scan(). d_list must be published, i.e. its guard must still be alive.
+// frees other guards' nodes on this thread, hence T: Send
+unsafe fn help_reclaim<T: Send, const MAX_THREADS: usize, const HP_PER_THREAD: usize>(
+    d_list: *const (),
+    array: &HazardPointerArray<MAX_THREADS, HP_PER_THREAD>,
+) -> usize {
+    let d_list = unsafe { &*(d_list as *const Mutex<Vec<Retired<T>>>) };
+    // owner is retiring or scanning itself
+    let Ok(mut d_list) = d_list.try_lock() else {
+        return 0;
+    };
+    // taken under the lock, so every pointer in d_list was retired before the snapshot
+    let p_list_snapshot = array.p_list_snapshot();
+    let (unprotected, protected): (Vec<_>, Vec<_>) = std::mem::take(&mut *d_list)
+        .into_iter()
+        .partition(|item| p_list_snapshot.binary_search(&(item.ptr as *mut ())).is_err());
+    *d_list = protected;
+    drop(d_list);
+    let freed = unprotected.len();
+    array
+        .pending_bytes
+        .fetch_sub(freed * std::mem::size_of::<T>(), Ordering::Relaxed);
+    for item in unprotected {
+        #[cfg(feature = "scan-stats")]
+        array.scan_counters.record_reclaim(item.retired_at_scan);
+        item.reclaim();
+    }
+    freed
+}
+
+// d_list is never left inconsistent: a panicking destructor only loses its own pointer
+fn lock_ignoring_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// hazard pointers of a single thread slot, in a page of their own, see local_slots()
+#[repr(align(4096))]
+struct SlotChunk<const HP_PER_THREAD: usize>([AtomicPtr<()>; HP_PER_THREAD]);
+
+impl<const HP_PER_THREAD: usize> SlotChunk<HP_PER_THREAD> {
+    fn new() -> Self {
+        SlotChunk([const { AtomicPtr::new(std::ptr::null_mut()) }; HP_PER_THREAD])
+    }
+}
+
+// (limit, callback), see HazardPointerGuard::on_backlog_exceeded()
+type BacklogHook = (usize, Box<dyn Fn(usize)>);
+
+// no Send impl for HazardPointerGuard since it is supposed for static usage
+pub struct HazardPointerGuard<
+    'a,
+    T,
+    const MAX_THREADS: usize = 64,
+    const HP_PER_THREAD: usize = 16,
+> {
+    array: &'a HazardPointerArray<MAX_THREADS, HP_PER_THREAD>,
     starting_idx: usize,
     available_indices: Cell<u64>,
-    d_list: RefCell<Vec<*mut T>>,
+    // max number of simultaneously protected pointers since the last reset_peak_protected()
+    peak_protected: Cell<usize>,
+    // boxed, so that it stays put for other guards to help with, see global_scan()
+    d_list: Box<Mutex<Vec<Retired<T>>>>,
+    backlog_hook: Cell<Option<BacklogHook>>,
+    // see global_scan(). d_list is published on the first retirement, that is where T: Send
+    // is known, and only then may other guards help with it
+    d_list_published: Cell<bool>,
 }
 
-impl<T> HazardPointerGuard<'_, T> {
-    // safety: it is user's duty to ensure that the pointer is valid
-    // and that there's no concurrent modification or freeing of the pointer
-    pub unsafe fn protect(&self, data_ptr: *mut T) -> Result<ProtectedPointer<T>, ProtectionError> {
-        if data_ptr.is_null() {
+/*
+    thin pointers only: hazard slots hold bare addresses (*mut ()), so a fat pointer's metadata
+    (dyn Trait's vtable, slice's length) would have nowhere to go. guard's T is Sized, which keeps
+    *mut dyn Trait or *mut [u8] out at compile time already, and protect() asserts it (at compile
+    time as well) for the slot store, should that bound ever be relaxed. unsized data goes behind
+    a sized node instead, e.g. a guard of Box<dyn Trait> or Box<[u8]>: the node's address is what
+    gets protected and retired, and the fat pointer stays intact inside it
+*/
+impl<T, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD>
+{
+    const SCAN_THRESHOLD: usize = 2 * HP_PER_THREAD;
+
+    /// # Safety
+    ///
+    /// `data_ptr` must be valid, and must not be freed concurrently before the protection is
+    /// published: caller rechecks the pointer against its source after protect(), and only
+    /// dereferences it, if it's still there. no concurrent modification of the pointee either
+    pub unsafe fn protect(
+        &self,
+        data_ptr: *mut T,
+    ) -> Result<ProtectedPointer<'_, T, MAX_THREADS, HP_PER_THREAD>, ProtectionError> {
+        const {
+            assert!(
+                size_of::<*mut T>() == size_of::<*mut ()>(),
+                "HazardPointerGuard::protect(): fat pointers would lose their metadata"
+            )
+        };
+        let Some(non_null_ptr) = NonNull::new(data_ptr) else {
             return Err(ProtectionError::NullPointer);
-        }
+        };
         let current = self.available_indices.get();
         if current == 0 {
             return Err(ProtectionError::NoAvailableIndices);
@@ -82,127 +547,620 @@ impl<T> HazardPointerGuard<'_, T> {
 
         let offset = current.trailing_zeros() as usize;
         self.available_indices.set(current & !(1u64 << offset));
-        self.array.p_list[self.starting_idx + offset].store(unsafe {std::mem::transmute(data_ptr)}, Ordering::Release);
+        self.peak_protected
+            .set(self.peak_protected.get().max(self.protected_count()));
+        self.hazard(offset).store(data_ptr as *mut (), Ordering::Release);
 
         Ok(ProtectedPointer {
-            ptr: data_ptr,
+            ptr: non_null_ptr,
             index: offset,
             guard: self,
         })
     }
 
-    pub fn unprotect(&self, protected_pointer: &ProtectedPointer<T>) {
-        self.array.p_list[self.starting_idx + protected_pointer.index]
+    // offset-th hazard pointer of this guard's slot
+    fn hazard(&self, offset: usize) -> &AtomicPtr<()> {
+        &self.array.hazards(self.starting_idx / HP_PER_THREAD)[offset]
+    }
+
+    // number of pointers currently protected by this guard, out of HP_PER_THREAD
+    pub fn protected_count(&self) -> usize {
+        HP_PER_THREAD - self.available_indices.get().count_ones() as usize
+    }
+
+    // whether ptr is in one of this guard's own slots (other guards' protections don't count)
+    pub fn is_protected(&self, ptr: *const T) -> bool {
+        let occupied = !self.available_indices.get() & (!0 >> (64 - HP_PER_THREAD));
+        (0..HP_PER_THREAD).any(|offset| {
+            occupied & (1u64 << offset) != 0
+                && self.hazard(offset).load(Ordering::Relaxed) == ptr as *mut ()
+        })
+    }
+
+    pub fn peak_protected(&self) -> usize {
+        self.peak_protected.get()
+    }
+
+    // starts peak tracking over from what is protected now, returning that count. collections
+    // use it (in debug builds) to check that an operation stays within its HAZARDS_PER_OP
+    pub fn reset_peak_protected(&self) -> usize {
+        let protected = self.protected_count();
+        self.peak_protected.set(protected);
+        protected
+    }
+
+    pub fn unprotect(&self, protected_pointer: &ProtectedPointer<T, MAX_THREADS, HP_PER_THREAD>) {
+        self.hazard(protected_pointer.index)
             .store(core::ptr::null_mut(), Ordering::Release);
         let indices = self.available_indices.get();
         self.available_indices.set(indices | (1u64 << protected_pointer.index));
     }
+}
+
+/*
+    retired pointers may be freed on another thread: the one, that adopts them as orphans after
+    this guard is dropped, or, with global scan, whichever guard helps with this guard's d_list.
+    so T (and a destructor of retire_boxed()) must be Send to get retired at all, just like it
+    must be to get sent over to that thread
+*/
+impl<T: Send, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD>
+{
+    /*
+        pointer is unprotected first (into_raw() consumes protected pointer, and its drop clears
+        the slot), and only then is pushed to d_list, so for a moment it's neither protected nor
+        retired. that's sound, since nothing may reclaim it in between:
+        - pointer is already unlinked by caller, so no other thread can protect or retire it;
+        - this guard reclaims its d_list only in scan(), that runs strictly after the push
+          (in retire() below), with a fresh p_list snapshot;
+        - other guards reclaim orphans, which come from d_lists of dropped guards as well, and,
+          with global scan, this very d_list, but with a snapshot taken after the push too;
+        so the window is equivalent to the pointer being in d_list unprotected already.
+        this relies on reclamation never happening synchronously on unprotect: if unprotect()
+        ever triggers scan(), pointer must be pushed to d_list before it gets unprotected
+    */
+    pub fn retire_node(&self, protected_pointer: ProtectedPointer<T, MAX_THREADS, HP_PER_THREAD>) {
+        self.retire_raw_pointer(protected_pointer.into_raw());
+    }
 
-    pub fn retire_node(&self, protected_pointer: ProtectedPointer<T>) {
-        let ptr = unsafe { protected_pointer.into_raw()} ;
-        self.retire_raw_pointer(ptr);
+    // takes either a plain raw pointer, or a RetireToken from ProtectedPointer::into_raw()
+    pub fn retire_raw_pointer(&self, ptr: impl Into<RetireToken<T>>) {
+        self.retire(Retired {
+            ptr: ptr.into().ptr,
+            destructor: None,
+            #[cfg(feature = "scan-stats")]
+            retired_at_scan: self.array.scan_counters.current_scan(),
+        });
     }
 
-    pub fn retire_raw_pointer(&self, ptr: *mut T) {
-        let mut d_list = self.d_list.borrow_mut();
-        d_list.push(ptr);
-        if d_list.len() > SCAN_THRESHOLD {
-            drop(d_list);
+    // for pointers, that shouldn't be just freed with Box::from_raw, e.g. nodes that must
+    // return to the specific pool/arena they came from. destructor may capture whatever state
+    // it needs, and is invoked by scan once the pointer is no longer protected.
+    // costs an extra allocation per retirement, so prefer retire_raw_pointer() when possible
+    pub fn retire_boxed(&self, ptr: *mut T, destructor: Box<dyn FnOnce(*mut T) + Send>) {
+        self.retire(Retired {
+            ptr,
+            destructor: Some(destructor),
+            #[cfg(feature = "scan-stats")]
+            retired_at_scan: self.array.scan_counters.current_scan(),
+        });
+    }
+
+    /*
+        for nodes of recursive structures, e.g. a tree node, that owns children, which are
+        hazard-managed (protected and retired) on their own. plain retirement would free such node
+        with Box::from_raw, running its Drop, which may well free the children recursively (that's
+        what it'd do on single-threaded teardown), while other threads still protect some of them.
+        shallow_dtor must free the node's own allocation only, and leave the children alone: each
+        one is retired separately, once it's unlinked, and is freed when nobody protects it.
+        e.g. retire_shallow(node, dealloc_shallow), or a destructor, that detaches children first
+        (sets them to null) and then drops the node, if node owns anything else, that must be freed
+    */
+    pub fn retire_shallow(&self, ptr: *mut T, shallow_dtor: fn(*mut T))
+    where
+        T: 'static,
+    {
+        self.retire_boxed(ptr, Box::new(shallow_dtor));
+    }
+
+    /*
+        frees the pointer right away if is_safe() confirms, that no hazard pointer may refer to it,
+        e.g. a node that was allocated, but never published (like the node of a failed enqueue):
+        then neither d_list nor scan is touched. otherwise it's retired as usual.
+        only this guard's own slots are checked (in debug builds), other threads' hazard pointers
+        are not
+    */
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, and must not be freed or retired elsewhere.
+    /// whenever `is_safe()` returns true, `ptr` must never have been published, i.e. no other
+    /// thread may hold or be about to protect it
+    pub unsafe fn retire_immediate_if(&self, ptr: *mut T, is_safe: impl FnOnce() -> bool) {
+        if is_safe() {
+            debug_assert!(
+                !self.is_protected(ptr),
+                "HazardPointerGuard::retire_immediate_if(): pointer is protected by this guard"
+            );
+            unsafe { drop(Box::from_raw(ptr)) };
+        } else {
+            self.retire_raw_pointer(ptr);
+        }
+    }
+
+    fn retire(&self, retired: Retired<T>) {
+        if self.array.global_scan && !self.d_list_published.get() {
+            self.publish_d_list();
+        }
+        let mut d_list = lock_ignoring_poison(&self.d_list);
+        d_list.push(retired);
+        self.array
+            .pending_bytes
+            .fetch_add(std::mem::size_of::<T>(), Ordering::Relaxed);
+        let should_scan = d_list.len() > Self::SCAN_THRESHOLD;
+        drop(d_list);
+        if should_scan {
             self.scan();
+            self.check_backlog();
+        }
+        if self.array.pending_bytes() > self.array.memory_ceiling {
+            self.wait_for_memory_ceiling();
+        }
+    }
+
+    // see global_scan(). unpublished by release_d_list()
+    fn publish_d_list(&self) {
+        *lock_ignoring_poison(&self.array.shared_d_lists[self.starting_idx / HP_PER_THREAD]) =
+            Some(SharedDList {
+                d_list: &*self.d_list as *const Mutex<Vec<Retired<T>>> as *const (),
+                help: help_reclaim::<T, MAX_THREADS, HP_PER_THREAD>,
+            });
+        self.d_list_published.set(true);
+    }
+}
+
+impl<T, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    HazardPointerGuard<'_, T, MAX_THREADS, HP_PER_THREAD>
+{
+    // see HazardPointerArray::with_memory_ceiling()
+    fn wait_for_memory_ceiling(&self) {
+        let mut backoff = Backoff::new();
+        while self.array.pending_bytes() > self.array.memory_ceiling
+            && !lock_ignoring_poison(&self.d_list).is_empty()
+        {
+            if self.scan() > 0 {
+                return;
+            }
+            backoff.spin_yield();
         }
     }
 
+    /*
+        retired pointers are freed only on scan, which is normally triggered by d_list crossing
+        SCAN_THRESHOLD. that amortizes scan cost (a pass over the whole p_list) over many
+        retirements, but nodes retired by a thread, that has mostly stopped retiring, may stay
+        in its d_list for a long time after their readers have gone. such a thread may call
+        try_reclaim() whenever it's idle to free its backlog, trading a bit of throughput for
+        lower memory retention. returns the number of freed pointers.
+        we don't scan on unprotect() implicitly: guard's own slots don't tell anything about
+        other threads' hazard pointers, so that would mostly be a wasted p_list pass on a hot path
+    */
+    pub fn try_reclaim(&self) -> usize {
+        if lock_ignoring_poison(&self.d_list).is_empty()
+            && self.array.orphans.load(Ordering::Relaxed).is_null()
+            && !self.array.global_scan
+        {
+            return 0;
+        }
+        self.scan()
+    }
+
+    /*
+        d_list, that a scan couldn't shrink, means some reader holds on to retired nodes (say, it
+        has stalled), and the backlog grows with every retirement: a leak in disguise, until it
+        is released. callback is invoked with the backlog size from retire(), after every scan,
+        that has left limit or more pointers in d_list, e.g. to log a warning or alert someone.
+        it's a diagnostic hook only, and must not retire with this guard: it's not reentered,
+        so whatever it retires goes unchecked. a new callback replaces the previous one
+    */
+    pub fn on_backlog_exceeded(&self, limit: usize, callback: impl Fn(usize) + 'static) {
+        self.backlog_hook.set(Some((limit, Box::new(callback))));
+    }
+
+    fn check_backlog(&self) {
+        // taken out while it runs, so that whatever it does with the guard, it isn't reentered
+        let Some((limit, callback)) = self.backlog_hook.take() else {
+            return;
+        };
+        let backlog = lock_ignoring_poison(&self.d_list).len();
+        if backlog >= limit {
+            callback(backlog);
+        }
+        // unless the callback has set a new one
+        let replaced = self.backlog_hook.take();
+        self.backlog_hook.set(replaced.or(Some((limit, callback))));
+    }
+
     // here, we perform 'thread-local' scan
-    fn scan(&self) {
-        let mut p_list_snapshot = self
-            .array
-            .p_list
-            .iter()
-            .filter_map(|e| {
-                let ptr = e.load(Ordering::Acquire);
-                if !ptr.is_null() {
-                    return Some(ptr);
-                }
-                None
-            })
-            .collect::<Vec<_>>();
-        p_list_snapshot.dedup();
-        p_list_snapshot.sort();
+    fn scan(&self) -> usize {
+        #[cfg(feature = "scan-stats")]
+        let started = std::time::Instant::now();
+        let p_list_snapshot = self.array.p_list_snapshot();
         // if not found in p_list then deallocate
         // else push to new_d_list
-        let mut d_list = self.d_list.borrow_mut();
-        let old_list = std::mem::take(&mut *d_list);
-
-        *d_list = old_list
+        /*
+            destructors run after d_list is unlocked: T's Drop may well call back into a
+            collection, that retires with this very guard (e.g. a pooled object, that pushes
+            itself back on drop). such retire() simply lands in the fresh d_list, and may even
+            scan it in turn, instead of deadlocking on the lock, that we'd still hold. protecting
+            from there is fine too: the snapshot is done with, and pointers being freed are out
+            of d_list already, so nested scans never see them
+        */
+        let mut d_list = lock_ignoring_poison(&self.d_list);
+        let (unprotected, protected): (Vec<_>, Vec<_>) = std::mem::take(&mut *d_list)
             .into_iter()
-            .filter_map(|item| {
-                if p_list_snapshot.binary_search(&(unsafe { std::mem::transmute(item)})).is_err() {
-                    unsafe {
-                        let _ = Box::from_raw(item);
-                    }
-                    None
-                } else {
-                    Some(item)
+            .partition(|item| p_list_snapshot.binary_search(&(item.ptr as *mut ())).is_err());
+        *d_list = protected;
+        drop(d_list);
+        let freed = unprotected.len();
+        self.array
+            .pending_bytes
+            .fetch_sub(freed * std::mem::size_of::<T>(), Ordering::Relaxed);
+        for item in unprotected {
+            #[cfg(feature = "scan-stats")]
+            self.array.scan_counters.record_reclaim(item.retired_at_scan);
+            item.reclaim();
+        }
+        let mut freed = freed + self.adopt_orphans();
+        if self.array.global_scan && freed < Self::SCAN_THRESHOLD / 2 {
+            freed += self.array.help_scan(self.starting_idx / HP_PER_THREAD);
+        }
+        #[cfg(feature = "scan-stats")]
+        self.array.scan_counters.record_scan(started.elapsed(), freed);
+        freed
+    }
+
+    /*
+        orphans are reclaimed with their own reclaim functions, whatever T of this guard is.
+        they need a snapshot of their own, taken after they're taken: scan's snapshot may predate
+        an orphan's retirement (its guard may have unlinked it and dropped since), and miss
+        a protection, that was published in between and still holds
+    */
+    fn adopt_orphans(&self) -> usize {
+        let mut current = self.array.take_orphans();
+        if current.is_null() {
+            return 0;
+        }
+        let p_list_snapshot = self.array.p_list_snapshot();
+        let (mut first_left, mut last_left): (*mut Orphan, *mut Orphan) =
+            (std::ptr::null_mut(), std::ptr::null_mut());
+        let mut freed = 0;
+        while !current.is_null() {
+            let next = unsafe { (*current).next };
+            if p_list_snapshot.binary_search(&unsafe { (*current).ptr }).is_err() {
+                let orphan = unsafe { Box::from_raw(current) };
+                self.array.pending_bytes.fetch_sub(orphan.size, Ordering::Relaxed);
+                #[cfg(feature = "scan-stats")]
+                self.array.scan_counters.record_reclaim(orphan.retired_at_scan);
+                orphan.reclaim();
+                freed += 1;
+            } else {
+                // still protected, relink into the list of leftovers
+                unsafe { (*current).next = first_left };
+                if last_left.is_null() {
+                    last_left = current;
                 }
-            })
-            .collect();
+                first_left = current;
+            }
+            current = next;
+        }
+        if !first_left.is_null() {
+            self.array.push_orphans(first_left, last_left);
+        }
+        freed
     }
 
     // just for the sake of completeness
     pub fn unregister_thread(self) {
         drop(self);
     }
+
+    // see Drop below
+    fn clear_hazards(&self) {
+        for hazard in self.array.hazards(self.starting_idx / HP_PER_THREAD) {
+            hazard.store(std::ptr::null_mut(), Ordering::Release);
+        }
+        self.available_indices.set(!0 >> (64 - HP_PER_THREAD));
+    }
+
+    // frees what it can and hands over whatever is still protected to the array,
+    // where it's still counted as pending
+    fn release_d_list(&mut self) {
+        if self.d_list_published.replace(false) {
+            // waits for whoever is helping with d_list right now
+            *lock_ignoring_poison(&self.array.shared_d_lists[self.starting_idx / HP_PER_THREAD]) =
+                None;
+        }
+        self.scan();
+        let (mut first, mut last): (*mut Orphan, *mut Orphan) =
+            (std::ptr::null_mut(), std::ptr::null_mut());
+        let d_list = self.d_list.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for retired in std::mem::take(d_list) {
+            let orphan = Box::into_raw(Box::new(retired.into_orphan(first)));
+            if last.is_null() {
+                last = orphan;
+            }
+            first = orphan;
+        }
+        if !first.is_null() {
+            self.array.push_orphans(first, last);
+        }
+    }
+}
+
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    HazardPointerGuard<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
+    /*
+        drops everything typed about the guard, keeping only its thread slot reserved, e.g. to keep
+        guards of different T in one registry. retired pointers go the same way as on drop (freed
+        or orphaned), and no pointer can be protected at this point, since protected pointers
+        borrow the guard. erased guard can neither protect nor retire: all it can do is to give
+        the slot back, when it's unregistered or dropped
+    */
+    pub fn erase(mut self) -> ErasedGuard<'a, MAX_THREADS, HP_PER_THREAD> {
+        self.release_d_list();
+        self.clear_hazards();
+        let erased = ErasedGuard {
+            array: self.array,
+            starting_idx: self.starting_idx,
+        };
+        // slot now belongs to erased guard, and there's nothing else left to drop but (empty)
+        // d_list itself and backlog hook
+        let d_list = unsafe { std::ptr::read(&self.d_list) };
+        let backlog_hook = self.backlog_hook.take();
+        std::mem::forget(self);
+        drop(d_list);
+        drop(backlog_hook);
+        erased
+    }
 }
 
-impl<'a, T> Drop for HazardPointerGuard<'a, T> {
+/*
+    guard goes away the same way, whether its thread unregisters or unwinds from a panic mid
+    operation (protected pointers borrow the guard, so they are dropped, and unprotected, first):
+    1. release_d_list(): last scan, then whatever is still protected elsewhere is handed over
+       to the array's orphans, which survivors adopt in their scans. a retired pointer is always
+       either in d_list or in orphans, never in between;
+    2. own hazard slots are cleared only after that, so should any protection outlive its
+       ProtectedPointer (say, it was mem::forget-ed), it keeps holding for the whole handoff,
+       and no concurrent scan frees the pointer under it. after the guard is gone, nobody is
+       left to release it, and it would block reclamation for good;
+    3. thread slot is given back last, so the next guard registered there starts clean
+*/
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize> Drop
+    for HazardPointerGuard<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
+    fn drop(&mut self) {
+        self.release_d_list();
+        self.clear_hazards();
+        self.array
+            .thread_registry
+            .fetch_or(1 << (self.starting_idx / HP_PER_THREAD), Ordering::Release);
+    }
+}
+
+// see HazardPointerGuard::erase()
+pub struct ErasedGuard<'a, const MAX_THREADS: usize = 64, const HP_PER_THREAD: usize = 16> {
+    array: &'a HazardPointerArray<MAX_THREADS, HP_PER_THREAD>,
+    starting_idx: usize,
+}
+
+impl<'a, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    ErasedGuard<'a, MAX_THREADS, HP_PER_THREAD>
+{
+    pub fn unregister_thread(self) {
+        drop(self);
+    }
+}
+
+impl<'a, const MAX_THREADS: usize, const HP_PER_THREAD: usize> Drop
+    for ErasedGuard<'a, MAX_THREADS, HP_PER_THREAD>
+{
     fn drop(&mut self) {
-        self.scan();
         self.array
             .thread_registry
             .fetch_or(1 << (self.starting_idx / HP_PER_THREAD), Ordering::Release);
     }
 }
 
-pub struct ProtectedPointer<'a, T> {
+// shallow destructor for retire_shallow(): frees the allocation only, running neither T's Drop,
+// nor its fields' destructors. whatever else node owns, besides children, is leaked
+pub fn dealloc_shallow<T>(ptr: *mut T) {
+    unsafe { drop(Box::from_raw(ptr as *mut ManuallyDrop<T>)) };
+}
+
+// retired pointer along with the way it should be freed
+struct Retired<T> {
     ptr: *mut T,
+    // None stands for regular Box deallocation
+    destructor: Option<Box<dyn FnOnce(*mut T) + Send>>,
+    #[cfg(feature = "scan-stats")]
+    retired_at_scan: u64,
+}
+
+impl<T> Retired<T> {
+    fn reclaim(self) {
+        match self.destructor {
+            Some(destructor) => destructor(self.ptr),
+            None => unsafe { drop(Box::from_raw(self.ptr)) },
+        }
+    }
+
+    fn into_orphan(self, next: *mut Orphan) -> Orphan {
+        Orphan {
+            ptr: self.ptr as *mut (),
+            destructor: match self.destructor {
+                Some(destructor) => Box::into_raw(Box::new(destructor)) as *mut (),
+                None => std::ptr::null_mut(),
+            },
+            reclaim: Self::reclaim_erased,
+            size: std::mem::size_of::<T>(),
+            #[cfg(feature = "scan-stats")]
+            retired_at_scan: self.retired_at_scan,
+            next,
+        }
+    }
+
+    // restores Retired<T> from its erased parts, see into_orphan()
+    unsafe fn reclaim_erased(ptr: *mut (), destructor: *mut ()) {
+        let destructor = (!destructor.is_null()).then(|| unsafe {
+            *Box::from_raw(destructor as *mut Box<dyn FnOnce(*mut T) + Send>)
+        });
+        Retired {
+            ptr: ptr as *mut T,
+            destructor,
+            // orphan's own stamp is recorded by whoever reclaims it
+            #[cfg(feature = "scan-stats")]
+            retired_at_scan: 0,
+        }
+        .reclaim();
+    }
+}
+
+// type-erased Retired<T>, see HazardPointerArray::orphans
+struct Orphan {
+    ptr: *mut (),
+    // boxed Box<dyn FnOnce(*mut T) + Send>, or null for regular Box deallocation
+    destructor: *mut (),
+    // monomorphized for the T of guard that has retired the pointer
+    reclaim: unsafe fn(*mut (), *mut ()),
+    size: usize,
+    #[cfg(feature = "scan-stats")]
+    retired_at_scan: u64,
+    next: *mut Orphan,
+}
+
+impl Orphan {
+    fn reclaim(self) {
+        unsafe { (self.reclaim)(self.ptr, self.destructor) };
+    }
+}
+
+pub struct ProtectedPointer<
+    'a,
+    T,
+    const MAX_THREADS: usize = 64,
+    const HP_PER_THREAD: usize = 16,
+> {
+    // protect() never hands out null pointers, so let type system know it
+    ptr: NonNull<T>,
     index: usize,
-    guard: &'a HazardPointerGuard<'a, T>,
+    guard: &'a HazardPointerGuard<'a, T, MAX_THREADS, HP_PER_THREAD>,
 }
 
-impl<'a, T> ProtectedPointer<'a, T> {
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize>
+    ProtectedPointer<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
+    // returns protected address, e.g. for comparison against the current value of some atomic.
+    // pointer stays protected and owned by this ProtectedPointer, nothing is transferred
+    // (unlike into_raw()). don't keep the returned pointer around after ProtectedPointer is gone
     pub fn as_ptr(&self) -> *const T {
-        self.ptr
+        self.ptr.as_ptr()
+    }
+
+    // same as as_ptr(), but mutable, e.g. for CAS-ing protected address in and out of AtomicPtr.
+    // takes &self, since it only reads the address: all writes through the pointer are up to
+    // caller and are as unsafe as with any other raw pointer
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
     }
 
-    pub fn as_mut_ptr(&mut self) -> *mut T {
+    // same as above, as NonNull
+    pub fn as_non_null(&self) -> NonNull<T> {
         self.ptr
     }
-    
-    // safety: consumes protected pointer, unprotects it and returns underlying raw pointer
-    // caller must ensure the memory remains valid as long as needed
-    // pointer must not be freed directly, only through retire_raw_pointer
-    pub unsafe fn into_raw(self) -> *mut T {
+
+    // moves protection over to another guard (e.g. on handoff of a node between owners).
+    // pointer gets protected in other guard first, and only then is unprotected here, so there's
+    // no moment when it's not protected at all. on failure (other guard has no free slots)
+    // protected pointer is given back untouched. other guard may belong to an array of
+    // different limits
+    pub fn transfer_to<'b, const OTHER_THREADS: usize, const OTHER_HP_PER_THREAD: usize>(
+        self,
+        other: &'b HazardPointerGuard<'b, T, OTHER_THREADS, OTHER_HP_PER_THREAD>,
+    ) -> Result<
+        ProtectedPointer<'b, T, OTHER_THREADS, OTHER_HP_PER_THREAD>,
+        (Self, ProtectionError),
+    > {
+        match unsafe { other.protect(self.ptr.as_ptr()) } {
+            Ok(transferred) => {
+                // drop unprotects pointer in the original guard
+                drop(self);
+                Ok(transferred)
+            }
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    // consumes protected pointer and unprotects it. what's left is not safe to dereference
+    // anymore, so it's handed out as a RetireToken, that can only be retired
+    pub fn into_raw(self) -> RetireToken<T> {
         // as protected pointer is consumed, guard automatically unprotects pointer
-        let ptr = self.ptr;
-        ptr
+        RetireToken {
+            ptr: self.ptr.as_ptr(),
+        }
     }
 }
 
-impl<'a, T> std::ops::Deref for ProtectedPointer<'a, T> {
+/*
+    pointer, that has just been unprotected by ProtectedPointer::into_raw(). it may be freed by
+    another thread at any moment after that, so the token doesn't give the pointer back (nor
+    dereferences to it): the only thing to do with it is to pass it to retire_raw_pointer().
+    dropping the token just leaks the pointer, which is safe.
+    plain raw pointers convert into tokens, so retire_raw_pointer() still takes those as well
+*/
+/// ```compile_fail
+/// use lock_freedom::mechanisms::hp::HazardPointerArray;
+///
+/// let array = HazardPointerArray::new();
+/// let guard = array.register_thread().ok().unwrap();
+/// let protected = unsafe { guard.protect(Box::into_raw(Box::new(1usize))) }.ok().unwrap();
+/// let token = protected.into_raw();
+/// // no longer protected, so there's no way to read through it
+/// let value: usize = *token;
+/// guard.retire_raw_pointer(token);
+/// ```
+pub struct RetireToken<T> {
+    ptr: *mut T,
+}
+
+impl<T> From<*mut T> for RetireToken<T> {
+    fn from(ptr: *mut T) -> Self {
+        RetireToken { ptr }
+    }
+}
+
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize> std::ops::Deref
+    for ProtectedPointer<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
     type Target = T;
     // should be safe if guarantees (no access outside protected pointers) are fulfilled🚬
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.ptr }
+        unsafe { self.ptr.as_ref() }
     }
 }
 
-impl<'a , T> std::ops::DerefMut for ProtectedPointer<'a, T> {
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize> std::ops::DerefMut
+    for ProtectedPointer<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.ptr }
+        unsafe { self.ptr.as_mut() }
     }
 }
 
-impl<'a, T> Drop for ProtectedPointer<'a, T> {
+impl<'a, T, const MAX_THREADS: usize, const HP_PER_THREAD: usize> Drop
+    for ProtectedPointer<'a, T, MAX_THREADS, HP_PER_THREAD>
+{
     fn drop(&mut self) {
         // default behavior:
         // remove pointer from p_list without any memory reclamation attempts
@@ -210,6 +1168,20 @@ impl<'a, T> Drop for ProtectedPointer<'a, T> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub registered_threads: usize,
+    pub free_thread_slots: usize,
+    // currently protected pointers, across all guards
+    pub protected: usize,
+    pub pending_bytes: usize,
+    // cumulative, see scan_stats()
+    #[cfg(feature = "scan-stats")]
+    pub scans: u64,
+    #[cfg(feature = "scan-stats")]
+    pub reclaimed: u64,
+}
+
 pub enum ProtectionError {
     NoAvailableIndices,
     NullPointer,
@@ -218,3 +1190,869 @@ pub enum ProtectionError {
 pub enum RegisterThreadError {
     NoAvailableIndices,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dealloc_shallow, ErasedGuard, HazardPointerArray, HazardPointerGuard, RegisterThreadError,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // limits of the default HazardPointerArray
+    const MAX_THREADS: usize = 64;
+    const HP_PER_THREAD: usize = 16;
+    const SCAN_THRESHOLD: usize = HazardPointerGuard::<usize>::SCAN_THRESHOLD;
+
+    fn registered_slot<T, const THREADS: usize, const PER_THREAD: usize>(
+        guard: &HazardPointerGuard<T, THREADS, PER_THREAD>,
+    ) -> usize {
+        guard.starting_idx / PER_THREAD
+    }
+
+    #[test]
+    fn test_fair_registration() {
+        // default: the lowest free slot is always reused
+        let hp_array = HazardPointerArray::new();
+        for _ in 0..10 {
+            let guard = hp_array.register_thread::<usize>().ok().unwrap();
+            assert_eq!(registered_slot(&guard), 0);
+        }
+
+        // as many threads as slots, so that each slot gets its fair share of the churn below
+        const SLOTS: usize = 4;
+        let hp_array = HazardPointerArray::<SLOTS>::with_limits().fair_registration();
+        for i in 0..3 * SLOTS {
+            let guard = hp_array.register_thread::<usize>().ok().unwrap();
+            assert_eq!(registered_slot(&guard), i % SLOTS);
+        }
+        // occupied slots are skipped, and search wraps around
+        let kept = hp_array.register_thread::<usize>().ok().unwrap();
+        let next = hp_array.register_thread::<usize>().ok().unwrap();
+        assert_eq!(registered_slot(&next), (registered_slot(&kept) + 1) % SLOTS);
+        drop((kept, next));
+
+        // concurrent churn spreads over all slots
+        let threads = SLOTS;
+        let per_thread = 1000;
+        let counts = [const { AtomicUsize::new(0) }; SLOTS];
+        let (hp_array_ref, counts_ref) = (&hp_array, &counts);
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(move || {
+                    for _ in 0..per_thread {
+                        let guard = hp_array_ref.register_thread::<usize>().ok().unwrap();
+                        counts_ref[registered_slot(&guard)].fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let counts = counts.map(|count| count.into_inner());
+        assert_eq!(counts.iter().sum::<usize>(), threads * per_thread);
+        assert!(counts.iter().all(|&count| count >= per_thread / 4), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_register_pool() {
+        let hp_array = HazardPointerArray::new();
+        // one slot short: fails without claiming anything
+        assert!(hp_array.register_pool::<usize>(MAX_THREADS + 1).is_err());
+        let mut pool = hp_array.register_pool::<usize>(MAX_THREADS).ok().unwrap();
+        assert_eq!(
+            pool.iter().map(registered_slot).collect::<Vec<_>>(),
+            (0..MAX_THREADS).collect::<Vec<_>>()
+        );
+        assert!(hp_array.register_thread::<usize>().is_err());
+        assert!(hp_array.register_pool::<usize>(1).is_err());
+        // empty pool fits into a full array
+        assert!(hp_array.register_pool::<usize>(0).ok().unwrap().is_empty());
+
+        // guards are independent: every one gives its own slot back
+        pool.pop();
+        let guard = hp_array.register_thread::<usize>().ok().unwrap();
+        assert_eq!(registered_slot(&guard), MAX_THREADS - 1);
+        drop(guard);
+        drop(pool);
+        assert_eq!(hp_array.register_pool::<usize>(MAX_THREADS).ok().unwrap().len(), MAX_THREADS);
+    }
+
+    #[test]
+    fn test_with_limits() {
+        let hp_array = HazardPointerArray::<2, 3>::with_limits();
+        let guard = hp_array.register_thread::<usize>().ok().unwrap();
+        let other = hp_array.register_thread::<usize>().ok().unwrap();
+        assert!(matches!(
+            hp_array.register_thread::<usize>(),
+            Err(RegisterThreadError::NoAvailableIndices)
+        ));
+        assert_eq!(hp_array.diagnostics().registered_threads, 2);
+
+        let nodes = (0..4).map(|i| Box::into_raw(Box::new(i))).collect::<Vec<_>>();
+        let protected = nodes[..3]
+            .iter()
+            .map(|&node| unsafe { guard.protect(node) }.ok().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(guard.protected_count(), 3);
+        assert!(unsafe { guard.protect(nodes[3]) }.is_err());
+        // SCAN_THRESHOLD follows HP_PER_THREAD: the 7th retirement scans
+        for i in 0..2 * 3 {
+            other.retire_raw_pointer(Box::into_raw(Box::new(i)));
+        }
+        assert_eq!(hp_array.pending_bytes(), 6 * size_of::<usize>());
+        other.retire_raw_pointer(nodes[3]);
+        assert_eq!(hp_array.pending_bytes(), 0);
+
+        // protection moves over to an array of different limits just as well
+        let default_array = HazardPointerArray::new();
+        let receiver = default_array.register_thread::<usize>().ok().unwrap();
+        let transferred = protected
+            .into_iter()
+            .map(|protected| protected.transfer_to(&receiver).ok().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(guard.protected_count(), 0);
+        assert_eq!(receiver.protected_count(), 3);
+        drop(transferred);
+        for &node in &nodes[..3] {
+            other.retire_raw_pointer(node);
+        }
+        assert_eq!(other.try_reclaim(), 3);
+
+        // both bitmaps used up to their last bit
+        let widest = HazardPointerArray::<64, 64>::with_limits();
+        let pool = widest.register_pool::<usize>(64).ok().unwrap();
+        assert!(widest.register_thread::<usize>().is_err());
+        let node = Box::into_raw(Box::new(0usize));
+        let protected = (0..64)
+            .map(|_| unsafe { pool[63].protect(node) }.ok().unwrap())
+            .collect::<Vec<_>>();
+        assert!(unsafe { pool[63].protect(node) }.is_err());
+        assert!(pool[63].is_protected(node));
+        drop(protected);
+        assert_eq!(pool[63].protected_count(), 0);
+        pool[63].retire_raw_pointer(node);
+        drop(pool);
+        assert_eq!(widest.diagnostics().free_thread_slots, 64);
+    }
+
+    /*
+        best-effort: on which node a page actually lives can't be asked without libc
+        (get_mempolicy() with MPOL_F_ADDR), so this checks what first-touch placement relies on:
+        slot's hazards get a page of their own, allocated by the registering thread itself,
+        and protections there are seen by scans of other threads
+    */
+    #[test]
+    fn test_local_slots() {
+        let hp_array = HazardPointerArray::new().local_slots();
+        assert!(hp_array.local_chunks.iter().all(|chunk| chunk.load(Ordering::Relaxed).is_null()));
+
+        let main_guard = hp_array.register_thread::<usize>().ok().unwrap();
+        let node = Box::into_raw(Box::new(42usize));
+        let node_addr = node as usize;
+        let protected = AtomicBool::new(false);
+        let retired = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let guard = hp_array.register_thread::<usize>().ok().unwrap();
+                let slot = registered_slot(&guard);
+                let chunk = hp_array.local_chunks[slot].load(Ordering::Relaxed);
+                assert!(!chunk.is_null());
+                assert_eq!(chunk as usize % 4096, 0);
+                assert!(std::ptr::eq(guard.hazard(0), unsafe { &(*chunk).0[0] }));
+
+                let protection = unsafe { guard.protect(node_addr as *mut usize) }.ok().unwrap();
+                protected.store(true, Ordering::Release);
+                while !retired.load(Ordering::Acquire) {
+                    std::thread::yield_now();
+                }
+                drop(protection);
+            });
+            while !protected.load(Ordering::Acquire) {
+                std::thread::yield_now();
+            }
+            // the other thread's local slot is scanned too
+            main_guard.retire_raw_pointer(node);
+            assert_eq!(main_guard.try_reclaim(), 0);
+            retired.store(true, Ordering::Release);
+        });
+        assert_eq!(main_guard.try_reclaim(), 1);
+
+        // every claimed slot got its own page, and chunks stay across registrations
+        let slots = hp_array.local_chunks.iter().map(|chunk| chunk.load(Ordering::Relaxed) as usize);
+        let mut pages = slots.filter(|&chunk| chunk != 0).map(|chunk| chunk / 4096).collect::<Vec<_>>();
+        assert_eq!(pages.len(), 2);
+        pages.dedup();
+        assert_eq!(pages.len(), 2);
+        let chunk = hp_array.local_chunks[1].load(Ordering::Relaxed);
+        drop(hp_array.register_thread::<usize>().ok().unwrap());
+        assert_eq!(hp_array.local_chunks[1].load(Ordering::Relaxed), chunk);
+    }
+
+    #[test]
+    fn test_global_scan() {
+        let (node_count, protected_idx) = (SCAN_THRESHOLD / 2, 0);
+        let (retired, reclaimed) = (AtomicBool::new(false), AtomicBool::new(false));
+        for global_scan in [false, true] {
+            let hp_array = if global_scan {
+                HazardPointerArray::new().global_scan()
+            } else {
+                HazardPointerArray::new()
+            };
+            let nodes = (0..node_count).map(|i| Box::into_raw(Box::new(i)) as usize).collect::<Vec<_>>();
+            let active = hp_array.register_thread::<usize>().ok().unwrap();
+            let protection = unsafe { active.protect(nodes[protected_idx] as *mut usize) }.ok().unwrap();
+            retired.store(false, Ordering::Relaxed);
+            reclaimed.store(false, Ordering::Relaxed);
+            std::thread::scope(|s| {
+                // retires a few nodes, below SCAN_THRESHOLD, and goes idle
+                s.spawn(|| {
+                    let idle = hp_array.register_thread::<usize>().ok().unwrap();
+                    for &node in &nodes {
+                        idle.retire_raw_pointer(node as *mut usize);
+                    }
+                    retired.store(true, Ordering::Release);
+                    while !reclaimed.load(Ordering::Acquire) {
+                        std::thread::yield_now();
+                    }
+                    // whatever is left gets orphaned here
+                });
+                while !retired.load(Ordering::Acquire) {
+                    std::thread::yield_now();
+                }
+                let pending = hp_array.pending_bytes();
+                if global_scan {
+                    // all but the one, that is still protected
+                    assert_eq!(active.try_reclaim(), node_count - 1);
+                    assert_eq!(hp_array.pending_bytes(), std::mem::size_of::<usize>());
+                } else {
+                    assert_eq!(active.try_reclaim(), 0);
+                    assert_eq!(hp_array.pending_bytes(), pending);
+                }
+                reclaimed.store(true, Ordering::Release);
+            });
+            drop(protection);
+            active.try_reclaim();
+            assert_eq!(hp_array.pending_bytes(), 0);
+        }
+    }
+
+    #[test]
+    fn test_pointer_accessors() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let raw = Box::into_raw(Box::new(42usize));
+        let is_protected = |ptr: *mut usize| {
+            hp_array.all_hazards().any(|e| e.load(Ordering::Acquire) == ptr as *mut ())
+        };
+
+        let protected = unsafe { guard.protect(raw) }.ok().unwrap();
+        assert_eq!(protected.as_ptr(), raw as *const usize);
+        assert_eq!(protected.as_mut_ptr(), raw);
+        assert_eq!(protected.as_non_null().as_ptr(), raw);
+        // accessors neither consume nor unprotect
+        assert!(is_protected(raw));
+        assert!(guard.is_protected(raw));
+        assert_eq!(*protected, 42);
+
+        drop(protected);
+        assert!(!is_protected(raw));
+        assert!(!guard.is_protected(raw));
+        unsafe { drop(Box::from_raw(raw)) };
+    }
+
+    static RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+    // not a ZST, so that every box gets its own address
+    struct ReclaimCounter {
+        _id: usize,
+    }
+
+    impl Drop for ReclaimCounter {
+        fn drop(&mut self) {
+            RECLAIMED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_try_reclaim() {
+        let hp_array = HazardPointerArray::new();
+        let reader = hp_array.register_thread::<ReclaimCounter>().ok().unwrap();
+        let writer = hp_array.register_thread::<ReclaimCounter>().ok().unwrap();
+
+        let nodes = (0..8)
+            .map(|i| Box::into_raw(Box::new(ReclaimCounter { _id: i })))
+            .collect::<Vec<_>>();
+        // long-running reader blocks reclamation of a single node
+        let protected = unsafe { reader.protect(nodes[0]) }.ok().unwrap();
+        nodes.iter().for_each(|&node| writer.retire_raw_pointer(node));
+        // far below scan threshold, nothing is freed yet
+        assert_eq!(RECLAIMED.load(Ordering::Relaxed), 0);
+
+        assert_eq!(writer.try_reclaim(), 7);
+        assert_eq!(RECLAIMED.load(Ordering::Relaxed), 7);
+
+        drop(protected);
+        assert_eq!(writer.try_reclaim(), 1);
+        assert_eq!(writer.try_reclaim(), 0);
+        assert_eq!(RECLAIMED.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn test_retire_boxed() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<usize>().ok().unwrap();
+        // nodes retired into this pool should come back here instead of being freed
+        let pool = Arc::new(Mutex::new(Vec::new()));
+
+        let node = Box::into_raw(Box::new(1usize));
+        let protected = unsafe { guard.protect(node) }.ok().unwrap();
+        let pool_ref = pool.clone();
+        guard.retire_boxed(node, Box::new(move |ptr| pool_ref.lock().unwrap().push(ptr as usize)));
+        // still protected
+        assert_eq!(guard.try_reclaim(), 0);
+        assert!(pool.lock().unwrap().is_empty());
+
+        drop(protected);
+        assert_eq!(guard.try_reclaim(), 1);
+        assert_eq!(*pool.lock().unwrap(), vec![node as usize]);
+
+        // regular retirement still frees with Box
+        guard.retire_raw_pointer(pool.lock().unwrap().pop().unwrap() as *mut usize);
+        assert_eq!(guard.try_reclaim(), 1);
+    }
+
+    #[test]
+    fn test_retire_token() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<usize>().ok().unwrap();
+        let node = Box::into_raw(Box::new(1usize));
+        let protected = unsafe { guard.protect(node) }.ok().unwrap();
+        assert_eq!(guard.protected_count(), 1);
+
+        // slot is cleared as soon as the token is taken, not when it's retired
+        let token = protected.into_raw();
+        assert_eq!(guard.protected_count(), 0);
+        guard.retire_raw_pointer(token);
+        assert_eq!(guard.try_reclaim(), 1);
+    }
+
+    #[test]
+    fn test_transfer_to() {
+        let hp_array = HazardPointerArray::new();
+        let owner = hp_array.register_thread::<usize>().ok().unwrap();
+        let receiver = hp_array.register_thread::<usize>().ok().unwrap();
+        let retirer = hp_array.register_thread::<usize>().ok().unwrap();
+        let is_protected_by = |guard: &HazardPointerGuard<usize>, ptr: *mut usize| {
+            hp_array
+                .hazards(registered_slot(guard))
+                .iter()
+                .any(|e| e.load(Ordering::Acquire) == ptr as *mut ())
+        };
+
+        let node = Box::into_raw(Box::new(0usize));
+        let protected = unsafe { owner.protect(node) }.ok().unwrap();
+        retirer.retire_raw_pointer(node);
+
+        let transferred = protected.transfer_to(&receiver).ok().unwrap();
+        assert!(!is_protected_by(&owner, node));
+        assert!(is_protected_by(&receiver, node));
+        assert_eq!(retirer.try_reclaim(), 0);
+
+        drop(transferred);
+        assert_eq!(retirer.try_reclaim(), 1);
+    }
+
+    #[test]
+    fn test_transfer_to_exhausted_guard() {
+        let hp_array = HazardPointerArray::new();
+        let owner = hp_array.register_thread::<usize>().ok().unwrap();
+        let receiver = hp_array.register_thread::<usize>().ok().unwrap();
+        let node = Box::into_raw(Box::new(0usize));
+
+        // take all receiver's slots
+        let taken = (0..HP_PER_THREAD)
+            .map(|_| unsafe { receiver.protect(node) }.ok().unwrap())
+            .collect::<Vec<_>>();
+        let protected = unsafe { owner.protect(node) }.ok().unwrap();
+        let (protected, _) = protected.transfer_to(&receiver).err().unwrap();
+        // still protected by the owner
+        assert_eq!(protected.as_mut_ptr(), node);
+        assert!(hp_array
+            .hazards(registered_slot(&owner))
+            .iter()
+            .any(|e| e.load(Ordering::Acquire) == node as *mut ()));
+
+        drop(taken);
+        drop(protected);
+        unsafe { drop(Box::from_raw(node)) };
+    }
+
+    #[test]
+    fn test_memory_ceiling() {
+        type Node = [u64; 8];
+        let node_size = std::mem::size_of::<Node>();
+        let hp_array = HazardPointerArray::with_memory_ceiling(2 * node_size);
+        let reader = hp_array.register_thread::<Node>().ok().unwrap();
+        let done = AtomicBool::new(false);
+
+        let nodes = (0..3).map(|_| Box::into_raw(Box::new([0u64; 8]))).collect::<Vec<_>>();
+        // stalled reader pins every node
+        let protected = nodes
+            .iter()
+            .map(|&node| unsafe { reader.protect(node) }.ok().unwrap())
+            .collect::<Vec<_>>();
+
+        std::thread::scope(|s| {
+            let (hp_array_ref, done_ref) = (&hp_array, &done);
+            // raw pointers aren't Send
+            let addresses = nodes.iter().map(|&node| node as usize).collect::<Vec<_>>();
+            s.spawn(move || {
+                let writer = hp_array_ref.register_thread::<Node>().ok().unwrap();
+                for address in addresses {
+                    writer.retire_raw_pointer(address as *mut Node);
+                }
+                done_ref.store(true, Ordering::Release);
+            });
+
+            std::thread::sleep(Duration::from_millis(100));
+            // third retirement crossed the ceiling and can't free anything
+            assert!(!done.load(Ordering::Acquire));
+            assert_eq!(hp_array.pending_bytes(), 3 * node_size);
+
+            drop(protected);
+        });
+        assert!(done.load(Ordering::Acquire));
+        // writer has freed everything, partly while blocked, partly on drop
+        assert_eq!(hp_array.pending_bytes(), 0);
+    }
+
+    // not a ZST, counts its own drops
+    struct DropFlag(Arc<AtomicUsize>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_retire_immediate_if() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<DropFlag>().ok().unwrap();
+        let drops = Arc::new(AtomicUsize::new(0));
+
+        // never published: freed on the spot, with no scan and nothing pending
+        let node = Box::into_raw(Box::new(DropFlag(drops.clone())));
+        unsafe { guard.retire_immediate_if(node, || true) };
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(hp_array.pending_bytes(), 0);
+        assert_eq!(guard.try_reclaim(), 0);
+
+        // not proven safe: goes through d_list as usual
+        let node = Box::into_raw(Box::new(DropFlag(drops.clone())));
+        unsafe { guard.retire_immediate_if(node, || false) };
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(hp_array.pending_bytes() > 0);
+        assert_eq!(guard.try_reclaim(), 1);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+
+    // owns its child, and frees it on drop, like it would on sequential teardown
+    struct Parent {
+        child: *mut DropFlag,
+        _flag: DropFlag,
+    }
+
+    // owns its child, just through a pointer
+    unsafe impl Send for Parent {}
+
+    impl Drop for Parent {
+        fn drop(&mut self) {
+            if !self.child.is_null() {
+                unsafe { drop(Box::from_raw(self.child)) };
+            }
+        }
+    }
+
+    #[test]
+    fn test_retire_shallow() {
+        let hp_array = HazardPointerArray::new();
+        let parents = hp_array.register_thread::<Parent>().ok().unwrap();
+        let children = hp_array.register_thread::<DropFlag>().ok().unwrap();
+        let reader = hp_array.register_thread::<DropFlag>().ok().unwrap();
+        let (parent_drops, child_drops) =
+            (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let new_parent = || {
+            Box::into_raw(Box::new(Parent {
+                child: Box::into_raw(Box::new(DropFlag(child_drops.clone()))),
+                _flag: DropFlag(parent_drops.clone()),
+            }))
+        };
+
+        // children are still read elsewhere, when parents get reclaimed
+        let (first, second) = (new_parent(), new_parent());
+        let (first_child, second_child) = unsafe { ((*first).child, (*second).child) };
+        let protected_first = unsafe { reader.protect(first_child) }.ok().unwrap();
+        let protected_second = unsafe { reader.protect(second_child) }.ok().unwrap();
+        // detaches the child, and drops the rest as usual
+        parents.retire_shallow(first, |parent| {
+            let mut parent = unsafe { Box::from_raw(parent) };
+            parent.child = std::ptr::null_mut();
+        });
+        // frees the allocation only
+        parents.retire_shallow(second, dealloc_shallow);
+        assert_eq!(parents.try_reclaim(), 2);
+        assert_eq!(parent_drops.load(Ordering::Relaxed), 1);
+        assert_eq!(child_drops.load(Ordering::Relaxed), 0);
+        assert!(Arc::ptr_eq(&protected_first.0, &child_drops));
+
+        children.retire_raw_pointer(first_child);
+        children.retire_raw_pointer(second_child);
+        assert_eq!(children.try_reclaim(), 0);
+        drop((protected_first, protected_second));
+        assert_eq!(children.try_reclaim(), 2);
+        assert_eq!(child_drops.load(Ordering::Relaxed), 2);
+    }
+
+    // fat pointers can't be protected (guard's T is Sized), unsized data goes behind a thin node
+    #[test]
+    fn test_unsized_behind_thin_node() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<Box<dyn Fn() -> usize + Send>>().ok().unwrap();
+        let bytes_guard = hp_array.register_thread::<Box<[u8]>>().ok().unwrap();
+
+        let node: *mut Box<dyn Fn() -> usize + Send> = Box::into_raw(Box::new(Box::new(|| 42)));
+        let bytes = Box::into_raw(Box::new(vec![1u8, 2, 3].into_boxed_slice()));
+        let protected = unsafe { guard.protect(node) }.ok().unwrap();
+        let protected_bytes = unsafe { bytes_guard.protect(bytes) }.ok().unwrap();
+        // slot holds node's address, metadata stays in the node
+        assert!(hp_array.all_hazards().any(|e| e.load(Ordering::Acquire) == node as *mut ()));
+        assert_eq!((*protected)(), 42);
+        assert_eq!(protected_bytes.len(), 3);
+
+        guard.retire_node(protected);
+        bytes_guard.retire_node(protected_bytes);
+        assert_eq!(guard.try_reclaim() + bytes_guard.try_reclaim(), 2);
+    }
+
+    #[test]
+    fn test_orphans_of_mixed_types() {
+        let hp_array = HazardPointerArray::new();
+        let reader = hp_array.register_thread::<()>().ok().unwrap();
+        let pooled_retirer = hp_array.register_thread::<[u8; 3]>().ok().unwrap();
+        let flag_retirer = hp_array.register_thread::<DropFlag>().ok().unwrap();
+
+        let pool = Arc::new(Mutex::new(Vec::new()));
+        let pooled = Box::into_raw(Box::new([1u8; 3]));
+        let drops = Arc::new(AtomicUsize::new(0));
+        let flagged = Box::into_raw(Box::new(DropFlag(drops.clone())));
+        // reader of unrelated type keeps both alive
+        let protected = [pooled as *mut (), flagged as *mut ()]
+            .map(|ptr| unsafe { reader.protect(ptr) }.ok().unwrap());
+
+        let pool_ref = pool.clone();
+        pooled_retirer
+            .retire_boxed(pooled, Box::new(move |ptr| pool_ref.lock().unwrap().push(ptr as usize)));
+        flag_retirer.retire_raw_pointer(flagged);
+        // both are orphaned on drop, since they can't be freed yet
+        drop(pooled_retirer);
+        drop(flag_retirer);
+        let pending = std::mem::size_of::<[u8; 3]>() + std::mem::size_of::<DropFlag>();
+        assert_eq!(hp_array.pending_bytes(), pending);
+
+        let adopter = hp_array.register_thread::<usize>().ok().unwrap();
+        assert_eq!(adopter.try_reclaim(), 0);
+        drop(protected);
+        // each one gets its own destructor, not adopter's usize one
+        assert_eq!(adopter.try_reclaim(), 2);
+        assert_eq!(*pool.lock().unwrap(), vec![pooled as usize]);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(hp_array.pending_bytes(), 0);
+        assert_eq!(adopter.try_reclaim(), 0);
+
+        unsafe { drop(Box::from_raw(pooled)) };
+    }
+
+    #[test]
+    fn test_erase() {
+        let hp_array = HazardPointerArray::new();
+        let reader = hp_array.register_thread::<usize>().ok().unwrap();
+        let retirer = hp_array.register_thread::<usize>().ok().unwrap();
+        let node = Box::into_raw(Box::new(0usize));
+        let protected = unsafe { reader.protect(node) }.ok().unwrap();
+        retirer.retire_raw_pointer(node);
+
+        // guards of different types in a single registry
+        let mut registry: Vec<ErasedGuard> = vec![retirer.erase()];
+        while let Ok(guard) = hp_array.register_thread::<String>() {
+            registry.push(guard.erase());
+        }
+        assert_eq!(registry.len(), MAX_THREADS - 1);
+        // erased guards still hold their slots
+        assert!(hp_array.register_thread::<usize>().is_err());
+
+        registry.pop().unwrap().unregister_thread();
+        let adopter = hp_array.register_thread::<usize>().ok().unwrap();
+        // node retired by erased guard wasn't lost
+        assert_eq!(adopter.try_reclaim(), 0);
+        drop(protected);
+        assert_eq!(adopter.try_reclaim(), 1);
+        assert_eq!(hp_array.pending_bytes(), 0);
+    }
+
+    // reclaiming workload with a reader, that holds a node over many scans
+    #[cfg(feature = "scan-stats")]
+    #[test]
+    fn test_scan_stats() {
+        let hp_array = HazardPointerArray::new();
+        let reader = hp_array.register_thread::<usize>().ok().unwrap();
+        let writer = hp_array.register_thread::<usize>().ok().unwrap();
+        let nodes = 10 * SCAN_THRESHOLD;
+
+        let held = Box::into_raw(Box::new(0));
+        let protected = unsafe { reader.protect(held) }.ok().unwrap();
+        writer.retire_raw_pointer(held);
+        for i in 1..nodes {
+            writer.retire_raw_pointer(Box::into_raw(Box::new(i)));
+        }
+        drop(protected);
+        writer.try_reclaim();
+
+        let stats = hp_array.scan_stats();
+        assert_eq!(stats.reclaimed, nodes as u64);
+        assert!(stats.scans >= (nodes / (SCAN_THRESHOLD + 1)) as u64);
+        assert_eq!(stats.duration_histogram.iter().sum::<u64>(), stats.scans);
+        assert_eq!(stats.reclaimed_histogram.iter().sum::<u64>(), stats.scans);
+        // held node has survived every scan but the last one, the rest at most one scan each
+        assert!(stats.scans_survived >= stats.scans - 1);
+        assert!(stats.average_scans_survived() < 2.0);
+        assert!(stats.average_reclaimed_per_scan() > 1.0);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let hp_array = HazardPointerArray::new();
+        let diagnostics = hp_array.diagnostics();
+        assert_eq!(diagnostics.registered_threads, 0);
+        assert_eq!(diagnostics.free_thread_slots, MAX_THREADS);
+        assert_eq!(diagnostics.protected, 0);
+
+        let hp_array_ref = &hp_array;
+        std::thread::scope(|s| {
+            for _ in 0..MAX_THREADS {
+                s.spawn(move || {
+                    let guard = hp_array_ref.register_thread::<usize>().ok().unwrap();
+                    for i in 0..4 * SCAN_THRESHOLD {
+                        let node = Box::into_raw(Box::new(i));
+                        let protected = unsafe { guard.protect(node) }.ok().unwrap();
+                        let diagnostics = hp_array_ref.diagnostics();
+                        assert!(diagnostics.registered_threads >= 1);
+                        assert_eq!(
+                            diagnostics.registered_threads + diagnostics.free_thread_slots,
+                            MAX_THREADS
+                        );
+                        assert!(diagnostics.protected >= 1);
+                        assert!(diagnostics.protected <= MAX_THREADS * HP_PER_THREAD);
+                        guard.retire_node(protected);
+                    }
+                });
+            }
+        });
+
+        // every guard is gone: nothing is protected, everything retired has been reclaimed
+        let diagnostics = hp_array.diagnostics();
+        assert_eq!(diagnostics.registered_threads, 0);
+        assert_eq!(diagnostics.free_thread_slots, MAX_THREADS);
+        assert_eq!(diagnostics.protected, 0);
+        assert_eq!(diagnostics.pending_bytes, 0);
+        #[cfg(feature = "scan-stats")]
+        assert_eq!(diagnostics.reclaimed, (MAX_THREADS * 4 * SCAN_THRESHOLD) as u64);
+    }
+
+    // payload, that retires its successor with the same guard, that is reclaiming it
+    struct Reentrant {
+        guard: *const HazardPointerGuard<'static, Reentrant>,
+        next: *mut Reentrant,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    // raw pointers to the guard, so it's only ever reclaimed on the guard's own thread here
+    unsafe impl Send for Reentrant {}
+
+    impl Drop for Reentrant {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            if !self.next.is_null() {
+                unsafe { (*self.guard).retire_raw_pointer(self.next) };
+            }
+        }
+    }
+
+    #[test]
+    fn test_reentrant_retire_from_destructor() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<Reentrant>().ok().unwrap();
+        let guard_ptr = &guard as *const HazardPointerGuard<Reentrant> as *const _;
+        let dropped = Arc::new(AtomicUsize::new(0));
+        // enough chains for their heads' destructors to cross SCAN_THRESHOLD, and scan again
+        // from within scan
+        let (chains, chain_len) = (2 * SCAN_THRESHOLD, 3);
+        for _ in 0..chains {
+            let head = (0..chain_len).fold(std::ptr::null_mut(), |next, _| {
+                Box::into_raw(Box::new(Reentrant {
+                    guard: guard_ptr,
+                    next,
+                    dropped: dropped.clone(),
+                }))
+            });
+            guard.retire_raw_pointer(head);
+        }
+        while guard.try_reclaim() > 0 {}
+        assert_eq!(dropped.load(Ordering::Relaxed), chains * chain_len);
+        assert_eq!(hp_array.pending_bytes(), 0);
+    }
+
+    // payload, that uses the guard reclaiming it in every way it can: protects (so p_list
+    // changes under scan), and scans again from within scan
+    struct SelfReferential {
+        guard: *const HazardPointerGuard<'static, SelfReferential>,
+        sentinel: *mut SelfReferential,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    // see Reentrant
+    unsafe impl Send for SelfReferential {}
+
+    impl Drop for SelfReferential {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            if self.sentinel.is_null() {
+                return;
+            }
+            let guard = unsafe { &*self.guard };
+            let protected = unsafe { guard.protect(self.sentinel) }.ok().unwrap();
+            guard.try_reclaim();
+            assert!(guard.is_protected(self.sentinel));
+            drop(protected);
+        }
+    }
+
+    #[test]
+    fn test_scan_destructor_touching_guard() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread::<SelfReferential>().ok().unwrap();
+        let guard_ptr = &guard as *const HazardPointerGuard<SelfReferential> as *const _;
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let sentinel = Box::into_raw(Box::new(SelfReferential {
+            guard: guard_ptr,
+            sentinel: std::ptr::null_mut(),
+            dropped: dropped.clone(),
+        }));
+        let nodes = 3 * SCAN_THRESHOLD;
+        for _ in 0..nodes {
+            guard.retire_raw_pointer(Box::into_raw(Box::new(SelfReferential {
+                guard: guard_ptr,
+                sentinel,
+                dropped: dropped.clone(),
+            })));
+        }
+        guard.try_reclaim();
+        assert_eq!(dropped.load(Ordering::Relaxed), nodes);
+        assert_eq!(guard.protected_count(), 0);
+        drop(unsafe { Box::from_raw(sentinel) });
+    }
+
+    #[test]
+    fn test_on_backlog_exceeded() {
+        let hp_array = HazardPointerArray::new();
+        let reader = hp_array.register_thread::<usize>().ok().unwrap();
+        let writer = hp_array.register_thread::<usize>().ok().unwrap();
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let limit = 4;
+        let reported_ref = reported.clone();
+        writer.on_backlog_exceeded(limit, move |backlog| reported_ref.borrow_mut().push(backlog));
+
+        // stalled reader: every node it protects stays in writer's d_list for good
+        let mut held = Vec::new();
+        for i in 0..HP_PER_THREAD {
+            let node = Box::into_raw(Box::new(i));
+            held.push(unsafe { reader.protect(node) }.ok().unwrap());
+            writer.retire_raw_pointer(node);
+            for j in 0..SCAN_THRESHOLD {
+                writer.retire_raw_pointer(Box::into_raw(Box::new(j)));
+            }
+        }
+        {
+            let reported = reported.borrow();
+            assert!(!reported.is_empty());
+            assert!(reported.iter().all(|&backlog| backlog >= limit));
+            assert!(reported.is_sorted());
+            assert!(*reported.last().unwrap() >= HP_PER_THREAD - 1);
+        }
+
+        // reader is back: backlog is gone, and nothing is reported anymore
+        drop(held);
+        writer.try_reclaim();
+        let reports = reported.borrow().len();
+        for j in 0..2 * SCAN_THRESHOLD {
+            writer.retire_raw_pointer(Box::into_raw(Box::new(j)));
+        }
+        assert_eq!(reported.borrow().len(), reports);
+        drop(writer);
+    }
+
+    // Send counterpart of DropFlag, for nodes that cross threads
+    struct DropCount(Arc<AtomicUsize>);
+
+    impl Drop for DropCount {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_panicked_thread_hands_over() {
+        let hp_array = HazardPointerArray::new();
+        let survivor = hp_array.register_thread::<DropCount>().ok().unwrap();
+        let drops = Arc::new(AtomicUsize::new(0));
+        let count = SCAN_THRESHOLD / 2;
+        let nodes = (0..count)
+            .map(|_| Box::into_raw(Box::new(DropCount(drops.clone()))) as usize)
+            .collect::<Vec<_>>();
+        // survivor reads a couple of nodes, that worker is about to retire
+        let read = [nodes[0], nodes[1]]
+            .map(|node| unsafe { survivor.protect(node as *mut DropCount) }.ok().unwrap());
+
+        let (hp_array_ref, nodes_ref) = (&hp_array, &nodes);
+        let outcome = std::thread::scope(|s| {
+            s.spawn(move || {
+                let worker = hp_array_ref.register_thread::<DropCount>().ok().unwrap();
+                let nodes = nodes_ref.iter().map(|&node| node as *mut DropCount);
+                let nodes = nodes.collect::<Vec<_>>();
+                // one protection is released by unwinding, another one is leaked
+                let _protected = unsafe { worker.protect(nodes[2]) }.ok().unwrap();
+                std::mem::forget(unsafe { worker.protect(nodes[3]) }.ok().unwrap());
+                for &node in &nodes {
+                    worker.retire_raw_pointer(node);
+                }
+                panic!("worker failed mid operation");
+            })
+            .join()
+        });
+        assert!(outcome.is_err());
+
+        // leaked protection held through the last scan, and is cleared after the handoff only
+        assert_eq!(drops.load(Ordering::Relaxed), count - 3);
+        let diagnostics = hp_array.diagnostics();
+        assert_eq!(diagnostics.registered_threads, 1);
+        assert_eq!(diagnostics.protected, 2);
+
+        // survivor adopts the rest, once it's done reading
+        drop(read);
+        assert_eq!(survivor.try_reclaim(), 3);
+        assert_eq!(drops.load(Ordering::Relaxed), count);
+        assert_eq!(hp_array.pending_bytes(), 0);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod hp;
+pub mod ebr;
+pub mod reclaim;
 pub mod rcu;
+#[cfg(feature = "scan-stats")]
+pub mod scan_stats;
@@ -2,8 +2,9 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::ptr;
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 use crate::utils::backoff::Backoff;
+use crate::utils::spin_wait::SpinWait;
 
 static CONTROL_BIT: usize = 1;
 static RCU_ID: AtomicUsize = AtomicUsize::new(1);
@@ -15,7 +16,8 @@ thread_local! {
 
 pub struct Rcu<T: Sync> {
     /*
-        since this is a single-writer RCU, there could be only 2 possible versions of
+        since every update waits for readers of the previous version before publishing (see
+        handed_off for concurrent writers), there could be only 2 possible versions of
         underlying data at a time. so, instead of having two separate fields, we can have
         a combined value, that can both ensure consistency and simplify design:
         current epoch value can be stored to the least significant bit of pointer,
@@ -34,9 +36,21 @@ pub struct Rcu<T: Sync> {
         epoch and runs synchronize(). since reader hasn't updated 'readers' yet, writer is free
         to free the previous pointer. now reader updates 'readers' for previous epoch and obtains
         a guard with a dangling pointer. to rule out this risk, we delay previous pointer
-        reclamation to the next synchronize() invocation
+        reclamation to the next update
     */
-    previous_ptr: RefCell<*mut T>,
+    previous_ptr: AtomicPtr<T>,
+    /*
+        update() and try_update() may be called by several writers at once, for the
+        replace-whole-value, last-writer-wins case: writers contend on ptr_and_epoch CAS only,
+        and the one, that wins, hands the version it has replaced over to previous_ptr (freeing
+        the one that was there). handoffs must happen in publication order, though: if the
+        winner of the next CAS swapped previous_ptr first, the earlier winner would get (and
+        free) a version, whose readers nobody has waited for. so a winner first waits for the
+        handoff of the version it has replaced, which is tracked here: the latest version whose
+        predecessor is already in previous_ptr. that's a wait for another writer's few
+        instructions, never for readers. update_in_place() is still single-writer
+    */
+    handed_off: AtomicPtr<T>,
     rcu_id: usize,
     // reading threads counters for both rcu epochs
     readers: [AtomicUsize; 2],
@@ -44,41 +58,179 @@ pub struct Rcu<T: Sync> {
 
 impl<T: Sync> Rcu<T> {
     pub fn new(data: T) -> Self {
+        Self::from_box(Box::new(data))
+    }
+
+    // takes over an existing allocation, e.g. when value is already boxed after deserialization,
+    // instead of moving it out of the box and allocating again
+    pub fn from_box(boxed: Box<T>) -> Self {
         assert!(std::mem::align_of::<T>() & 1 == 0);
         let id = RCU_ID.fetch_add(1, Ordering::Relaxed);
-        let data_ptr = Box::into_raw(Box::new(data));
+        let data_ptr = Box::into_raw(boxed);
         Rcu {
             ptr_and_epoch: AtomicPtr::new(data_ptr),
-            previous_ptr: RefCell::new(ptr::null_mut()),
+            previous_ptr: AtomicPtr::new(ptr::null_mut()),
+            handed_off: AtomicPtr::new(data_ptr),
             rcu_id: id,
             readers: [const { AtomicUsize::new(0) }; 2],
         }
     }
 
-    pub fn read(&self) -> RcuReadGuard<T> {
-        let ptr_and_epoch = self.ptr_and_epoch.load(Ordering::Relaxed);
-        let epoch = ptr_and_epoch as usize & CONTROL_BIT;
+    /*
+        for latency-sensitive setups: makes the first read()/update() on this thread avoid paying
+        for cold readers counters' cache line and for thread-local map's first allocation.
+        touches both epochs' counters with no-op RMWs (so the line is fetched for writing, as
+        read() would do) and reserves room for this rcu in thread's nested reads map.
+        semantically it's a no-op, and it should be called on every thread that's about to read
+    */
+    pub fn prewarm(&self) {
+        for readers in self.readers.iter() {
+            readers.fetch_add(0, Ordering::Relaxed);
+        }
+        THREAD_RECORD.with(|tr| tr.borrow_mut().reserve(1));
+    }
+
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        self.read_with_ordering(Ordering::Relaxed)
+    }
+
+    /*
+        one-shot read, for callers, that don't need to hold on to the value: f gets the current
+        version under a read guard, that is released right after f returns (or unwinds). guard
+        is a regular one, so it costs the same as read() does, f just can't leak it, and so can't
+        block grace periods for longer than it runs
+    */
+    pub fn with_current<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        let guard = self.read();
+        f(&guard)
+    }
+
+    /*
+        epoch (0 or 1) of the current version, metadata only: nothing is pinned, so the version
+        may get replaced right after. every update flips it, so it tells, e.g., whether rcu has
+        been updated an odd number of times since it was last checked, or which epoch's readers
+        the next update will wait for (the other one)
+    */
+    pub fn peek_epoch(&self) -> usize {
+        self.ptr_and_epoch.load(Ordering::Acquire) as usize & CONTROL_BIT
+    }
+
+    /*
+        read() with explicit ordering of the version pointer load, for those who'd rather have
+        happens-before spelled out than rely on the hardware. none of them makes read see a newer
+        version: whatever the ordering, load returns some version, that is no older than the one
+        this thread has seen before (coherence), and it may well be the previous one, if update
+        is in flight. they differ in what is guaranteed about the version's contents:
+        - Relaxed (read()'s default): relies on address dependency, i.e. on that data is only
+          reached through the loaded pointer. every mainstream platform (but Alpha) orders such
+          dependent loads after the pointer load in hardware, so reader sees the value as it was
+          written before publishing. that's consume semantics though, which the language memory
+          model doesn't provide, so formally it's on the platform;
+        - Acquire: load synchronizes with update's publishing CAS (SeqCst): the value and anything
+          else, that writer did before the update, is visible to reader, on any platform;
+        - SeqCst: Acquire, plus the load takes part in the single total order of SeqCst
+          operations, e.g. for protocols, that pair the read with SeqCst accesses of other
+          atomics.
+        Release and AcqRel aren't valid for a load and panic. note, that pin() rechecks the version
+        with a SeqCst load anyway, which synchronizes with the publishing CAS as well, so as of
+        now, ordering only tells, what the caller relies on
+    */
+    pub fn read_with_ordering(&self, ordering: Ordering) -> RcuReadGuard<'_, T> {
+        assert!(
+            matches!(ordering, Ordering::Relaxed | Ordering::Acquire | Ordering::SeqCst),
+            "Rcu::read_with_ordering(): {:?} isn't a load ordering",
+            ordering
+        );
+        let (ptr, epoch) = self.pin(ordering);
+        RcuReadGuard {
+            rcu: self,
+            ptr,
+            epoch,
+        }
+    }
+
+    /*
+        registers thread as a reader of the current version, returning it along with its epoch.
+        writers only wait for readers, that they can see: a reader, that has loaded the version,
+        but not yet bumped the counter, may get preempted for two updates, the second of which
+        frees that very version (see previous_ptr). so the version is pinned only if it's still
+        the current one after the counter went up, otherwise the reader backs off and starts
+        over. counter bump and recheck are SeqCst, and so are writers' CAS and counter loads:
+        either the recheck sees the replacing CAS, or the writer, that syncs on this epoch after
+        it, sees the bump
+    */
+    fn pin(&self, ordering: Ordering) -> (*const T, usize) {
+        loop {
+            let ptr_and_epoch = self.ptr_and_epoch.load(ordering);
+            let epoch = ptr_and_epoch as usize & CONTROL_BIT;
+            THREAD_RECORD.with(|tr| {
+                let mut rcu_nested_map = tr.borrow_mut();
+                let nested = rcu_nested_map.entry(self.rcu_id).or_insert([0, 0]);
+                // outermost read of this epoch on this thread
+                if nested[epoch] == 0 {
+                    self.readers[epoch].fetch_add(1, Ordering::SeqCst);
+                }
+                nested[epoch] += 1;
+            });
+            if self.ptr_and_epoch.load(Ordering::SeqCst) == ptr_and_epoch {
+                return ((ptr_and_epoch as usize & !CONTROL_BIT) as *const T, epoch);
+            }
+            self.unpin(epoch);
+        }
+    }
+
+    fn unpin(&self, epoch: usize) {
         THREAD_RECORD.with(|tr| {
             let mut rcu_nested_map = tr.borrow_mut();
-            if rcu_nested_map.contains_key(&self.rcu_id) {
-                if rcu_nested_map[&self.rcu_id][epoch] == 0 {
-                    self.readers[epoch].fetch_add(1, Ordering::Release);
+            let nested = rcu_nested_map.get_mut(&self.rcu_id).unwrap();
+            nested[epoch] -= 1;
+            if nested[epoch] == 0 {
+                self.readers[epoch].fetch_sub(1, Ordering::Release);
+                if nested[epoch ^ 1] == 0 {
+                    rcu_nested_map.remove(&self.rcu_id);
                 }
-            } else {
-                self.readers[epoch].fetch_add(1, Ordering::Release);
-                rcu_nested_map.insert(self.rcu_id, [0, 0]);
             }
-            let nested = rcu_nested_map.get_mut(&self.rcu_id).unwrap();
-            nested[epoch] += 1;
         });
-        RcuReadGuard {
-            rcu: self,
-            ptr: (ptr_and_epoch as usize & !CONTROL_BIT) as *const T,
-            epoch,
-        }
     }
     pub fn update(&self, data: T) {
-        let new_data_ptr = Box::into_raw(Box::new(data));
+        self.publish(Box::into_raw(Box::new(data)));
+    }
+
+    /*
+        update for large values, that saves an allocation per update: once readers of the previous
+        version are gone (the same grace period, after which synchronize() would free it), its
+        allocation is recycled for the new version instead of being freed. the new version starts
+        as a copy of the current one (via clone_from(), so T may reuse its own buffers as well),
+        and f modifies it in place before it's published. there are still at most two live
+        versions, so in a steady state updates just alternate between two allocations.
+        the very first update has nothing to recycle yet and allocates.
+        unlike update(), it must not race with other writers: recycled version is taken out of
+        previous_ptr before anything is published
+    */
+    pub fn update_in_place(&self, f: impl FnOnce(&mut T))
+    where
+        T: Clone,
+    {
+        let current_ptr_and_epoch = self.ptr_and_epoch.load(Ordering::Acquire);
+        let next_epoch = (current_ptr_and_epoch as usize & CONTROL_BIT) ^ CONTROL_BIT;
+        let current_ptr = (current_ptr_and_epoch as usize & !CONTROL_BIT) as *mut T;
+
+        SpinWait::until(|| self.readers[next_epoch].load(Ordering::SeqCst) == 0);
+        let recycled_ptr = self.previous_ptr.swap(ptr::null_mut(), Ordering::AcqRel);
+
+        // current version is still being read, so it's only cloned from, never written to
+        let new_data_ptr = if recycled_ptr.is_null() {
+            Box::into_raw(Box::new(unsafe { (*current_ptr).clone() }))
+        } else {
+            unsafe { (*recycled_ptr).clone_from(&*current_ptr) };
+            recycled_ptr
+        };
+        f(unsafe { &mut *new_data_ptr });
+        // nothing left to free in publish(): previous_ptr is null now
+        self.publish(new_data_ptr);
+    }
+
+    fn publish(&self, new_data_ptr: *mut T) {
         let mut backoff = Backoff::new();
         
         loop {
@@ -86,17 +238,18 @@ impl<T: Sync> Rcu<T> {
             let next_epoch = (current_ptr_and_epoch as usize & CONTROL_BIT) ^ CONTROL_BIT;
             let current_ptr = (current_ptr_and_epoch as usize & !CONTROL_BIT) as *mut T;
             
-            self.synchronize(next_epoch, current_ptr);
+            self.synchronize(next_epoch);
             
             let new_ptr_and_epoch = (new_data_ptr as usize | next_epoch) as *mut T;
             
             match self.ptr_and_epoch.compare_exchange(
                 current_ptr_and_epoch,
                 new_ptr_and_epoch,
-                Ordering::Release,
+                Ordering::SeqCst,
                 Ordering::Relaxed
             ) {
                 Ok(_) => {
+                    self.hand_off(current_ptr, new_data_ptr);
                     break;
                 },
                 Err(_) => {
@@ -107,11 +260,17 @@ impl<T: Sync> Rcu<T> {
         }
     }
     
+    /*
+        update, that fails instead of waiting for readers of the previous version. it fails as
+        well, if another writer publishes in between the check and the CAS. either way, nothing
+        is committed: previous_ptr is only ever touched by hand_off(), after a successful CAS,
+        so a failed attempt leaves it pointing at the version, that it's been pointing at
+    */
     pub fn try_update(&self, data: T) -> bool {
         let current_ptr_and_epoch = self.ptr_and_epoch.load(Ordering::Acquire);
         let next_epoch = (current_ptr_and_epoch as usize & CONTROL_BIT) ^ CONTROL_BIT;
         let current_ptr = (current_ptr_and_epoch as usize & !CONTROL_BIT) as *mut T;
-        if !self.try_synchronize(next_epoch, current_ptr) {
+        if !self.is_synchronizable(next_epoch) {
             return false;
         }
         
@@ -120,10 +279,13 @@ impl<T: Sync> Rcu<T> {
         match self.ptr_and_epoch.compare_exchange(
             current_ptr_and_epoch,
             packed_ptr_and_epoch, 
-            Ordering::Release,
+            Ordering::SeqCst,
             Ordering::Relaxed
         ) {
-            Ok(_) => { true },
+            Ok(_) => {
+                self.hand_off(current_ptr, new_data_ptr);
+                true
+            },
             Err(_) => {
                 unsafe { drop(Box::from_raw(new_data_ptr)) };
                 false
@@ -132,32 +294,44 @@ impl<T: Sync> Rcu<T> {
         
     }
 
-    fn synchronize(&self, sync_epoch: usize, ptr: *mut T) {
-        let mut backoff = Backoff::new();
-
-        // wait for readers of sync_epoch to finish
-        while self.readers[sync_epoch].load(Ordering::Acquire) != 0 {
-            backoff.spin_yield();
-        }
+    // whether synchronization for epoch would go through right now, i.e. it has no readers
+    pub fn is_synchronizable(&self, epoch: usize) -> bool {
+        self.readers[epoch & CONTROL_BIT].load(Ordering::SeqCst) == 0
+    }
 
-        let previous_ptr = self.previous_ptr.replace(ptr);
+    /*
+        epoch, which readers the next update() would have to wait for (readers of the previous
+        version), if there are any right now. for monitoring: readers are supposed to be
+        short-lived, so an epoch, that keeps being reported here, most likely has a leaked read
+        guard, and writer's update() is going to hang (try_update() keeps failing).
+        readers of the current epoch don't block anything and aren't reported
+    */
+    pub fn grace_period_blocked_on(&self) -> Option<usize> {
+        let current_epoch = self.ptr_and_epoch.load(Ordering::Acquire) as usize & CONTROL_BIT;
+        let sync_epoch = current_epoch ^ CONTROL_BIT;
+        (!self.is_synchronizable(sync_epoch)).then_some(sync_epoch)
+    }
 
-        // someone could have already succeeded with synchronization
-        // free if we have a previous pointer and it's different from current
-        if !previous_ptr.is_null() && previous_ptr != ptr {
-            unsafe { drop(Box::from_raw(previous_ptr)) };
-        }
+    fn synchronize(&self, sync_epoch: usize) {
+        // wait for readers of sync_epoch (those of previous version) to finish
+        SpinWait::until(|| self.readers[sync_epoch].load(Ordering::SeqCst) == 0);
     }
 
-    fn try_synchronize(&self, sync_epoch: usize, ptr: *mut T) -> bool  {
-        if self.readers[sync_epoch].load(Ordering::Acquire) != 0 {
-            return false;
+    /*
+        called by the writer, whose CAS has replaced the version, that previous_ptr is freed for:
+        its readers were waited for before that CAS, and new ones only get the published version
+        since then. see handed_off for the ordering between concurrent writers
+    */
+    fn hand_off(&self, replaced: *mut T, published: *mut T) {
+        let mut backoff = Backoff::new();
+        while self.handed_off.load(Ordering::Acquire) != replaced {
+            backoff.spin_yield();
         }
-        let previous_ptr = self.previous_ptr.replace(ptr);
-        if !previous_ptr.is_null() && previous_ptr != ptr {
+        let previous_ptr = self.previous_ptr.swap(replaced, Ordering::AcqRel);
+        if !previous_ptr.is_null() {
             unsafe { drop(Box::from_raw(previous_ptr)) };
         }
-        true
+        self.handed_off.store(published, Ordering::Release);
     }
 }
 
@@ -187,19 +361,52 @@ impl<'a, T: Sync> Deref for RcuReadGuard<'a, T> {
     }
 }
 
-impl<'a, T: Sync> Drop for RcuReadGuard<'a, T> {
-    fn drop(&mut self) {
+/*
+    clone shares the same pinned version: it's a nested read of the guard's own epoch (rather than
+    of the current one, as read() would be), so it only bumps thread's nested counter, and the
+    epoch's readers counter is released once the last of the clones is dropped.
+    guards aren't Send, so the clone stays on the thread, whose record it counts in
+*/
+impl<'a, T: Sync> Clone for RcuReadGuard<'a, T> {
+    fn clone(&self) -> Self {
         THREAD_RECORD.with(|tr| {
             let mut rcu_nested_map = tr.borrow_mut();
-            let nested = rcu_nested_map.get_mut(&self.rcu.rcu_id).unwrap();
-            nested[self.epoch] -= 1;
-            if nested[self.epoch] == 0 {
-                self.rcu.readers[self.epoch].fetch_sub(1, Ordering::Release);
-                if nested[self.epoch ^ 1] == 0 {
-                    rcu_nested_map.remove(&self.rcu.rcu_id);
-                }
-            }
+            // entry is there for as long as this guard is alive
+            rcu_nested_map.get_mut(&self.rcu.rcu_id).unwrap()[self.epoch] += 1;
         });
+        RcuReadGuard {
+            rcu: self.rcu,
+            ptr: self.ptr,
+            epoch: self.epoch,
+        }
+    }
+}
+
+impl<'a, T: Sync> RcuReadGuard<'a, T> {
+    /*
+        moves a long-lived reader on to the latest version, if rcu has been updated since the
+        guard was taken: the current version is pinned, and only then the old one is released.
+        lingering readers of the old epoch block the next update's grace period (and keep the old
+        version alive), so a long-running reader may call it whenever it's fine to see new data.
+        any references obtained through the guard before are gone by then (it takes &mut self).
+        returns whether the guard has moved
+    */
+    pub fn refresh(&mut self) -> bool {
+        let current = self.rcu.ptr_and_epoch.load(Ordering::Relaxed) as usize & !CONTROL_BIT;
+        if current as *const T == self.ptr {
+            return false;
+        }
+        let (ptr, epoch) = self.rcu.pin(Ordering::Relaxed);
+        self.rcu.unpin(self.epoch);
+        self.ptr = ptr;
+        self.epoch = epoch;
+        true
+    }
+}
+
+impl<'a, T: Sync> Drop for RcuReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.rcu.unpin(self.epoch);
     }
 }
 
@@ -210,10 +417,358 @@ impl<T: Sync> Drop for Rcu<T> {
         // wouldn't outlive rcu
         if !ptr.is_null() {
             unsafe { drop(Box::from_raw(ptr)); }
-            let prev_ptr = self.previous_ptr.replace(ptr::null_mut());
+            let prev_ptr = *self.previous_ptr.get_mut();
             if !prev_ptr.is_null() {
                 unsafe {drop(Box::from_raw(prev_ptr)); }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Rcu, THREAD_RECORD};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_prewarm_keeps_state() {
+        let rcu = Rcu::new(1u16);
+        rcu.prewarm();
+        assert_eq!(rcu.readers[0].load(Ordering::Relaxed), 0);
+        assert_eq!(rcu.readers[1].load(Ordering::Relaxed), 0);
+        assert!(THREAD_RECORD.with(|tr| !tr.borrow().contains_key(&rcu.rcu_id)));
+
+        {
+            let guard = rcu.read();
+            assert_eq!(*guard, 1);
+        }
+        assert!(rcu.try_update(2));
+        assert_eq!(*rcu.read(), 2);
+        assert!(THREAD_RECORD.with(|tr| !tr.borrow().contains_key(&rcu.rcu_id)));
+    }
+
+    #[test]
+    fn test_update_in_place() {
+        let rcu = Rcu::new(vec![0u64; 1024]);
+        let mut allocations = std::collections::HashSet::new();
+        let mut buffers = std::collections::HashSet::new();
+        {
+            let guard = rcu.read();
+            allocations.insert(&*guard as *const Vec<u64>);
+            buffers.insert(guard.as_ptr());
+        }
+        for i in 1..=10 {
+            rcu.update_in_place(|data| data[0] = i);
+            let guard = rcu.read();
+            assert_eq!(guard[0], i);
+            assert!(guard[1..].iter().all(|&value| value == 0));
+            allocations.insert(&*guard as *const Vec<u64>);
+            buffers.insert(guard.as_ptr());
+        }
+        // one allocation for the initial value, one for the first update, and then they alternate.
+        // clone_from() makes vector reuse its buffer as well
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(buffers.len(), 2);
+    }
+
+    #[test]
+    fn test_grace_period_blocked_on() {
+        let rcu = Rcu::new(1u16);
+        assert_eq!(rcu.grace_period_blocked_on(), None);
+
+        let lingering = rcu.read();
+        // readers of the current version don't block anything yet
+        assert!(!rcu.is_synchronizable(lingering.epoch));
+        assert_eq!(rcu.grace_period_blocked_on(), None);
+
+        assert!(rcu.try_update(2));
+        assert!(rcu.is_synchronizable(lingering.epoch ^ 1));
+        assert_eq!(rcu.grace_period_blocked_on(), Some(lingering.epoch));
+        assert!(!rcu.try_update(3));
+
+        drop(lingering);
+        assert!(rcu.is_synchronizable(0) && rcu.is_synchronizable(1));
+        assert_eq!(rcu.grace_period_blocked_on(), None);
+        assert!(rcu.try_update(3));
+        assert_eq!(*rcu.read(), 3);
+    }
+
+    #[test]
+    fn test_with_current() {
+        let rcu = Rcu::new(String::from("first"));
+        let epoch = rcu.peek_epoch();
+        assert_eq!(rcu.with_current(|value| value.len()), 5);
+
+        rcu.update(String::from("second"));
+        assert_eq!(rcu.peek_epoch(), epoch ^ 1);
+        assert_eq!(rcu.with_current(|value| value.clone()), "second");
+        // reader is gone as soon as f returns, so nothing blocks the next grace period
+        assert!(rcu.is_synchronizable(0) && rcu.is_synchronizable(1));
+        assert!(THREAD_RECORD.with(|tr| !tr.borrow().contains_key(&rcu.rcu_id)));
+        assert!(rcu.try_update(String::from("third")));
+        assert_eq!(rcu.peek_epoch(), epoch);
+
+        // same within an outer read: nested read is released, while the outer one stays
+        let outer = rcu.read();
+        assert_eq!(rcu.with_current(|value| value.clone()), "third");
+        assert_eq!(rcu.readers[outer.epoch].load(Ordering::Relaxed), 1);
+        drop(outer);
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 0);
+
+        // and when f panics
+        let unwound = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            rcu.with_current(|_| panic!("reader failed"))
+        }));
+        assert!(unwound.is_err());
+        assert!(rcu.is_synchronizable(0) && rcu.is_synchronizable(1));
+        rcu.update(String::from("fourth"));
+        assert_eq!(*rcu.read(), "fourth");
+    }
+
+    #[test]
+    fn test_from_box() {
+        let boxed = Box::new([7u16; 64]);
+        let boxed_ptr = &*boxed as *const [u16; 64];
+        let rcu = Rcu::from_box(boxed);
+        {
+            let guard = rcu.read();
+            // same allocation, no re-boxing
+            assert_eq!(&*guard as *const [u16; 64], boxed_ptr);
+            assert_eq!(*guard, [7u16; 64]);
+        }
+        rcu.update([8u16; 64]);
+        assert_eq!(*rcu.read(), [8u16; 64]);
+    }
+
+    #[test]
+    fn test_cloned_read_guard() {
+        let rcu = Rcu::new(1u16);
+        let guard = rcu.read();
+        let epoch = guard.epoch;
+        let clone = guard.clone();
+        // newer version is published, clones keep sharing the pinned one
+        rcu.update(2);
+        let clone_of_clone = clone.clone();
+        assert_eq!((*guard, *clone, *clone_of_clone), (1, 1, 1));
+        assert_eq!(*rcu.read(), 2);
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 1);
+
+        drop(clone);
+        drop(guard);
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 1);
+        assert_eq!(*clone_of_clone, 1);
+        drop(clone_of_clone);
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 0);
+        assert!(THREAD_RECORD.with(|tr| !tr.borrow().contains_key(&rcu.rcu_id)));
+
+        // the other way around: original outlives its clones
+        let guard = rcu.read();
+        let epoch = guard.epoch;
+        drop((guard.clone(), guard.clone()));
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 1);
+        drop(guard);
+        assert_eq!(rcu.readers[epoch].load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let rcu = Rcu::new(1u16);
+        let mut guard = rcu.read();
+        let old_epoch = guard.epoch;
+        assert!(!guard.refresh());
+
+        rcu.update(2);
+        assert_eq!(*guard, 1);
+        assert_eq!(rcu.readers[old_epoch].load(Ordering::Relaxed), 1);
+        assert!(guard.refresh());
+        assert_eq!(*guard, 2);
+        assert_ne!(guard.epoch, old_epoch);
+        assert_eq!(rcu.readers[old_epoch].load(Ordering::Relaxed), 0);
+        assert_eq!(rcu.readers[guard.epoch].load(Ordering::Relaxed), 1);
+
+        // old epoch has no readers left, so the next update doesn't wait for anybody
+        assert!(rcu.try_update(3));
+        assert!(guard.refresh());
+        assert_eq!(*guard, 3);
+        drop(guard);
+        assert_eq!(rcu.readers[0].load(Ordering::Relaxed), 0);
+        assert_eq!(rcu.readers[1].load(Ordering::Relaxed), 0);
+    }
+
+    // live instances, so that both leaks and double frees of replaced versions show up
+    struct Tracked<'a> {
+        writer: usize,
+        seq: usize,
+        // seq repeated, torn or freed value would hardly keep it
+        check: usize,
+        live: &'a AtomicUsize,
+    }
+
+    impl<'a> Tracked<'a> {
+        fn new(writer: usize, seq: usize, live: &'a AtomicUsize) -> Self {
+            live.fetch_add(1, Ordering::Relaxed);
+            Tracked { writer, seq, check: !seq, live }
+        }
+    }
+
+    impl Drop for Tracked<'_> {
+        fn drop(&mut self) {
+            assert_eq!(self.check, !self.seq, "Tracked: dropped twice");
+            self.check = 0;
+            assert!(self.live.fetch_sub(1, Ordering::Relaxed) > 0);
+        }
+    }
+
+    // versions held by readers survive both a failed try_update() and the update after it.
+    // Tracked's check catches a premature free here (and so does Miri)
+    #[test]
+    fn test_failed_try_update_then_update() {
+        let live = AtomicUsize::new(0);
+        let rcu = Rcu::new(Tracked::new(0, 0, &live));
+        let first_reader = rcu.read();
+        assert!(rcu.try_update(Tracked::new(0, 1, &live)));
+        // first_reader's version is the previous one now, so the next one has to wait
+        assert!(!rcu.try_update(Tracked::new(0, 2, &live)));
+        assert_eq!(live.load(Ordering::Relaxed), 2);
+
+        let second_reader = rcu.read();
+        drop(first_reader);
+        rcu.update(Tracked::new(0, 3, &live));
+        // version 0 is freed, version 1 is the previous one, held by second_reader
+        assert_eq!(live.load(Ordering::Relaxed), 2);
+        assert_eq!(second_reader.seq, 1);
+        assert_eq!(second_reader.check, !1);
+        drop(second_reader);
+
+        assert_eq!(rcu.read().seq, 3);
+        drop(rcu);
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+    }
+
+    impl Clone for Tracked<'_> {
+        fn clone(&self) -> Self {
+            Tracked::new(self.writer, self.seq, self.live)
+        }
+    }
+
+    /*
+        previous_ptr handoff, when update_in_place() recycles the previous version: first
+        in-place update has nothing to recycle, and leaves the replaced version (pinned by a
+        reader) in previous_ptr; the second one recycles it, but only once its reader is gone;
+        then update() frees the version, that the second one has replaced, and keeps the one
+        pinned by the next reader. deterministic and single-threaded, so it runs under Miri,
+        which catches a write into a version, that is still being read, or a double free
+    */
+    #[test]
+    fn test_reclaim_in_place_handoff() {
+        let live = AtomicUsize::new(0);
+        let rcu = Rcu::new(Tracked::new(0, 0, &live));
+        let set_seq = |seq: usize| {
+            move |version: &mut Tracked| {
+                version.seq = seq;
+                version.check = !seq;
+            }
+        };
+
+        let first_reader = rcu.read();
+        rcu.update_in_place(set_seq(1));
+        assert_eq!(live.load(Ordering::Relaxed), 2);
+        assert_eq!((first_reader.seq, first_reader.check), (0, !0));
+        drop(first_reader);
+
+        rcu.update_in_place(set_seq(2));
+        assert_eq!(live.load(Ordering::Relaxed), 2);
+        let second_reader = rcu.read();
+        assert_eq!(second_reader.seq, 2);
+
+        rcu.update(Tracked::new(0, 3, &live));
+        assert_eq!(live.load(Ordering::Relaxed), 2);
+        assert_eq!((second_reader.seq, second_reader.check), (2, !2));
+        drop(second_reader);
+
+        assert_eq!(rcu.read().seq, 3);
+        drop(rcu);
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let live = AtomicUsize::new(0);
+        let (writers, updates, readers) = (4, 2000, 2);
+        let rcu = Rcu::new(Tracked::new(usize::MAX, 0, &live));
+        let writers_done = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for w in 0..writers {
+                let (rcu, live, writers_done) = (&rcu, &live, &writers_done);
+                s.spawn(move || {
+                    for seq in 0..updates {
+                        if seq % 2 == 0 {
+                            rcu.update(Tracked::new(w, seq, live));
+                        } else {
+                            while !rcu.try_update(Tracked::new(w, seq, live)) {
+                                std::thread::yield_now();
+                            }
+                        }
+                    }
+                    writers_done.fetch_add(1, Ordering::Release);
+                });
+            }
+            for _ in 0..readers {
+                s.spawn(|| {
+                    // every writer's versions are seen in the order they were written
+                    let mut last_seen = vec![None; writers];
+                    while writers_done.load(Ordering::Acquire) < writers {
+                        let guard = rcu.read();
+                        assert_eq!(guard.check, !guard.seq);
+                        if guard.writer != usize::MAX {
+                            let last = &mut last_seen[guard.writer];
+                            assert!(last.is_none_or(|last| last <= guard.seq));
+                            *last = Some(guard.seq);
+                        }
+                    }
+                });
+            }
+        });
+        // current and previous versions only
+        assert!(live.load(Ordering::Relaxed) <= 2);
+        assert_eq!(rcu.read().seq, updates - 1);
+        drop(rcu);
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_read_with_ordering() {
+        // whatever version reader gets, it sees the value as written: (n, n * n)
+        let rcu = Rcu::new((0u64, 0u64));
+        let updates = 2000;
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for n in 1..=updates {
+                    rcu.update((n, n * n));
+                }
+            });
+            s.spawn(|| {
+                let orderings = [Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst];
+                let mut last_seen = 0;
+                for ordering in orderings.into_iter().cycle() {
+                    let guard = rcu.read_with_ordering(ordering);
+                    let (n, square) = *guard;
+                    assert_eq!(square, n * n);
+                    // and never goes back to an older version
+                    assert!(n >= last_seen);
+                    last_seen = n;
+                    if n == updates {
+                        break;
+                    }
+                }
+            });
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't a load ordering")]
+    fn test_read_with_store_ordering() {
+        let rcu = Rcu::new(1u16);
+        let _guard = rcu.read_with_ordering(Ordering::Release);
+    }
+}
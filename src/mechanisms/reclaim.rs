@@ -0,0 +1,216 @@
+use crate::mechanisms::ebr::{EbrDomain, EbrGuard, EbrProtectedPointer};
+use crate::mechanisms::hp::{
+    HazardPointerArray, HazardPointerGuard, ProtectedPointer, ProtectionError, RegisterThreadError,
+};
+use std::marker::PhantomData;
+use std::ops::DerefMut;
+
+/*
+    collections are written once against these traits, so that reclamation scheme is just a type
+    parameter: MSQueue<T> uses hazard pointers, MSQueue<T, EbrReclaimer> uses epochs.
+    both schemes share the same usage pattern: load pointer -> protect -> recheck source ->
+    dereference -> (unlink ->) retire
+*/
+
+pub trait Reclaimer {
+    // shared state, that threads register in (HazardPointerArray, EbrDomain)
+    type Domain: Sync;
+    // per-thread handle, that protects and retires nodes of type T
+    type Guard<'a, T>: ReclaimerGuard<T>;
+
+    fn register_thread<T>(domain: &Self::Domain) -> Result<Self::Guard<'_, T>, RegisterThreadError>;
+}
+
+pub trait ReclaimerGuard<T> {
+    type Protected<'g>: Protected<T>
+    where
+        Self: 'g;
+
+    /// # Safety
+    ///
+    /// same contract as HazardPointerGuard::protect(): `ptr` must be valid, and the protected
+    /// pointer may only be dereferenced after it's been rechecked against its source
+    unsafe fn protect(&self, ptr: *mut T) -> Result<Self::Protected<'_>, ProtectionError>;
+
+    // retired nodes may be freed on another thread (hazard pointers hand them over as orphans,
+    // see HazardPointerArray::orphans), so only Send ones can be retired
+    fn retire_node<'g>(&'g self, protected: Self::Protected<'g>)
+    where
+        T: Send;
+
+    fn retire_raw_pointer(&self, ptr: *mut T)
+    where
+        T: Send;
+
+    // peak tracking of simultaneously protected pointers, see HazardPointerGuard.
+    // schemes, that don't limit protections per thread, have nothing to report
+    fn peak_protected(&self) -> usize {
+        0
+    }
+
+    fn reset_peak_protected(&self) -> usize {
+        0
+    }
+
+    // for debug assertions of protection-critical orderings. schemes, that protect everything
+    // for as long as the guard is alive, report any pointer as protected
+    fn is_protected(&self, _ptr: *const T) -> bool {
+        true
+    }
+}
+
+/*
+    debug-only check, that an operation never protects more pointers at once than its collection
+    declares in HAZARDS_PER_OP: created at the start of the operation, checked on drop.
+    it resets guard's peak tracking, so nested budgets only cover their own part of operation.
+    in release builds it does nothing
+*/
+pub(crate) struct HazardBudget<'g, T, G: ReclaimerGuard<T>> {
+    guard: &'g G,
+    base: usize,
+    limit: usize,
+    operation: &'static str,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<'g, T, G: ReclaimerGuard<T>> HazardBudget<'g, T, G> {
+    pub(crate) fn new(guard: &'g G, limit: usize, operation: &'static str) -> Self {
+        let base = if cfg!(debug_assertions) {
+            guard.reset_peak_protected()
+        } else {
+            0
+        };
+        HazardBudget {
+            guard,
+            base,
+            limit,
+            operation,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'g, T, G: ReclaimerGuard<T>> Drop for HazardBudget<'g, T, G> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !std::thread::panicking() {
+            let used = self.guard.peak_protected().saturating_sub(self.base);
+            assert!(
+                used <= self.limit,
+                "{} protected {} pointers at once, while HAZARDS_PER_OP is {}",
+                self.operation,
+                used,
+                self.limit
+            );
+        }
+    }
+}
+
+pub trait Protected<T>: DerefMut<Target = T> {
+    fn as_ptr(&self) -> *const T;
+
+    fn as_mut_ptr(&self) -> *mut T;
+}
+
+pub struct HpReclaimer;
+
+impl Reclaimer for HpReclaimer {
+    type Domain = HazardPointerArray;
+    type Guard<'a, T> = HazardPointerGuard<'a, T>;
+
+    fn register_thread<T>(domain: &Self::Domain) -> Result<Self::Guard<'_, T>, RegisterThreadError> {
+        domain.register_thread()
+    }
+}
+
+impl<'a, T> ReclaimerGuard<T> for HazardPointerGuard<'a, T> {
+    type Protected<'g>
+        = ProtectedPointer<'g, T>
+    where
+        Self: 'g;
+
+    unsafe fn protect(&self, ptr: *mut T) -> Result<Self::Protected<'_>, ProtectionError> {
+        unsafe { HazardPointerGuard::protect(self, ptr) }
+    }
+
+    fn retire_node<'g>(&'g self, protected: Self::Protected<'g>)
+    where
+        T: Send,
+    {
+        HazardPointerGuard::retire_node(self, protected)
+    }
+
+    fn retire_raw_pointer(&self, ptr: *mut T)
+    where
+        T: Send,
+    {
+        HazardPointerGuard::retire_raw_pointer(self, ptr)
+    }
+
+    fn peak_protected(&self) -> usize {
+        HazardPointerGuard::peak_protected(self)
+    }
+
+    fn reset_peak_protected(&self) -> usize {
+        HazardPointerGuard::reset_peak_protected(self)
+    }
+
+    fn is_protected(&self, ptr: *const T) -> bool {
+        HazardPointerGuard::is_protected(self, ptr)
+    }
+}
+
+impl<'a, T> Protected<T> for ProtectedPointer<'a, T> {
+    fn as_ptr(&self) -> *const T {
+        ProtectedPointer::as_ptr(self)
+    }
+
+    fn as_mut_ptr(&self) -> *mut T {
+        ProtectedPointer::as_mut_ptr(self)
+    }
+}
+
+pub struct EbrReclaimer;
+
+impl Reclaimer for EbrReclaimer {
+    type Domain = EbrDomain;
+    type Guard<'a, T> = EbrGuard<'a, T>;
+
+    fn register_thread<T>(domain: &Self::Domain) -> Result<Self::Guard<'_, T>, RegisterThreadError> {
+        domain.register_thread()
+    }
+}
+
+impl<'a, T> ReclaimerGuard<T> for EbrGuard<'a, T> {
+    type Protected<'g>
+        = EbrProtectedPointer<'g, T>
+    where
+        Self: 'g;
+
+    unsafe fn protect(&self, ptr: *mut T) -> Result<Self::Protected<'_>, ProtectionError> {
+        unsafe { EbrGuard::protect(self, ptr) }
+    }
+
+    fn retire_node<'g>(&'g self, protected: Self::Protected<'g>)
+    where
+        T: Send,
+    {
+        EbrGuard::retire_node(self, protected)
+    }
+
+    fn retire_raw_pointer(&self, ptr: *mut T)
+    where
+        T: Send,
+    {
+        EbrGuard::retire_raw_pointer(self, ptr)
+    }
+}
+
+impl<'a, T> Protected<T> for EbrProtectedPointer<'a, T> {
+    fn as_ptr(&self) -> *const T {
+        EbrProtectedPointer::as_ptr(self)
+    }
+
+    fn as_mut_ptr(&self) -> *mut T {
+        EbrProtectedPointer::as_mut_ptr(self)
+    }
+}
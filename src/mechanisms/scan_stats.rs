@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/*
+    instrumentation of hazard pointer reclamation, compiled in only with "scan-stats" feature.
+    histograms are logarithmic: bucket 0 counts zeros, bucket i counts values in [2^(i-1), 2^i),
+    and the last bucket takes everything above. node's latency is measured in scans (of any
+    guard of the array), that have completed between its retirement and its reclamation: the
+    more scans a node survives, the longer it's been kept alive by hazard pointers (or waited
+    for its guard to reach SCAN_THRESHOLD)
+*/
+
+pub const DURATION_BUCKETS: usize = 40;
+pub const RECLAIMED_BUCKETS: usize = 16;
+
+pub(crate) struct ScanCounters {
+    scans: AtomicU64,
+    reclaimed: AtomicU64,
+    scans_survived: AtomicU64,
+    // scan durations in nanoseconds
+    duration_histogram: [AtomicU64; DURATION_BUCKETS],
+    // number of pointers freed by a single scan
+    reclaimed_histogram: [AtomicU64; RECLAIMED_BUCKETS],
+}
+
+#[derive(Clone, Debug)]
+pub struct ScanStats {
+    pub scans: u64,
+    pub reclaimed: u64,
+    pub scans_survived: u64,
+    pub duration_histogram: [u64; DURATION_BUCKETS],
+    pub reclaimed_histogram: [u64; RECLAIMED_BUCKETS],
+}
+
+fn bucket(value: u64, buckets: usize) -> usize {
+    ((u64::BITS - value.leading_zeros()) as usize).min(buckets - 1)
+}
+
+impl ScanCounters {
+    pub(crate) const fn new() -> Self {
+        ScanCounters {
+            scans: AtomicU64::new(0),
+            reclaimed: AtomicU64::new(0),
+            scans_survived: AtomicU64::new(0),
+            duration_histogram: [const { AtomicU64::new(0) }; DURATION_BUCKETS],
+            reclaimed_histogram: [const { AtomicU64::new(0) }; RECLAIMED_BUCKETS],
+        }
+    }
+
+    // stamp for a pointer being retired now
+    pub(crate) fn current_scan(&self) -> u64 {
+        self.scans.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_reclaim(&self, retired_at_scan: u64) {
+        // reclaiming scan itself is not counted yet
+        let survived = self.current_scan().saturating_sub(retired_at_scan);
+        self.scans_survived.fetch_add(survived, Ordering::Relaxed);
+        self.reclaimed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_scan(&self, duration: Duration, reclaimed: usize) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.duration_histogram[bucket(nanos, DURATION_BUCKETS)].fetch_add(1, Ordering::Relaxed);
+        self.reclaimed_histogram[bucket(reclaimed as u64, RECLAIMED_BUCKETS)]
+            .fetch_add(1, Ordering::Relaxed);
+        self.scans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ScanStats {
+        ScanStats {
+            scans: self.scans.load(Ordering::Relaxed),
+            reclaimed: self.reclaimed.load(Ordering::Relaxed),
+            scans_survived: self.scans_survived.load(Ordering::Relaxed),
+            duration_histogram: self
+                .duration_histogram
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            reclaimed_histogram: self
+                .reclaimed_histogram
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl ScanStats {
+    // average number of scans a reclaimed node has survived after its retirement
+    pub fn average_scans_survived(&self) -> f64 {
+        if self.reclaimed == 0 {
+            return 0.0;
+        }
+        self.scans_survived as f64 / self.reclaimed as f64
+    }
+
+    pub fn average_reclaimed_per_scan(&self) -> f64 {
+        if self.scans == 0 {
+            return 0.0;
+        }
+        self.reclaimed as f64 / self.scans as f64
+    }
+
+    // lower bound of histogram bucket's range
+    pub fn bucket_floor(bucket: usize) -> u64 {
+        match bucket {
+            0 => 0,
+            _ => 1 << (bucket - 1),
+        }
+    }
+}
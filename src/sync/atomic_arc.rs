@@ -0,0 +1,235 @@
+use crate::mechanisms::hp::{HazardPointerGuard, ProtectionError};
+use crate::mechanisms::reclaim::HazardBudget;
+use crate::utils::backoff::Backoff;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{fence, AtomicPtr, Ordering};
+
+/*
+    atomic slot holding an Arc<T>. the naive approach (storing Arc::into_raw pointer and bumping
+    the strong count on load) is racy: loader could read the pointer, get preempted, and meanwhile
+    a writer swaps the slot and drops the last strong reference. so loader would increment a
+    refcount in already freed memory. here, loaders protect the slot's pointer with a hazard
+    pointer long enough to clone the Arc, and writers retire the replaced value, so that its
+    strong count is decremented only when no loader can observe it anymore.
+    hazard pointer scan reclaims retired pointers with Box::from_raw, so instead of raw Arc
+    pointers we keep boxed Arcs in the slot (one extra allocation per store)
+*/
+
+pub struct AtomicArc<T> {
+    ptr: AtomicPtr<ArcNode<T>>,
+    // AtomicPtr is Send + Sync for any pointee, so borrow auto traits from Arc<T> instead
+    _marker: PhantomData<Arc<T>>,
+}
+
+#[repr(transparent)]
+pub struct ArcNode<T>(Arc<T>);
+
+impl<T: Send + Sync> AtomicArc<T> {
+    // max number of pointers a single operation protects at once (the slot's current node).
+    // guards used with this slot need at least that many free hazard pointer slots
+    pub const HAZARDS_PER_OP: usize = 1;
+
+    pub fn new(value: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(ArcNode(value)))),
+            _marker: PhantomData,
+        }
+    }
+
+    // user should register thread to obtain guard
+    pub fn load(&self, guard: &HazardPointerGuard<ArcNode<T>>) -> Arc<T> {
+        let _budget = HazardBudget::new(guard, Self::HAZARDS_PER_OP, "AtomicArc::load()");
+        let mut backoff = Backoff::new();
+        loop {
+            let ptr = self.ptr.load(Ordering::Relaxed);
+            let protected = match unsafe { guard.protect(ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                // slot always holds a node
+                Err(ProtectionError::NullPointer) => {
+                    panic!("AtomicArc::load(): found null pointer while protecting slot");
+                }
+            };
+            // pointer could have been retired before we published the hazard pointer
+            if self.ptr.load(Ordering::Acquire) != ptr {
+                continue;
+            }
+            // node stays alive while protected, so bumping strong count is safe here
+            return Arc::clone(&protected.0);
+        }
+    }
+
+    // no protection needed: we never dereference replaced pointer, only retire it
+    pub fn store(&self, value: Arc<T>, guard: &HazardPointerGuard<ArcNode<T>>) {
+        let new_node = Box::into_raw(Box::new(ArcNode(value)));
+        let old_node = self.ptr.swap(new_node, Ordering::AcqRel);
+        guard.retire_raw_pointer(old_node);
+    }
+
+    // replaces stored value with new one if it is the same allocation as current (Arc::ptr_eq).
+    // on failure returns the actually stored value
+    pub fn compare_and_swap(
+        &self,
+        current: &Arc<T>,
+        new: Arc<T>,
+        guard: &HazardPointerGuard<ArcNode<T>>,
+    ) -> Result<(), Arc<T>> {
+        let _budget =
+            HazardBudget::new(guard, Self::HAZARDS_PER_OP, "AtomicArc::compare_and_swap()");
+        let new_node = Box::into_raw(Box::new(ArcNode(new)));
+        let mut backoff = Backoff::new();
+        loop {
+            let ptr = self.ptr.load(Ordering::Relaxed);
+            let protected = match unsafe { guard.protect(ptr) } {
+                Ok(ptr) => {
+                    fence(Ordering::Acquire);
+                    ptr
+                }
+                Err(ProtectionError::NoAvailableIndices) => {
+                    backoff.spin();
+                    continue;
+                }
+                Err(ProtectionError::NullPointer) => {
+                    panic!("AtomicArc::compare_and_swap(): found null pointer while protecting slot");
+                }
+            };
+            if self.ptr.load(Ordering::Acquire) != ptr {
+                continue;
+            }
+
+            if !Arc::ptr_eq(&protected.0, current) {
+                let actual = Arc::clone(&protected.0);
+                // new node was never published
+                unsafe { drop(Box::from_raw(new_node)) };
+                return Err(actual);
+            }
+
+            if self
+                .ptr
+                .compare_exchange(ptr, new_node, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                guard.retire_node(protected);
+                return Ok(());
+            }
+            backoff.spin();
+        }
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // exclusive access: retired nodes are owned by guards, the current one is ours
+        let ptr = *self.ptr.get_mut();
+        unsafe { drop(Box::from_raw(ptr)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicArc;
+    use crate::mechanisms::hp::HazardPointerArray;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier, LazyLock};
+
+    static HP_ARRAY: LazyLock<HazardPointerArray> = LazyLock::new(HazardPointerArray::new);
+
+    #[test]
+    fn test_basic_operations() {
+        let guard = HP_ARRAY.register_thread().ok().unwrap();
+        let first = Arc::new(1);
+        let atomic_arc = AtomicArc::new(first.clone());
+        assert_eq!(*atomic_arc.load(&guard), 1);
+
+        assert!(atomic_arc.compare_and_swap(&first, Arc::new(2), &guard).is_ok());
+        let second = atomic_arc.load(&guard);
+        assert_eq!(*second, 2);
+
+        let failed = atomic_arc.compare_and_swap(&first, Arc::new(3), &guard);
+        assert!(Arc::ptr_eq(&failed.unwrap_err(), &second));
+
+        atomic_arc.store(Arc::new(4), &guard);
+        assert_eq!(*atomic_arc.load(&guard), 4);
+    }
+
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct DropCounter {
+        value: usize,
+    }
+
+    impl DropCounter {
+        fn new(value: usize) -> Self {
+            CREATED.fetch_add(1, Ordering::Relaxed);
+            DropCounter { value }
+        }
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let thread_count = 3;
+        let per_thread_ops = 256;
+
+        for _ in 0..200 {
+            let atomic_arc = AtomicArc::new(Arc::new(DropCounter::new(0)));
+            let atomic_arc_ref = &atomic_arc;
+            // guard drop leaves nodes still protected by others unreclaimed,
+            // so no guard should go away while others are loading
+            let barrier = Barrier::new(thread_count);
+            let barrier_ref = &barrier;
+
+            std::thread::scope(|s| {
+                for t in 0..thread_count {
+                    s.spawn(move || {
+                        let guard = HP_ARRAY.register_thread().ok().unwrap();
+                        for i in 0..per_thread_ops {
+                            let loaded = atomic_arc_ref.load(&guard);
+                            if i % 2 == 0 {
+                                atomic_arc_ref.store(Arc::new(DropCounter::new(t * i)), &guard);
+                            } else {
+                                let new = Arc::new(DropCounter::new(loaded.value + 1));
+                                let _ = atomic_arc_ref.compare_and_swap(&loaded, new, &guard);
+                            }
+                        }
+                        barrier_ref.wait();
+                    });
+                }
+            });
+            drop(atomic_arc);
+            assert_eq!(CREATED.load(Ordering::Relaxed), DROPPED.load(Ordering::Relaxed));
+        }
+    }
+
+    // declared HAZARDS_PER_OP is exactly the peak of the most demanding operation
+    #[test]
+    fn test_hazards_per_op() {
+        let hp_array = HazardPointerArray::new();
+        let guard = hp_array.register_thread().ok().unwrap();
+        let first = Arc::new(1);
+        let atomic_arc = AtomicArc::new(first.clone());
+        let hazards = AtomicArc::<i32>::HAZARDS_PER_OP;
+
+        guard.reset_peak_protected();
+        assert_eq!(*atomic_arc.load(&guard), 1);
+        assert_eq!(guard.peak_protected(), hazards);
+
+        guard.reset_peak_protected();
+        assert!(atomic_arc.compare_and_swap(&first, Arc::new(2), &guard).is_ok());
+        assert_eq!(guard.peak_protected(), hazards);
+        assert_eq!(guard.protected_count(), 0);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::collections::ms_queue::{MSQueue, QueueNode};
+use crate::mechanisms::hp::{HazardPointerArray, HazardPointerGuard};
+use crate::utils::backoff::Backoff;
+use std::cell::OnceCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+    MPMC channel over MSQueue, that hides hazard pointer registration from the user.
+    every channel owns its own HazardPointerArray:
+    - every sender and receiver registers a guard lazily on its first send/recv and keeps it
+    until dropped: registration scans the whole slot array, way too costly to do per call.
+    receivers retire dequeued nodes into their guards' d_lists, senders never retire anything
+    - a guard must not be used from two threads at once, so both are Send (given T: Send),
+    but not Sync: every thread takes its own clone
+    note, that hazard pointer array has a fixed amount of slots: with all of them taken,
+    new senders and receivers would spin until some other one is dropped
+*/
+
+struct Shared<T> {
+    queue: MSQueue<T>,
+    hp_array: HazardPointerArray,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn register_thread(&self) -> HazardPointerGuard<'_, QueueNode<T>> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.hp_array.register_thread() {
+                Ok(guard) => return guard,
+                Err(_) => backoff.spin_yield(),
+            }
+        }
+    }
+}
+
+// 'static is a lie: guard actually borrows shared.hp_array, which lives as long as Arc does.
+// both sender and receiver declare guard before shared, so that it's dropped while hp_array
+// is still alive
+type ChannelGuard<T> = HazardPointerGuard<'static, QueueNode<T>>;
+
+fn cached_guard<'a, T>(
+    guard: &'a OnceCell<ChannelGuard<T>>,
+    shared: &Arc<Shared<T>>,
+) -> &'a ChannelGuard<T> {
+    guard.get_or_init(|| {
+        let guard = shared.register_thread();
+        // safety: hp_array is heap-allocated inside Arc, that the owner of the cell keeps alive
+        // for at least as long as the guard (see field order)
+        unsafe {
+            std::mem::transmute::<HazardPointerGuard<'_, QueueNode<T>>, ChannelGuard<T>>(guard)
+        }
+    })
+}
+
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: MSQueue::new(),
+        hp_array: HazardPointerArray::new(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            guard: OnceCell::new(),
+            shared: shared.clone(),
+        },
+        Receiver {
+            guard: OnceCell::new(),
+            shared,
+        },
+    )
+}
+
+pub struct Sender<T> {
+    // see ChannelGuard
+    guard: OnceCell<ChannelGuard<T>>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    // fails only if all receivers are gone, returning value back
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(SendError(value));
+        }
+        let guard = cached_guard(&self.guard, &self.shared);
+        self.shared.queue.enqueue(value, guard);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    // every clone registers its own guard
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            guard: OnceCell::new(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // release pairs with receivers' acquire: everything we've sent is visible
+        // to a receiver that observes sender count drop to zero
+        self.shared.senders.fetch_sub(1, Ordering::Release);
+    }
+}
+
+pub struct Receiver<T> {
+    // see ChannelGuard
+    guard: OnceCell<ChannelGuard<T>>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let guard = cached_guard(&self.guard, &self.shared);
+        if let Some(value) = self.shared.queue.dequeue(guard) {
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            // last sender could have enqueued right before leaving, so recheck
+            return self
+                .shared
+                .queue
+                .dequeue(guard)
+                .ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    // spins (with yielding) until either value arrives or all senders are gone
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+                Err(TryRecvError::Empty) => backoff.spin_yield(),
+            }
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    // every clone registers its own guard
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            guard: OnceCell::new(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+// guard is owned by sender/receiver and is never shared (OnceCell keeps them !Sync), so moving
+// it together with its owner to another thread is fine. values, though, do cross threads
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+pub struct SendError<T>(pub T);
+
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+pub enum RecvError {
+    Disconnected,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, RecvError, TryRecvError};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_disconnect() {
+        let (sender, receiver) = channel();
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        let sender_clone = sender.clone();
+        assert!(sender.send(1).is_ok());
+        drop(sender);
+        assert!(sender_clone.send(2).is_ok());
+        drop(sender_clone);
+
+        // queue is drained first, and only then receiver reports disconnection
+        assert_eq!(receiver.recv().ok(), Some(1));
+        assert_eq!(receiver.try_recv().ok(), Some(2));
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Disconnected)));
+        assert!(matches!(receiver.recv(), Err(RecvError::Disconnected)));
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.send(3).err().map(|e| e.0), Some(3));
+    }
+
+    #[test]
+    fn test_mpmc() {
+        let producers = 2;
+        let consumers = 2;
+        let per_producer = 1000;
+        let expected_values: HashSet<usize> = (0..producers * per_producer).collect();
+
+        for _ in 0..100 {
+            let (sender, receiver) = channel();
+            let collected_values = std::sync::Mutex::new(Vec::new());
+            let values_ref = &collected_values;
+
+            std::thread::scope(|s| {
+                for p in 0..producers {
+                    let sender = sender.clone();
+                    s.spawn(move || {
+                        for i in 0..per_producer {
+                            assert!(sender.send(p * per_producer + i).is_ok());
+                        }
+                    });
+                }
+                // all producers have their own clones, so channel disconnects once they're done
+                drop(sender);
+
+                for _ in 0..consumers {
+                    let receiver = receiver.clone();
+                    s.spawn(move || {
+                        while let Ok(value) = receiver.recv() {
+                            values_ref.lock().unwrap().push(value);
+                        }
+                    });
+                }
+            });
+            drop(receiver);
+
+            let collected_values = collected_values.into_inner().unwrap();
+            assert_eq!(collected_values.len(), producers * per_producer);
+            let actual_values: HashSet<usize> = collected_values.into_iter().collect();
+            assert_eq!(actual_values, expected_values);
+        }
+    }
+}
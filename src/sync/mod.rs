@@ -0,0 +1,2 @@
+pub mod atomic_arc;
+pub mod channel;
@@ -1,15 +1,90 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// caps of adaptive() parameters: 2^ADAPTIVE_MAX_LEVEL initial iterations, with the threshold
+// ADAPTIVE_THRESHOLD_SPAN doublings above the initial value
+const ADAPTIVE_MAX_LEVEL: u32 = 6;
+const ADAPTIVE_THRESHOLD_SPAN: u32 = 4;
+// fixed-point scale of the retries average, and the weight of a new sample (1/2^EMA_SHIFT)
+const EMA_SCALE: u32 = 16;
+const EMA_SHIFT: u32 = 3;
+// single pathological operation shouldn't throw the average off for long
+const MAX_RECORDED_RETRIES: u32 = 1024;
+
 pub(crate) struct Backoff {
     initial: u32,
     threshold: u32,
     current: u32,
+    // spin() yields instead of spinning, once current has reached it, see with_params()
+    yield_at: Option<u32>,
+    // spin() and spin_yield() calls so far, unlike current, not cleared by reset()
+    retries: u32,
+}
+
+/*
+    recent contention of some atomic, shared by all operations on it (e.g. by a collection): an
+    exponential moving average of how many retries operations needed before succeeding. every
+    new sample weighs 1/8, so a burst of contention shows up within a few operations, and fades
+    away just as fast once it's over. see Backoff::adaptive()
+*/
+pub(crate) struct Contention {
+    // average retries, times EMA_SCALE
+    retries_ema: AtomicU32,
+}
+
+impl Contention {
+    pub(crate) const fn new() -> Self {
+        Self {
+            retries_ema: AtomicU32::new(0),
+        }
+    }
+
+    // called by an operation, once it has succeeded, with its backoff's retries()
+    pub(crate) fn record(&self, retries: u32) {
+        let sample = retries.min(MAX_RECORDED_RETRIES) * EMA_SCALE;
+        // only a hint: a lost update is as good as a skipped sample, so no CAS loop
+        let ema = self.retries_ema.load(Ordering::Relaxed);
+        let ema = ema - (ema >> EMA_SHIFT) + (sample >> EMA_SHIFT);
+        self.retries_ema.store(ema, Ordering::Relaxed);
+    }
+
+    // rounded to the nearest integer
+    pub(crate) fn average_retries(&self) -> u32 {
+        (self.retries_ema.load(Ordering::Relaxed) + EMA_SCALE / 2) / EMA_SCALE
+    }
 }
 
 impl Backoff {
     pub(crate) const fn new() -> Self {
-        Self::with_params(1, 7)
+        Self::with_params(1, 7, None)
+    }
+
+    /*
+        backoff tuned by how contended the atomic has recently been: when operations mostly
+        succeed right away, it starts at a single iteration and gives up doubling early, so that
+        a rare retry doesn't cost much; the more retries they've needed, the longer it spins
+        from the start (about as many iterations as retries on average), and the higher it
+        lets spinning grow, so that contenders spread out sooner, instead of colliding again.
+        operation should pass its retries() to contention.record() once it succeeds
+    */
+    pub(crate) fn adaptive(contention: &Contention) -> Self {
+        let average = contention.average_retries();
+        // bit length of the average: 0 for no retries, then 1, 2, 2, 3, 3, 3, 3, ...
+        let level = (u32::BITS - average.leading_zeros()).min(ADAPTIVE_MAX_LEVEL);
+        Self::with_params(1 << level, level + ADAPTIVE_THRESHOLD_SPAN, None)
     }
 
-    pub(crate) const fn with_params(initial: u32, threshold_exponent: u32) -> Self {
+    /*
+        spin() doubles busy-spinning up to 2^threshold_exponent iterations. with yield_exponent,
+        once it has reached 2^yield_exponent iterations, spin() yields the time slice instead of
+        spinning any further: on an oversubscribed system, whoever we wait for may well be
+        descheduled, and spinning just burns the slice, that it could have run in. None keeps
+        spinning for as long as it takes, e.g. for waits, that are known to be short
+    */
+    pub(crate) const fn with_params(
+        initial: u32,
+        threshold_exponent: u32,
+        yield_exponent: Option<u32>,
+    ) -> Self {
         assert!(initial > 0, "backoff: initial value must be positive number");
         assert!(threshold_exponent > 0, "backoff: threshold_exponent must be positive number");
         assert!(threshold_exponent < 32, "backoff: threshold_exponent must be less than 32 to avoid shift overflow");
@@ -17,14 +92,29 @@ impl Backoff {
         let threshold = 1 << threshold_exponent;
         assert!(initial < threshold, "backoff: initial value must be less than the calculated threshold");
 
+        let yield_at = match yield_exponent {
+            Some(yield_exponent) => {
+                assert!(yield_exponent <= threshold_exponent, "backoff: yield_exponent must not exceed threshold_exponent");
+                Some(1 << yield_exponent)
+            }
+            None => None,
+        };
+
         Self {
             initial,
             threshold,
             current: initial,
+            yield_at,
+            retries: 0,
         }
     }
 
     pub(crate) fn spin(&mut self) {
+        self.retries = self.retries.saturating_add(1);
+        if self.is_yielding() {
+            std::thread::yield_now();
+            return;
+        }
         for _ in 0..self.current {
             std::hint::spin_loop();
         }
@@ -34,6 +124,7 @@ impl Backoff {
     }
     
     pub(crate) fn spin_yield(&mut self) {
+        self.retries = self.retries.saturating_add(1);
         if self.current == self.threshold {
             self.reset();
             std::thread::yield_now();
@@ -49,4 +140,90 @@ impl Backoff {
         self.current = self.initial;
     }
 
+    // whether spin() has crossed over to yielding
+    pub(crate) fn is_yielding(&self) -> bool {
+        self.yield_at.is_some_and(|yield_at| self.current >= yield_at)
+    }
+
+    pub(crate) fn retries(&self) -> u32 {
+        self.retries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, Contention, ADAPTIVE_MAX_LEVEL, ADAPTIVE_THRESHOLD_SPAN};
+
+    #[test]
+    fn test_yield_crossover() {
+        // 1, 2, 4 iterations spun, then yields from the fourth spin() on
+        let mut backoff = Backoff::with_params(1, 5, Some(3));
+        let mut spins_before_yield = 0;
+        while !backoff.is_yielding() {
+            backoff.spin();
+            spins_before_yield += 1;
+        }
+        assert_eq!(spins_before_yield, 3);
+        for _ in 0..10 {
+            backoff.spin();
+            assert!(backoff.is_yielding());
+        }
+        backoff.reset();
+        assert!(!backoff.is_yielding());
+
+        // without yield_exponent, spin() never yields, however long it's been spinning
+        let mut backoff = Backoff::with_params(1, 3, None);
+        for _ in 0..10 {
+            backoff.spin();
+            assert!(!backoff.is_yielding());
+        }
+    }
+
+    #[test]
+    fn test_adaptive() {
+        let contention = Contention::new();
+        let uncontended = Backoff::adaptive(&contention);
+        assert_eq!((uncontended.initial, uncontended.threshold), (1, 1 << ADAPTIVE_THRESHOLD_SPAN));
+
+        // operations start needing retries: backoff starts higher and may grow further
+        let mut previous = uncontended;
+        for retries in [2, 8, 32] {
+            for _ in 0..64 {
+                contention.record(retries);
+            }
+            assert_eq!(contention.average_retries(), retries);
+            let backoff = Backoff::adaptive(&contention);
+            assert!(backoff.initial > previous.initial);
+            assert!(backoff.threshold > previous.threshold);
+            previous = backoff;
+        }
+
+        // parameters are capped, however bad it gets
+        for _ in 0..64 {
+            contention.record(u32::MAX);
+        }
+        let backoff = Backoff::adaptive(&contention);
+        assert_eq!(backoff.initial, 1 << ADAPTIVE_MAX_LEVEL);
+        assert_eq!(backoff.threshold, 1 << (ADAPTIVE_MAX_LEVEL + ADAPTIVE_THRESHOLD_SPAN));
+
+        // contention is over: first-time successes bring it back down within a few dozen
+        for _ in 0..64 {
+            contention.record(0);
+        }
+        assert_eq!(contention.average_retries(), 0);
+        let backoff = Backoff::adaptive(&contention);
+        assert_eq!((backoff.initial, backoff.threshold), (1, 1 << ADAPTIVE_THRESHOLD_SPAN));
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut backoff = Backoff::with_params(1, 2, None);
+        for _ in 0..3 {
+            backoff.spin();
+        }
+        backoff.reset();
+        backoff.spin_yield();
+        // reset() starts spinning over, but doesn't forget the retries
+        assert_eq!(backoff.retries(), 4);
+    }
 }
@@ -0,0 +1,17 @@
+use std::ops::Deref;
+
+/*
+    aligns (and so pads) value to a cache line, so that neighbouring values in an array don't
+    share a line: writes to one of them don't invalidate the others in other cores' caches.
+    64 bytes is the line size on x86_64 and most of aarch64; on cpus with 128-byte lines
+    (e.g. apple silicon) adjacent-line prefetching still makes pairs of values interfere
+*/
+#[repr(align(64))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,150 @@
+/*
+    deterministic interleaving for tests. collections call yield_point() between their atomic
+    steps; normally it's a no-op, but on a thread driven by replay() it hands control over to the
+    scheduler, which lets exactly one thread run at a time and picks the next one with a seeded
+    rng. so given the same seed (and the same code between yield points), replay() drives the
+    very same interleaving every time: a failing seed pins the bug down as a regression test.
+    note, that determinism holds only as long as the code between yield points is deterministic
+    itself: e.g. TreiberStack's elimination (random slots, time-bounded waits) is not, and weak
+    CAS may fail spuriously on LL/SC platforms
+*/
+
+#[cfg(not(test))]
+#[inline(always)]
+pub(crate) fn yield_point() {}
+
+#[cfg(test)]
+pub(crate) use scheduler::{replay, yield_point};
+
+#[cfg(test)]
+mod scheduler {
+    use std::cell::RefCell;
+    use std::sync::{Arc, Condvar, Mutex};
+
+    thread_local! {
+        // scheduler driving current thread along with thread's id in it
+        static CURRENT: RefCell<Option<(Arc<Scheduler>, usize)>> = const { RefCell::new(None) };
+    }
+
+    struct Scheduler {
+        state: Mutex<State>,
+        switched: Condvar,
+    }
+
+    struct State {
+        running: Option<usize>,
+        finished: Vec<bool>,
+        rng: u64,
+        // ids of threads picked at every scheduling point
+        trace: Vec<usize>,
+    }
+
+    impl State {
+        // xorshift: no dependency on rand's algorithms staying the same across versions
+        fn next_random(&mut self) -> u64 {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            self.rng
+        }
+
+        fn pick_next(&mut self) {
+            let unfinished = (0..self.finished.len())
+                .filter(|&id| !self.finished[id])
+                .collect::<Vec<_>>();
+            if unfinished.is_empty() {
+                self.running = None;
+                return;
+            }
+            let next = unfinished[(self.next_random() % unfinished.len() as u64) as usize];
+            self.trace.push(next);
+            self.running = Some(next);
+        }
+    }
+
+    impl Scheduler {
+        fn wait_for_turn(&self, id: usize) {
+            let mut state = self.state.lock().unwrap();
+            while state.running != Some(id) {
+                state = self.switched.wait(state).unwrap();
+            }
+        }
+
+        fn switch(&self, id: usize, finished: bool) {
+            let mut state = self.state.lock().unwrap();
+            state.finished[id] |= finished;
+            state.pick_next();
+            self.switched.notify_all();
+            drop(state);
+            if !finished {
+                self.wait_for_turn(id);
+            }
+        }
+    }
+
+    // lets the scheduler pick which thread runs next, possibly the current one
+    pub(crate) fn yield_point() {
+        let current = CURRENT.with(|current| current.borrow().clone());
+        if let Some((scheduler, id)) = current {
+            scheduler.switch(id, false);
+        }
+    }
+
+    // marks thread as finished even if it panics, so that others don't wait for it forever
+    struct FinishOnDrop(Arc<Scheduler>, usize);
+
+    impl Drop for FinishOnDrop {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.borrow_mut().take());
+            self.0.switch(self.1, true);
+        }
+    }
+
+    /*
+        runs every closure on its own thread, one at a time, switching between them at yield
+        points as dictated by seed. returns the trace of scheduling decisions, which is the same
+        for the same seed. panics of any closure are propagated once all of them are done
+    */
+    pub(crate) fn replay<'a>(seed: u64, threads: Vec<Box<dyn FnOnce() + Send + 'a>>) -> Vec<usize> {
+        let scheduler = Arc::new(Scheduler {
+            state: Mutex::new(State {
+                running: None,
+                finished: vec![false; threads.len()],
+                // xorshift gets stuck at zero
+                rng: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+                trace: Vec::new(),
+            }),
+            switched: Condvar::new(),
+        });
+
+        std::thread::scope(|s| {
+            let handles = threads
+                .into_iter()
+                .enumerate()
+                .map(|(id, thread)| {
+                    let scheduler = scheduler.clone();
+                    s.spawn(move || {
+                        scheduler.wait_for_turn(id);
+                        CURRENT.with(|current| *current.borrow_mut() = Some((scheduler.clone(), id)));
+                        let _finish = FinishOnDrop(scheduler, id);
+                        thread();
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            {
+                let mut state = scheduler.state.lock().unwrap();
+                state.pick_next();
+                scheduler.switched.notify_all();
+            }
+            for handle in handles {
+                if let Err(panic) = handle.join() {
+                    std::panic::resume_unwind(panic);
+                }
+            }
+        });
+
+        let state = scheduler.state.lock().unwrap();
+        state.trace.clone()
+    }
+}
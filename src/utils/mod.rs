@@ -1 +1,7 @@
-pub(crate) mod backoff;
\ No newline at end of file
+pub(crate) mod backoff;
+pub(crate) mod cache_padded;
+pub(crate) mod interleave;
+pub(crate) mod spin_wait;
+pub mod stamped_ptr;
+
+pub use stamped_ptr::{Stamped, StampedPtr};
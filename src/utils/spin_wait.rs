@@ -0,0 +1,152 @@
+use crate::utils::backoff::Backoff;
+use std::time::{Duration, Instant};
+
+/*
+    'spin with backoff until condition holds' in one place. by default waits for as long as it
+    takes, yielding once backoff saturates (like Backoff::spin_yield()). optional limits:
+    - timeout: gives up once that much time has passed since the wait has started;
+    - max spins: gives up after that many unsuccessful checks;
+    - sleep escalation: after that much time, sleeps between checks instead of spinning, for
+      waits that may take long (e.g. for a preempted thread), so that waiter doesn't burn cpu.
+    clock is only read if timeout or sleep escalation is set.
+    e.g. SpinWait::new().spinning().with_timeout(window).wait_until(|| ...)
+*/
+
+// see SpinWait::until()
+const SLEEP_AFTER: Duration = Duration::from_millis(1);
+const SLEEP: Duration = Duration::from_micros(50);
+
+pub(crate) struct SpinWait {
+    backoff: Backoff,
+    yielding: bool,
+    timeout: Option<Duration>,
+    max_spins: Option<usize>,
+    // (wait duration to start sleeping after, sleep duration)
+    sleep_after: Option<(Duration, Duration)>,
+}
+
+impl SpinWait {
+    pub(crate) const fn new() -> Self {
+        SpinWait {
+            backoff: Backoff::new(),
+            yielding: true,
+            timeout: None,
+            max_spins: None,
+            sleep_after: None,
+        }
+    }
+
+    // unbounded wait, e.g. for a grace period: spins, then yields, and sleeps, if it takes
+    // longer than SLEEP_AFTER (say, waiting for a preempted thread). always returns true
+    pub(crate) fn until(predicate: impl FnMut() -> bool) -> bool {
+        Self::new().sleep_after(SLEEP_AFTER, SLEEP).wait_until(predicate)
+    }
+
+    pub(crate) const fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // never yields, for short waits, e.g. elimination rendezvous
+    pub(crate) const fn spinning(mut self) -> Self {
+        self.yielding = false;
+        self
+    }
+
+    pub(crate) const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) const fn with_max_spins(mut self, max_spins: usize) -> Self {
+        self.max_spins = Some(max_spins);
+        self
+    }
+
+    pub(crate) const fn sleep_after(mut self, after: Duration, sleep: Duration) -> Self {
+        self.sleep_after = Some((after, sleep));
+        self
+    }
+
+    // true once predicate holds, false if a limit is hit first. predicate is checked at least once
+    pub(crate) fn wait_until(mut self, mut predicate: impl FnMut() -> bool) -> bool {
+        let start = (self.timeout.is_some() || self.sleep_after.is_some()).then(Instant::now);
+        let mut spins = 0;
+        loop {
+            if predicate() {
+                return true;
+            }
+            spins += 1;
+            if self.max_spins.is_some_and(|max_spins| spins >= max_spins) {
+                return false;
+            }
+            let elapsed = start.map(|start| start.elapsed());
+            if elapsed.zip(self.timeout).is_some_and(|(elapsed, timeout)| elapsed >= timeout) {
+                return false;
+            }
+            match (self.sleep_after, elapsed) {
+                (Some((after, sleep)), Some(elapsed)) if elapsed >= after => {
+                    std::thread::sleep(sleep);
+                }
+                _ if self.yielding => self.backoff.spin_yield(),
+                _ => self.backoff.spin(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpinWait;
+    use crate::utils::backoff::Backoff;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_immediate_success() {
+        let mut checks = 0;
+        assert!(SpinWait::until(|| {
+            checks += 1;
+            true
+        }));
+        assert_eq!(checks, 1);
+        // limits don't matter if predicate already holds
+        assert!(SpinWait::new().with_max_spins(1).with_timeout(Duration::ZERO).wait_until(|| true));
+    }
+
+    #[test]
+    fn test_eventual_success() {
+        let mut checks = 0;
+        assert!(SpinWait::new().spinning().wait_until(|| {
+            checks += 1;
+            checks == 100
+        }));
+        assert_eq!(checks, 100);
+
+        // condition set by another thread, long enough to escalate to sleeping
+        let flag = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                flag.store(true, Ordering::Release);
+            });
+            assert!(SpinWait::until(|| flag.load(Ordering::Acquire)));
+        });
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = Duration::from_millis(10);
+        let start = Instant::now();
+        assert!(!SpinWait::new().with_timeout(timeout).wait_until(|| false));
+        assert!(start.elapsed() >= timeout);
+
+        let mut checks = 0;
+        let spin_wait = SpinWait::new().spinning().with_backoff(Backoff::with_params(1, 1, None));
+        assert!(!spin_wait.with_max_spins(8).wait_until(|| {
+            checks += 1;
+            false
+        }));
+        assert_eq!(checks, 8);
+    }
+}
@@ -0,0 +1,173 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/*
+    atomic pointer with a version stamp, that every successful compare_exchange bumps: a CAS,
+    that expects (A, stamp) fails, if the pointer has gone A -> B -> A since that load, even if
+    A is a new node, that happens to get the old address after the old one was freed.
+    a double-width (pointer, u64) CAS would need AtomicU128, which isn't stable, so the stamp
+    is packed into the upper STAMP_BITS of a single 64-bit word instead. that relies on user
+    space addresses fitting into the lower 48 bits, which holds for x86_64 and aarch64 with
+    4-level paging (the default; 5-level paging is only enabled for processes, that ask for it).
+    stamp wraps around after 2^16 updates, so an ABA still goes unnoticed, if a thread is
+    stalled between its load and CAS for exactly a multiple of that many updates
+*/
+
+const STAMP_BITS: u32 = 16;
+const PTR_BITS: u32 = u64::BITS - STAMP_BITS;
+const PTR_MASK: u64 = (1 << PTR_BITS) - 1;
+
+pub struct StampedPtr<T> {
+    packed: AtomicU64,
+    _marker: PhantomData<*mut T>,
+}
+
+unsafe impl<T> Send for StampedPtr<T> {}
+unsafe impl<T> Sync for StampedPtr<T> {}
+
+// (pointer, stamp) as seen by load(), to be passed back to compare_exchange()
+pub struct Stamped<T> {
+    pub ptr: *mut T,
+    pub stamp: u16,
+}
+
+impl<T> Clone for Stamped<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Stamped<T> {}
+
+impl<T> PartialEq for Stamped<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr && self.stamp == other.stamp
+    }
+}
+
+impl<T> Stamped<T> {
+    fn pack(self) -> u64 {
+        let addr = self.ptr as usize as u64;
+        debug_assert_eq!(
+            addr & !PTR_MASK,
+            0,
+            "StampedPtr: address doesn't fit into {} bits",
+            PTR_BITS
+        );
+        ((self.stamp as u64) << PTR_BITS) | addr
+    }
+
+    fn unpack(packed: u64) -> Self {
+        Stamped {
+            ptr: (packed & PTR_MASK) as usize as *mut T,
+            stamp: (packed >> PTR_BITS) as u16,
+        }
+    }
+}
+
+impl<T> StampedPtr<T> {
+    pub fn new(ptr: *mut T) -> Self {
+        const {
+            assert!(
+                size_of::<*mut T>() == size_of::<u64>(),
+                "StampedPtr: only 64-bit targets are supported"
+            )
+        };
+        StampedPtr {
+            packed: AtomicU64::new(Stamped { ptr, stamp: 0 }.pack()),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn load(&self, ordering: Ordering) -> Stamped<T> {
+        Stamped::unpack(self.packed.load(ordering))
+    }
+
+    // on success, new pointer is stored with current's stamp plus one (wrapping), and that
+    // is what's returned; on failure, the actual value is
+    pub fn compare_exchange(
+        &self,
+        current: Stamped<T>,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Stamped<T>, Stamped<T>> {
+        let new = Stamped {
+            ptr: new,
+            stamp: current.stamp.wrapping_add(1),
+        };
+        self.packed
+            .compare_exchange(current.pack(), new.pack(), success, failure)
+            .map(|_| new)
+            .map_err(Stamped::unpack)
+    }
+
+    pub fn compare_exchange_weak(
+        &self,
+        current: Stamped<T>,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Stamped<T>, Stamped<T>> {
+        let new = Stamped {
+            ptr: new,
+            stamp: current.stamp.wrapping_add(1),
+        };
+        self.packed
+            .compare_exchange_weak(current.pack(), new.pack(), success, failure)
+            .map(|_| new)
+            .map_err(Stamped::unpack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stamped, StampedPtr};
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    #[test]
+    fn test_aba_detected() {
+        let (a, b) = (Box::into_raw(Box::new(1)), Box::into_raw(Box::new(2)));
+        let stamped = StampedPtr::new(a);
+        let plain = AtomicPtr::new(a);
+        let seen = stamped.load(Ordering::Acquire);
+        assert!(seen == Stamped { ptr: a, stamp: 0 });
+
+        // meanwhile, A is replaced with B, and then A (or a new node, that has got A's address
+        // after A was freed) is back on top
+        let replaced = stamped
+            .compare_exchange(seen, b, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .unwrap();
+        assert!(replaced == Stamped { ptr: b, stamp: 1 });
+        assert!(stamped
+            .compare_exchange(replaced, a, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok());
+        plain.store(b, Ordering::Release);
+        plain.store(a, Ordering::Release);
+
+        // plain pointer can't tell, stamped one can
+        assert!(plain
+            .compare_exchange(a, b, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok());
+        match stamped.compare_exchange(seen, b, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => panic!("StampedPtr: CAS succeeded after A -> B -> A"),
+            Err(actual) => assert!(actual == Stamped { ptr: a, stamp: 2 }),
+        }
+        drop(unsafe { Box::from_raw(a) });
+        drop(unsafe { Box::from_raw(b) });
+    }
+
+    #[test]
+    fn test_stamp_wraps_around() {
+        let stamped = StampedPtr::<usize>::new(std::ptr::null_mut());
+        let mut current = stamped.load(Ordering::Relaxed);
+        for _ in 0..=u16::MAX {
+            current = stamped
+                .compare_exchange(current, current.ptr, Ordering::Relaxed, Ordering::Relaxed)
+                .ok()
+                .unwrap();
+        }
+        assert!(current == Stamped { ptr: std::ptr::null_mut(), stamp: 0 });
+    }
+}